@@ -0,0 +1,118 @@
+//! A printer for async code on tokio
+//!
+//! Writing to a printer blocks until the printer has taken the data, which
+//! can take seconds. [`AsyncPrinter`] does all of that on tokio's blocking
+//! threads, one job at a time:
+//!
+//! ```
+//! use thermal::{asynchronous::AsyncPrinter, MockDevice, Printer};
+//!
+//! # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+//! let printer = AsyncPrinter::new(Printer::new(MockDevice::new()));
+//!
+//! let receipt = Printer::document();
+//! receipt.println("Hello").unwrap();
+//! printer.submit(receipt.into_device()).await.unwrap();
+//!
+//! printer.run(|printer| printer.println("World")).await.unwrap();
+//! # });
+//! ```
+//!
+//! Jobs are cancellation safe: once submitted, a job runs to the end even if
+//! the future waiting for it is dropped, so the printer is never left with
+//! half a command.
+
+use std::{
+    panic,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::{
+    error::PrinterError, hotplug::ReconnectingPrinter, queue::JobPrinter, status::PrinterStatus,
+    Device, Document, Printer, UsbDevice,
+};
+
+/// A printer shared between tasks, running its jobs off the async runtime
+///
+/// Wraps a [`Printer`], or a [`ReconnectingPrinter`] to keep going after the
+/// printer is unplugged.
+pub struct AsyncPrinter<P = Printer<UsbDevice>> {
+    printer: Arc<Mutex<P>>,
+}
+
+impl<P> Clone for AsyncPrinter<P> {
+    fn clone(&self) -> Self {
+        Self {
+            printer: self.printer.clone(),
+        }
+    }
+}
+
+impl<P: Send + 'static> AsyncPrinter<P> {
+    pub fn new(printer: P) -> Self {
+        Self {
+            printer: Arc::new(Mutex::new(printer)),
+        }
+    }
+
+    /// Run a job with the printer on a blocking thread
+    ///
+    /// Jobs run one after another, in the order they get hold of the
+    /// printer. Fails with [`PrinterError::Cancelled`] if the runtime shuts
+    /// down before the job could start.
+    pub async fn run<T, E, F>(&self, job: F) -> Result<T, E>
+    where
+        F: FnOnce(&mut P) -> Result<T, E> + Send + 'static,
+        T: Send + 'static,
+        E: From<PrinterError> + Send + 'static,
+    {
+        let printer = self.printer.clone();
+
+        let result = tokio::task::spawn_blocking(move || job(&mut lock(&printer))).await;
+
+        match result {
+            Ok(result) => result,
+            Err(err) if err.is_panic() => panic::resume_unwind(err.into_panic()),
+            Err(_) => Err(PrinterError::Cancelled.into()),
+        }
+    }
+}
+
+impl<P: JobPrinter> AsyncPrinter<P> {
+    /// Send a document as a single job
+    pub async fn submit(&self, document: Document) -> Result<(), PrinterError> {
+        self.run(move |printer| printer.print_job(&document)).await
+    }
+}
+
+/// Lets a [`JobQueue`](crate::queue::JobQueue) worker print on the printer
+/// too, taking turns with the other jobs
+impl<P: JobPrinter> JobPrinter for AsyncPrinter<P> {
+    fn print_job(&mut self, job: &Document) -> Result<(), PrinterError> {
+        lock(&self.printer).print_job(job)
+    }
+
+    fn is_recoverable(&mut self, err: &PrinterError) -> bool {
+        lock(&self.printer).is_recoverable(err)
+    }
+}
+
+impl<D: Device + Send + 'static> AsyncPrinter<Printer<D>> {
+    /// Transmit printer status
+    pub async fn status(&self) -> Result<PrinterStatus, PrinterError> {
+        self.run(|printer| printer.status()).await
+    }
+}
+
+impl AsyncPrinter<ReconnectingPrinter> {
+    /// Transmit printer status, once the printer is connected
+    pub async fn status(&self) -> Result<PrinterStatus, PrinterError> {
+        self.run(|printer| printer.run(|printer| printer.status()))
+            .await
+    }
+}
+
+fn lock<P>(printer: &Mutex<P>) -> MutexGuard<'_, P> {
+    // A job that panicked left the printer as usable as any failed job
+    printer.lock().unwrap_or_else(|err| err.into_inner())
+}
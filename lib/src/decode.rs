@@ -0,0 +1,657 @@
+//! Decoding of raw ESC/POS byte streams back into commands
+
+use crate::{Font, Justification};
+
+const HT: u8 = 0x09;
+const LF: u8 = 0x0A;
+const FF: u8 = 0x0C;
+const CR: u8 = 0x0D;
+const CAN: u8 = 0x18;
+const DLE: u8 = 0x10;
+const DC2: u8 = 0x12;
+const ESC: u8 = 0x1B;
+const FS: u8 = 0x1C;
+const GS: u8 = 0x1D;
+
+/// A single decoded ESC/POS command
+///
+/// Numeric parameters are kept as they were sent, so `Underline(49)` and
+/// `Underline(1)` are both possible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command<'a> {
+    /// A run of printable characters (in the active code page)
+    Text(&'a [u8]),
+    /// LF
+    LineFeed,
+    /// CR
+    CarriageReturn,
+    /// HT
+    HorizontalTab,
+    /// ESC D **n1** ... **nk** NUL, in characters
+    TabStops(&'a [u8]),
+    /// FF
+    FormFeed,
+    /// ESC & **y** **c1** **c2** [**x** **d1...d(y × x)**]...
+    ///
+    /// `data` holds the definitions, each starting with its width **x**
+    DefineCharacters {
+        rows: u8,
+        first: u8,
+        last: u8,
+        data: &'a [u8],
+    },
+    /// ESC % **n**
+    UserDefinedCharacters(bool),
+    /// ESC ? **n**
+    CancelCharacter(u8),
+    /// ESC @
+    Initialize,
+    /// ESC a **n**
+    Justify(Justification),
+    /// ESC E **n**
+    Emphasized(bool),
+    /// ESC G **n**
+    DoubleStrike(bool),
+    /// ESC - **n**
+    Underline(u8),
+    /// ESC M **n**
+    Font(Font),
+    /// ESC ! **n**
+    PrintMode(u8),
+    /// ESC { **n**
+    UpsideDown(bool),
+    /// GS B **n**
+    Reverse(bool),
+    /// GS ! **n**, as width and height multipliers (1-8)
+    CharacterSize { width: u8, height: u8 },
+    /// ESC 2 (`None`) or ESC 3 **n**
+    LineSpacing(Option<u8>),
+    /// ESC d **n**
+    FeedLines(u8),
+    /// ESC J **n**, in vertical motion units
+    FeedUnits(u8),
+    /// ESC K **n**, in vertical motion units
+    ReverseFeed(u8),
+    /// GS P **x** **y**, in units per inch (0 for the printer default)
+    MotionUnits { horizontal: u8, vertical: u8 },
+    /// GS V **m** [**n**]
+    Cut { mode: u8, feed: Option<u8> },
+    /// GS L **nL** **nH**, in horizontal motion units
+    LeftMargin(u16),
+    /// GS W **nL** **nH**, in horizontal motion units
+    PrintWidth(u16),
+    /// ESC $ **nL** **nH**, in horizontal motion units
+    AbsolutePosition(u16),
+    /// ESC \ **nL** **nH**, in horizontal motion units
+    RelativePosition(i16),
+    /// ESC SP **n**, in horizontal motion units
+    CharacterSpacing(u8),
+    /// GS T **n**
+    CarriageReset { cancel_print_buffer: bool },
+    /// ESC c 0 **n**
+    PaperType(u8),
+    /// ESC S
+    StandardMode,
+    /// ESC L
+    PageMode,
+    /// FS L
+    DoubleDensityPageMode,
+    /// ESC W **xL** **xH** **yL** **yH** **dxL** **dxH** **dyL** **dyH**, in motion units
+    PageArea {
+        x: u16,
+        y: u16,
+        width: u16,
+        height: u16,
+    },
+    /// ESC T **n**
+    PrintDirection(u8),
+    /// GS $ **nL** **nH**, in vertical motion units
+    AbsoluteVerticalPosition(u16),
+    /// GS \ **nL** **nH**, in vertical motion units
+    RelativeVerticalPosition(i16),
+    /// ESC FF
+    PrintPage,
+    /// CAN
+    CancelPage,
+    /// GS :
+    MacroDefinition,
+    /// GS ^ **r** **t** **m**
+    ExecuteMacro { times: u8, wait: u8, mode: u8 },
+    /// GS H **n**
+    HriPosition(u8),
+    /// GS f **n**
+    HriFont(u8),
+    /// GS h **n**
+    BarcodeHeight(u8),
+    /// GS w **n**
+    BarcodeWidth(u8),
+    /// GS k **m** ...
+    Barcode { system: u8, data: &'a [u8] },
+    /// GS v 0 **m** **xL** **xH** **yL** **yH** **d1...dk**
+    RasterImage {
+        mode: u8,
+        width_bytes: u16,
+        height: u16,
+        data: &'a [u8],
+    },
+    /// ESC * **m** **nL** **nH** **d1...dk**
+    BitImage {
+        mode: u8,
+        width: u16,
+        data: &'a [u8],
+    },
+    /// A length-prefixed extended command such as GS ( k (QR codes) or
+    /// GS ( E (user settings)
+    ///
+    /// `data` starts at the byte after **pH**, which is usually **fn**
+    Extended {
+        prefix: u8,
+        class: u8,
+        data: &'a [u8],
+    },
+    /// A command that was recognized well enough to skip, but is not decoded
+    Unknown(&'a [u8]),
+    /// The stream ended in the middle of a command
+    Incomplete(&'a [u8]),
+}
+
+/// An iterator over the commands in a byte stream
+#[derive(Debug, Clone)]
+pub struct Decoder<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+/// Decode a raw ESC/POS byte stream
+pub fn decode(data: &[u8]) -> Decoder<'_> {
+    Decoder::new(data)
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    /// Offset of the next command in the stream
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+impl<'a> Iterator for Decoder<'a> {
+    type Item = Command<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.data[self.position..];
+        let &first = rest.first()?;
+
+        let (command, len) = match first {
+            HT => (Command::HorizontalTab, 1),
+            LF => (Command::LineFeed, 1),
+            FF => (Command::FormFeed, 1),
+            CR => (Command::CarriageReturn, 1),
+            CAN => (Command::CancelPage, 1),
+            ESC => esc(rest).unwrap_or((Command::Incomplete(rest), rest.len())),
+            GS => gs(rest).unwrap_or((Command::Incomplete(rest), rest.len())),
+            FS => fs(rest).unwrap_or((Command::Incomplete(rest), rest.len())),
+            DLE => dle(rest).unwrap_or((Command::Incomplete(rest), rest.len())),
+            DC2 => dc2(rest).unwrap_or((Command::Incomplete(rest), rest.len())),
+            0x00..=0x1F => (Command::Unknown(&rest[..1]), 1),
+            _ => {
+                let len = rest.iter().take_while(|&&b| b >= 0x20).count();
+
+                (Command::Text(&rest[..len]), len)
+            }
+        };
+
+        self.position += len;
+
+        Some(command)
+    }
+}
+
+/// Strip the ASCII offset some commands allow on their parameters ('0' == 0)
+pub(crate) fn digit(n: u8) -> u8 {
+    if n >= b'0' {
+        n - b'0'
+    } else {
+        n
+    }
+}
+
+fn justification(n: u8) -> Justification {
+    match digit(n) {
+        1 => Justification::Center,
+        2 => Justification::Right,
+        _ => Justification::Left,
+    }
+}
+
+fn u16_at(data: &[u8], index: usize) -> Option<u16> {
+    Some(*data.get(index)? as u16 | (*data.get(index + 1)? as u16) << 8)
+}
+
+fn slice(data: &[u8], len: usize) -> Option<(&[u8], usize)> {
+    if data.len() >= len {
+        Some((&data[..len], len))
+    } else {
+        None
+    }
+}
+
+/// A command with a known amount of trailing parameter bytes
+fn fixed(data: &[u8], params: usize) -> Option<(Command<'_>, usize)> {
+    slice(data, 2 + params).map(|(bytes, len)| (Command::Unknown(bytes), len))
+}
+
+/// XX ( **c** **pL** **pH** **d1...dk**
+fn extended(data: &[u8]) -> Option<(Command<'_>, usize)> {
+    let class = *data.get(2)?;
+    let len = u16_at(data, 3)? as usize;
+    let (bytes, len) = slice(data, 5 + len)?;
+
+    Some((
+        Command::Extended {
+            prefix: data[0],
+            class,
+            data: &bytes[5..],
+        },
+        len,
+    ))
+}
+
+fn esc(data: &[u8]) -> Option<(Command<'_>, usize)> {
+    let param = |i: usize| data.get(2 + i).copied();
+
+    Some(match *data.get(1)? {
+        b'@' => (Command::Initialize, 2),
+        b'a' => (Command::Justify(justification(param(0)?)), 3),
+        b'E' => (Command::Emphasized(param(0)? & 1 == 1), 3),
+        b'G' => (Command::DoubleStrike(param(0)? & 1 == 1), 3),
+        b'-' => (Command::Underline(param(0)?), 3),
+        b'M' => (
+            Command::Font(match digit(param(0)?) {
+                1 => Font::B,
+                _ => Font::A,
+            }),
+            3,
+        ),
+        b'!' => (Command::PrintMode(param(0)?), 3),
+        b'{' => (Command::UpsideDown(param(0)? & 1 == 1), 3),
+        b'2' => (Command::LineSpacing(None), 2),
+        b'3' => (Command::LineSpacing(Some(param(0)?)), 3),
+        b'd' => (Command::FeedLines(param(0)?), 3),
+        b'J' => (Command::FeedUnits(param(0)?), 3),
+        b'K' => (Command::ReverseFeed(param(0)?), 3),
+        b'L' => (Command::PageMode, 2),
+        b'W' => (
+            Command::PageArea {
+                x: u16_at(data, 2)?,
+                y: u16_at(data, 4)?,
+                width: u16_at(data, 6)?,
+                height: u16_at(data, 8)?,
+            },
+            10,
+        ),
+        b'T' => (Command::PrintDirection(param(0)?), 3),
+        FF => (Command::PrintPage, 2),
+        b'S' => (Command::StandardMode, 2),
+        b'$' => (Command::AbsolutePosition(u16_at(data, 2)?), 4),
+        b'\\' => (Command::RelativePosition(u16_at(data, 2)? as i16), 4),
+        b' ' => (Command::CharacterSpacing(param(0)?), 3),
+        b'c' if param(0)? == b'0' => (Command::PaperType(param(1)?), 4),
+        b'c' => return fixed(data, 2),
+        b'*' => {
+            let mode = param(0)?;
+            let width = u16_at(data, 3)?;
+            let len = width as usize * if mode >= 32 { 3 } else { 1 };
+            let (bytes, len) = slice(data, 5 + len)?;
+
+            (
+                Command::BitImage {
+                    mode,
+                    width,
+                    data: &bytes[5..],
+                },
+                len,
+            )
+        }
+        b'(' => return extended(data),
+        b'D' => {
+            let len = data[2..].iter().position(|&b| b == 0x00)?;
+
+            (Command::TabStops(&data[2..2 + len]), 3 + len)
+        }
+        b'&' => {
+            // ESC & y c1 c2 [x d1...d(y * x)]...
+            let height = param(0)? as usize;
+            let (first, last) = (param(1)?, param(2)?);
+            let mut len = 5;
+
+            for _ in first..=last {
+                let width = *data.get(len)? as usize;
+                len += 1 + width * height;
+            }
+
+            let (bytes, len) = slice(data, len)?;
+
+            (
+                Command::DefineCharacters {
+                    rows: height as u8,
+                    first,
+                    last,
+                    data: &bytes[5..],
+                },
+                len,
+            )
+        }
+        b'%' => (Command::UserDefinedCharacters(param(0)? & 1 == 1), 3),
+        b'?' => (Command::CancelCharacter(param(0)?), 3),
+        b'<' | b'i' | b'm' | b'q' => return fixed(data, 0),
+        b'f' | b'B' => return fixed(data, 2),
+        b'p' => return fixed(data, 3),
+        _ => return fixed(data, 1),
+    })
+}
+
+fn gs(data: &[u8]) -> Option<(Command<'_>, usize)> {
+    let param = |i: usize| data.get(2 + i).copied();
+
+    Some(match *data.get(1)? {
+        b'!' => {
+            let n = param(0)?;
+
+            (
+                Command::CharacterSize {
+                    width: (n >> 4 & 0x07) + 1,
+                    height: (n & 0x07) + 1,
+                },
+                3,
+            )
+        }
+        b'B' => (Command::Reverse(param(0)? & 1 == 1), 3),
+        b'V' => match param(0)? {
+            mode @ (65 | 66 | 97 | 98 | 103 | 104) => (
+                Command::Cut {
+                    mode,
+                    feed: Some(param(1)?),
+                },
+                4,
+            ),
+            mode => (Command::Cut { mode, feed: None }, 3),
+        },
+        b'T' => (
+            Command::CarriageReset {
+                cancel_print_buffer: digit(param(0)?) == 0,
+            },
+            3,
+        ),
+        b'H' => (Command::HriPosition(param(0)?), 3),
+        b'f' => (Command::HriFont(param(0)?), 3),
+        b'h' => (Command::BarcodeHeight(param(0)?), 3),
+        b'w' => (Command::BarcodeWidth(param(0)?), 3),
+        b'k' => {
+            let system = param(0)?;
+
+            if system <= 6 {
+                // Format A: NUL terminated
+                let len = data[3..].iter().position(|&b| b == 0x00)?;
+
+                (
+                    Command::Barcode {
+                        system,
+                        data: &data[3..3 + len],
+                    },
+                    4 + len,
+                )
+            } else {
+                // Format B: length prefixed
+                let len = param(1)? as usize;
+                let (bytes, len) = slice(data, 4 + len)?;
+
+                (
+                    Command::Barcode {
+                        system,
+                        data: &bytes[4..],
+                    },
+                    len,
+                )
+            }
+        }
+        b'v' => {
+            let mode = param(1)?;
+            let width_bytes = u16_at(data, 4)?;
+            let height = u16_at(data, 6)?;
+            let (bytes, len) = slice(data, 8 + width_bytes as usize * height as usize)?;
+
+            (
+                Command::RasterImage {
+                    mode,
+                    width_bytes,
+                    height,
+                    data: &bytes[8..],
+                },
+                len,
+            )
+        }
+        b'L' => (Command::LeftMargin(u16_at(data, 2)?), 4),
+        b'W' => (Command::PrintWidth(u16_at(data, 2)?), 4),
+        b'P' => (
+            Command::MotionUnits {
+                horizontal: param(0)?,
+                vertical: param(1)?,
+            },
+            4,
+        ),
+        b'(' => return extended(data),
+        b'8' => {
+            // GS 8 L p1 p2 p3 p4 m fn [params]
+            let len = u32::from_le_bytes([param(1)?, param(2)?, param(3)?, param(4)?]) as usize;
+            let (bytes, len) = slice(data, 7 + len)?;
+
+            (Command::Unknown(bytes), len)
+        }
+        b':' => (Command::MacroDefinition, 2),
+        b'$' => (Command::AbsoluteVerticalPosition(u16_at(data, 2)?), 4),
+        b'\\' => (
+            Command::RelativeVerticalPosition(u16_at(data, 2)? as i16),
+            4,
+        ),
+        b'^' => (
+            Command::ExecuteMacro {
+                times: param(0)?,
+                wait: param(1)?,
+                mode: param(2)?,
+            },
+            5,
+        ),
+        b'g' => return fixed(data, 4),
+        _ => return fixed(data, 1),
+    })
+}
+
+fn fs(data: &[u8]) -> Option<(Command<'_>, usize)> {
+    Some(match *data.get(1)? {
+        b'L' => (Command::DoubleDensityPageMode, 2),
+        b'(' => return extended(data),
+        // FS a 0 n reads a check, FS a 1 and FS a 2 load and eject it
+        b'a' if digit(*data.get(2)?) == 0 => return fixed(data, 2),
+        b'a' => return fixed(data, 1),
+        b'g' => {
+            // FS g 1 m a1 a2 a3 a4 nL nH [d1...dk], FS g 2 m a1 a2 a3 a4 nL nH
+            let function = digit(*data.get(2)?);
+            let len = if function == 1 {
+                u16_at(data, 8)? as usize
+            } else {
+                0
+            };
+            let (bytes, len) = slice(data, 10 + len)?;
+
+            (Command::Unknown(bytes), len)
+        }
+        b'.' | b'&' => return fixed(data, 0),
+        _ => return fixed(data, 1),
+    })
+}
+
+fn dle(data: &[u8]) -> Option<(Command<'_>, usize)> {
+    match *data.get(1)? {
+        // DLE DC4 fn [params], fn 8 being followed by d1...d7
+        0x14 if *data.get(2)? == 8 => fixed(data, 8),
+        0x14 => fixed(data, 3),
+        _ => fixed(data, 1),
+    }
+}
+
+fn dc2(data: &[u8]) -> Option<(Command<'_>, usize)> {
+    match data.get(1) {
+        Some(b'#') => fixed(data, 1),
+        Some(b'7') => fixed(data, 3),
+        // Not a command the clones know, so only DC2 itself is skipped
+        _ => Some((Command::Unknown(&data[..1]), 1)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CutMode, CutType, Printer};
+
+    fn commands(data: &[u8]) -> Vec<Command<'_>> {
+        decode(data).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let printer = Printer::document();
+        printer.justify(Justification::Center).unwrap();
+        printer.emphasized(true).unwrap();
+        printer.font(Font::B).unwrap();
+        printer.character_size(2, 3).unwrap();
+        printer.println("Hello").unwrap();
+        printer.feed_lines(2).unwrap();
+        printer.line_spacing(Some(40)).unwrap();
+        printer.position(100).unwrap();
+        printer.cut(CutMode::Feed(CutType::Partial, 3)).unwrap();
+
+        let data = printer.into_device().into_bytes();
+
+        assert_eq!(
+            commands(&data),
+            [
+                Command::Justify(Justification::Center),
+                Command::Emphasized(true),
+                Command::Font(Font::B),
+                Command::CharacterSize {
+                    width: 2,
+                    height: 3
+                },
+                Command::Text(b"Hello"),
+                Command::LineFeed,
+                Command::FeedLines(2),
+                Command::LineSpacing(Some(40)),
+                Command::AbsolutePosition(100),
+                Command::Cut {
+                    mode: 66,
+                    feed: Some(3)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn positions_cover_the_stream() {
+        let data = b"\x1b@ab\x1d\x21\x11c\n\x1dV\x00";
+        let mut decoder = decode(data);
+        let mut positions = vec![decoder.position()];
+
+        while decoder.next().is_some() {
+            positions.push(decoder.position());
+        }
+
+        assert_eq!(positions, [0, 2, 4, 7, 8, 9, 12]);
+    }
+
+    #[test]
+    fn realtime_buzzer_takes_seven_parameters() {
+        let data = [0x10, 0x14, 8, 1, 2, 3, 4, 5, 6, 7, LF];
+
+        assert_eq!(
+            commands(&data),
+            [Command::Unknown(&data[..10]), Command::LineFeed]
+        );
+    }
+
+    #[test]
+    fn realtime_pulse() {
+        let data = [0x10, 0x14, 1, 0, 5, LF];
+
+        assert_eq!(
+            commands(&data),
+            [Command::Unknown(&data[..5]), Command::LineFeed]
+        );
+    }
+
+    #[test]
+    fn clone_commands() {
+        let data = [DC2, b'#', 0x55, DC2, b'7', 11, 120, 40, b'A'];
+
+        assert_eq!(
+            commands(&data),
+            [
+                Command::Unknown(&data[..3]),
+                Command::Unknown(&data[3..8]),
+                Command::Text(b"A"),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_dc2_only_skips_itself() {
+        assert_eq!(
+            commands(&[DC2, b'A', b'B']),
+            [Command::Unknown(&[DC2]), Command::Text(b"AB")]
+        );
+        assert_eq!(commands(&[DC2]), [Command::Unknown(&[DC2])]);
+    }
+
+    #[test]
+    fn incomplete() {
+        let data = [ESC, b'W', 0, 0];
+
+        assert_eq!(commands(&data), [Command::Incomplete(&data)]);
+    }
+
+    #[test]
+    fn raster_image() {
+        let data = [GS, b'v', b'0', 0, 2, 0, 1, 0, 0xF0, 0x0F, LF];
+
+        assert_eq!(
+            commands(&data),
+            [
+                Command::RasterImage {
+                    mode: 0,
+                    width_bytes: 2,
+                    height: 1,
+                    data: &[0xF0, 0x0F],
+                },
+                Command::LineFeed,
+            ]
+        );
+    }
+
+    #[test]
+    fn check_commands() {
+        // FS a 0 n reads a check, FS a 1 loads it
+        let data = [FS, b'a', b'0', 1, FS, b'a', b'1', LF];
+
+        assert_eq!(
+            commands(&data),
+            [
+                Command::Unknown(&data[..4]),
+                Command::Unknown(&data[4..7]),
+                Command::LineFeed,
+            ]
+        );
+    }
+}
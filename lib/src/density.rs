@@ -0,0 +1,179 @@
+//! Print density, print speed and print head heating
+//!
+//! Epson printers pick a density and speed from fixed steps (GS ( K), while
+//! the common clones are tuned by how long and how many dots of the print
+//! head are heated at once (DC2 # and DC2 7). The [`Profile`] tells which of
+//! the two a printer understands.
+//!
+//! [`Profile`]: crate::Profile
+
+use std::time::Duration;
+
+use crate::{error::PrinterError, profile::PrintControl, Device, Printer};
+
+const DC2: u8 = 0x12;
+
+/// Heating of the print head on clones
+///
+/// More dots and longer heating times print darker but slower, and draw more
+/// current.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Heating {
+    /// Dots heated at once, in steps of 8 up to 2048
+    pub max_dots: u16,
+    /// How long the dots are heated, in steps of 10 µs up to 2.55 ms
+    pub time: Duration,
+    /// Pause between heating rounds, in steps of 10 µs up to 2.55 ms
+    pub interval: Duration,
+}
+
+impl Default for Heating {
+    /// The settings most clones start with
+    fn default() -> Self {
+        Self {
+            max_dots: 96,
+            time: Duration::from_micros(1200),
+            interval: Duration::from_micros(400),
+        }
+    }
+}
+
+/// Convert a duration to 10 µs units
+fn tens_of_micros(duration: Duration) -> u8 {
+    (duration.as_micros() / 10).min(255) as u8
+}
+
+impl<D: Device> Printer<D> {
+    fn print_control(&self, feature: &'static str) -> Result<PrintControl, PrinterError> {
+        self.profile.print_control.ok_or(PrinterError::Unsupported {
+            feature,
+            profile: self.profile.name,
+        })
+    }
+
+    /// Select print density
+    ///
+    /// Sets the density from -6 (lightest) through 0 (standard) to 6
+    /// (darkest). Clones spread the range over their 32 density steps.
+    ///
+    /// ASCII: GS ( K 2 0 49 **m**, or DC2 # **n** on the clones
+    pub fn print_density(&self, density: i8) -> Result<(), PrinterError> {
+        let density = density.clamp(-6, 6);
+
+        match self.print_control("print density")? {
+            PrintControl::Epson => self.raw([Self::GS, b'(', b'K', 2, 0, 49, density as u8]),
+            PrintControl::Heating => {
+                // 50% + 5% per step, with the default break time of 2
+                let level = ((density + 6) as u16 * 31 / 12) as u8;
+
+                self.raw([DC2, b'#', 2 << 5 | level])
+            }
+        }
+    }
+
+    /// Select print speed
+    ///
+    /// Sets the speed from 1 (slowest) to a model specific maximum, or the
+    /// default speed with 0. Clones print as fast as their [`Heating`]
+    /// allows instead.
+    ///
+    /// ASCII: GS ( K 2 0 50 **m**
+    pub fn print_speed(&self, speed: u8) -> Result<(), PrinterError> {
+        match self.print_control("print speed")? {
+            PrintControl::Epson => self.raw([Self::GS, b'(', b'K', 2, 0, 50, speed]),
+            PrintControl::Heating => Err(PrinterError::Unsupported {
+                feature: "print speed",
+                profile: self.profile.name,
+            }),
+        }
+    }
+
+    /// Set heating parameters
+    ///
+    /// Only the clones can be tuned this way, Epson printers use
+    /// [`print_density`](Self::print_density) and
+    /// [`print_speed`](Self::print_speed).
+    ///
+    /// ASCII: DC2 7 **n1** **n2** **n3**
+    pub fn heating(&self, heating: Heating) -> Result<(), PrinterError> {
+        match self.print_control("print head heating")? {
+            PrintControl::Heating => {
+                let dots = (heating.max_dots.clamp(8, 2048) / 8 - 1) as u8;
+
+                self.raw([
+                    DC2,
+                    b'7',
+                    dots,
+                    tens_of_micros(heating.time),
+                    tens_of_micros(heating.interval),
+                ])
+            }
+            PrintControl::Epson => Err(PrinterError::Unsupported {
+                feature: "print head heating",
+                profile: self.profile.name,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Document, Profile};
+
+    #[test]
+    fn epson() {
+        let printer = Printer::document();
+        printer.print_density(-9).unwrap();
+        printer.print_density(3).unwrap();
+        printer.print_speed(4).unwrap();
+
+        assert_eq!(
+            printer.device().to_vec(),
+            [
+                0x1D, b'(', b'K', 2, 0, 49, 0xFA, // Clamped to -6
+                0x1D, b'(', b'K', 2, 0, 49, 3, // Darker
+                0x1D, b'(', b'K', 2, 0, 50, 4, // Speed
+            ]
+        );
+        assert!(matches!(
+            printer.heating(Heating::default()),
+            Err(PrinterError::Unsupported { .. })
+        ));
+    }
+
+    #[test]
+    fn clone_density() {
+        let printer = Printer::with_profile(Document::new(), Profile::POS_80);
+        printer.print_density(-6).unwrap();
+        printer.print_density(0).unwrap();
+        printer.print_density(6).unwrap();
+
+        assert_eq!(
+            printer.device().to_vec(),
+            [0x12, b'#', 0x40, 0x12, b'#', 0x4F, 0x12, b'#', 0x5F]
+        );
+        assert!(matches!(
+            printer.print_speed(1),
+            Err(PrinterError::Unsupported { .. })
+        ));
+    }
+
+    #[test]
+    fn clone_heating() {
+        let printer = Printer::with_profile(Document::new(), Profile::POS_80);
+        printer.heating(Heating::default()).unwrap();
+        printer
+            .heating(Heating {
+                max_dots: 4096,
+                time: Duration::from_millis(3),
+                interval: Duration::from_micros(5),
+            })
+            .unwrap();
+
+        assert_eq!(
+            printer.device().to_vec(),
+            [0x12, b'7', 11, 120, 40, 0x12, b'7', 255, 255, 0]
+        );
+    }
+}
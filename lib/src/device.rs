@@ -0,0 +1,268 @@
+use std::{cell::RefCell, collections::VecDeque, time::Duration};
+
+use rusb::{Context, DeviceHandle, Direction, TransferType, UsbContext};
+use tracing::warn;
+
+use crate::{
+    discovery::PRINTER_CLASS,
+    error::{ConnectionError, PrinterError},
+};
+
+/// A destination for ESC/POS command bytes
+pub trait Device {
+    /// Write raw bytes to the device
+    fn write(&self, data: &[u8]) -> Result<(), PrinterError>;
+
+    /// Read the bytes sent back by the device, like status replies, waiting
+    /// at most `timeout` for them
+    ///
+    /// Returns the amount of bytes read. Devices that can not send anything
+    /// back fail with [`PrinterError::NotReadable`].
+    fn read(&self, buf: &mut [u8], timeout: Duration) -> Result<usize, PrinterError> {
+        let _ = (buf, timeout);
+
+        Err(PrinterError::NotReadable)
+    }
+}
+
+/// A printer connected over USB
+///
+/// The claimed interface is released when the device is dropped, and the
+/// kernel driver is given it back if it had to be detached.
+pub struct UsbDevice {
+    pub(crate) endpoint: u8,
+    /// Bulk IN endpoint, for printers that send status back
+    pub(crate) in_endpoint: Option<u8>,
+    pub(crate) handle: DeviceHandle<Context>,
+    pub(crate) timeout: Duration,
+    /// The claimed interface
+    interface: u8,
+    /// If a kernel driver was detached from the interface
+    reattach_driver: bool,
+}
+
+/// The interface a printer is written through
+struct PrinterInterface {
+    number: u8,
+    alternate_setting: u8,
+    endpoint: u8,
+    in_endpoint: Option<u8>,
+}
+
+impl PrinterInterface {
+    /// Find the interface to print through
+    ///
+    /// Composite devices can have their printer interface anywhere, so the
+    /// first alternate setting of the printer class with a bulk OUT endpoint
+    /// is preferred over any other interface with one.
+    fn find(device: &rusb::Device<Context>) -> Result<Self, ConnectionError> {
+        let config_descriptor = device.active_config_descriptor()?;
+        let mut fallback = None;
+
+        for interface in config_descriptor.interfaces() {
+            for descriptor in interface.descriptors() {
+                let mut endpoint = None;
+                let mut in_endpoint = None;
+
+                for endpoint_descriptor in descriptor.endpoint_descriptors() {
+                    if endpoint_descriptor.transfer_type() != TransferType::Bulk {
+                        continue;
+                    }
+
+                    match endpoint_descriptor.direction() {
+                        Direction::Out if endpoint.is_none() => {
+                            endpoint = Some(endpoint_descriptor.address())
+                        }
+                        Direction::In if in_endpoint.is_none() => {
+                            in_endpoint = Some(endpoint_descriptor.address())
+                        }
+                        _ => {}
+                    }
+                }
+
+                let found = match endpoint {
+                    Some(endpoint) => Self {
+                        number: descriptor.interface_number(),
+                        alternate_setting: descriptor.setting_number(),
+                        endpoint,
+                        in_endpoint,
+                    },
+                    None => continue,
+                };
+
+                if descriptor.class_code() == PRINTER_CLASS {
+                    return Ok(found);
+                }
+
+                fallback.get_or_insert(found);
+            }
+        }
+
+        fallback.ok_or(ConnectionError::NoBulkEndpoint)
+    }
+}
+
+impl UsbDevice {
+    /// Open a device and claim its printer interface
+    pub(crate) fn open(
+        device: &rusb::Device<Context>,
+        timeout: Duration,
+    ) -> Result<Self, ConnectionError> {
+        let interface = PrinterInterface::find(device)?;
+        let mut handle = device.open()?;
+
+        let reattach_driver = match handle.kernel_driver_active(interface.number) {
+            Ok(true) => {
+                handle.detach_kernel_driver(interface.number)?;
+                true
+            }
+            Ok(false) => false,
+            Err(_) => {
+                warn!("Unable to detect kernel driver. This may cause issues later");
+                false
+            }
+        };
+
+        handle.claim_interface(interface.number)?;
+
+        if interface.alternate_setting != 0 {
+            handle.set_alternate_setting(interface.number, interface.alternate_setting)?;
+        }
+
+        Ok(Self {
+            endpoint: interface.endpoint,
+            in_endpoint: interface.in_endpoint,
+            handle,
+            timeout,
+            interface: interface.number,
+            reattach_driver,
+        })
+    }
+
+    /// If the device is still plugged in
+    ///
+    /// A power cycled printer comes back at a new address, so it counts as
+    /// a different device.
+    pub(crate) fn is_attached(&self) -> bool {
+        let device = self.handle.device();
+
+        self.handle.context().devices().is_ok_and(|devices| {
+            devices.iter().any(|other| {
+                other.bus_number() == device.bus_number() && other.address() == device.address()
+            })
+        })
+    }
+
+    /// The serial number of the device, if it has one
+    pub(crate) fn serial_number(&self) -> Option<String> {
+        let descriptor = self.handle.device().device_descriptor().ok()?;
+
+        self.handle
+            .read_serial_number_string_ascii(&descriptor)
+            .ok()
+            .map(|serial_number| serial_number.trim().to_owned())
+            .filter(|serial_number| !serial_number.is_empty())
+    }
+}
+
+impl Device for UsbDevice {
+    fn write(&self, data: &[u8]) -> Result<(), PrinterError> {
+        self.handle.write_bulk(self.endpoint, data, self.timeout)?;
+
+        Ok(())
+    }
+
+    fn read(&self, buf: &mut [u8], timeout: Duration) -> Result<usize, PrinterError> {
+        let endpoint = self.in_endpoint.ok_or(PrinterError::NotReadable)?;
+
+        match self.handle.read_bulk(endpoint, buf, timeout) {
+            Ok(len) => Ok(len),
+            Err(rusb::Error::Timeout) => Ok(0),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+impl Drop for UsbDevice {
+    fn drop(&mut self) {
+        if let Err(err) = self.handle.release_interface(self.interface) {
+            warn!("Unable to release interface {}: {}", self.interface, err);
+        }
+
+        if self.reattach_driver {
+            if let Err(err) = self.handle.attach_kernel_driver(self.interface) {
+                warn!("Unable to reattach kernel driver: {}", err);
+            }
+        }
+    }
+}
+
+/// A fake printer that records what is written and answers reads from a
+/// queue of canned replies
+///
+/// Useful for exercising status and MICR handling without hardware:
+///
+/// ```
+/// use thermal::{MockDevice, Printer};
+///
+/// let printer = Printer::new(MockDevice::new());
+/// printer.device().reply([0x16]);
+///
+/// assert!(!printer.status().unwrap().offline);
+/// assert_eq!(printer.device().take_written(), [0x10, 0x04, 0x01]);
+/// ```
+#[derive(Debug, Default)]
+pub struct MockDevice {
+    written: RefCell<Vec<u8>>,
+    /// Replies, each read back as a separate transfer
+    replies: RefCell<VecDeque<Vec<u8>>>,
+}
+
+impl MockDevice {
+    /// Create a device with nothing written and no replies queued
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue a reply for the printer to read back
+    ///
+    /// A read returns at most one reply, like a USB transfer would.
+    pub fn reply<B: AsRef<[u8]>>(&self, data: B) {
+        self.replies.borrow_mut().push_back(data.as_ref().to_vec());
+    }
+
+    /// A copy of the bytes written so far
+    pub fn written(&self) -> Vec<u8> {
+        self.written.borrow().clone()
+    }
+
+    /// Take the bytes written so far, clearing the record
+    pub fn take_written(&self) -> Vec<u8> {
+        self.written.take()
+    }
+}
+
+impl Device for MockDevice {
+    fn write(&self, data: &[u8]) -> Result<(), PrinterError> {
+        self.written.borrow_mut().extend_from_slice(data);
+
+        Ok(())
+    }
+
+    /// Reads the queued replies, returning nothing once they run out
+    fn read(&self, buf: &mut [u8], _timeout: Duration) -> Result<usize, PrinterError> {
+        let mut replies = self.replies.borrow_mut();
+        let Some(mut reply) = replies.pop_front() else {
+            return Ok(0);
+        };
+
+        let len = buf.len().min(reply.len());
+        buf[..len].copy_from_slice(&reply[..len]);
+
+        if len < reply.len() {
+            replies.push_front(reply.split_off(len));
+        }
+
+        Ok(len)
+    }
+}
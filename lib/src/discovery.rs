@@ -0,0 +1,186 @@
+//! Finding the printers attached to the host
+//!
+//! Every USB printer class device and every device with the ids of a known
+//! [`Profile`] is listed, so identical printers can be told apart by their
+//! serial number or the port they are plugged into:
+//!
+//! ```no_run
+//! use thermal::{discovery, rusb::Context};
+//!
+//! let context = Context::new().unwrap();
+//!
+//! for info in discovery::discover(&context).unwrap() {
+//!     println!("{} {:?} {:?}", info.path, info.product, info.serial_number);
+//! }
+//! ```
+
+use rusb::{Context, Device, DeviceDescriptor, DeviceHandle, UsbContext};
+
+use crate::{error::ConnectionError, Printer, PrinterBuilder, Profile};
+
+/// USB interface class of printers
+pub(crate) const PRINTER_CLASS: u8 = 0x07;
+
+/// Reads one of the string descriptors of a device
+type ReadString = fn(&DeviceHandle<Context>, &DeviceDescriptor) -> rusb::Result<String>;
+
+/// A printer attached to the host
+#[derive(Debug, Clone)]
+pub struct PrinterInfo {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub bus: u8,
+    pub address: u8,
+    /// Where the printer is plugged in, as the bus and the ports leading to
+    /// it, like `1-2.4`
+    ///
+    /// Stays the same as long as the printer is plugged into the same port.
+    pub path: String,
+    /// String descriptors, if the device could be opened to read them
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial_number: Option<String>,
+    /// If the device identifies itself as a USB printer
+    pub printer_class: bool,
+    /// The profile of a known printer model
+    pub profile: Option<&'static Profile>,
+}
+
+/// List the printers attached to the host
+///
+/// Devices that can not be inspected are left out.
+pub fn discover(context: &Context) -> Result<Vec<PrinterInfo>, ConnectionError> {
+    Ok(context
+        .devices()?
+        .iter()
+        .filter_map(|device| PrinterInfo::new(&device))
+        .collect())
+}
+
+/// The bus and ports leading to a device, like `1-2.4`
+pub(crate) fn path(device: &Device<Context>) -> String {
+    let ports = device
+        .port_numbers()
+        .unwrap_or_default()
+        .iter()
+        .map(|port| port.to_string())
+        .collect::<Vec<_>>()
+        .join(".");
+
+    format!("{}-{}", device.bus_number(), ports)
+}
+
+/// Read a string descriptor of the device, if it has one
+fn read_string<F>(handle: &DeviceHandle<Context>, read: F) -> Option<String>
+where
+    F: FnOnce(&DeviceHandle<Context>) -> rusb::Result<String>,
+{
+    read(handle)
+        .ok()
+        .map(|string| string.trim().to_owned())
+        .filter(|string| !string.is_empty())
+}
+
+/// The serial number of a device, if it can be opened to read it
+pub(crate) fn serial_number(
+    device: &Device<Context>,
+    descriptor: &DeviceDescriptor,
+) -> Option<String> {
+    let handle = device.open().ok()?;
+
+    read_string(&handle, |handle| {
+        handle.read_serial_number_string_ascii(descriptor)
+    })
+}
+
+impl PrinterInfo {
+    fn new(device: &Device<Context>) -> Option<Self> {
+        let descriptor = device.device_descriptor().ok()?;
+        let (vendor_id, product_id) = (descriptor.vendor_id(), descriptor.product_id());
+
+        let printer_class = descriptor.class_code() == PRINTER_CLASS
+            || device.active_config_descriptor().is_ok_and(|config| {
+                config
+                    .interfaces()
+                    .flat_map(|interface| interface.descriptors())
+                    .any(|interface| interface.class_code() == PRINTER_CLASS)
+            });
+        let profile = Profile::for_device(vendor_id, product_id);
+
+        if !printer_class && profile.is_none() {
+            return None;
+        }
+
+        let handle = device.open().ok();
+        let string = |read: ReadString| {
+            handle
+                .as_ref()
+                .and_then(|handle| read_string(handle, |handle| read(handle, &descriptor)))
+        };
+
+        Some(Self {
+            vendor_id,
+            product_id,
+            bus: device.bus_number(),
+            address: device.address(),
+            path: path(device),
+            manufacturer: string(DeviceHandle::read_manufacturer_string_ascii),
+            product: string(DeviceHandle::read_product_string_ascii),
+            serial_number: string(DeviceHandle::read_serial_number_string_ascii),
+            printer_class,
+            profile,
+        })
+    }
+
+    /// A builder that connects to exactly this printer
+    pub fn builder(&self) -> PrinterBuilder {
+        Printer::builder(self.vendor_id, self.product_id).path(&self.path)
+    }
+
+    /// Connect to this printer
+    pub fn connect(&self, context: &Context) -> Result<Printer, ConnectionError> {
+        self.builder()
+            .connect(context)?
+            .ok_or(ConnectionError::NotFound)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builder_selects_the_port() {
+        let info = PrinterInfo {
+            vendor_id: 0x1a86,
+            product_id: 0x7584,
+            bus: 1,
+            address: 4,
+            path: "1-2.4".to_owned(),
+            manufacturer: None,
+            product: None,
+            serial_number: Some("A1".to_owned()),
+            printer_class: true,
+            profile: Profile::for_device(0x1a86, 0x7584),
+        };
+        let builder = info.builder();
+
+        assert_eq!((builder.vendor_id, builder.product_id), (0x1a86, 0x7584));
+        assert_eq!(builder.path.as_deref(), Some("1-2.4"));
+        // The port already tells identical printers apart
+        assert_eq!(builder.serial_number, None);
+    }
+
+    #[test]
+    fn known_profiles() {
+        assert_eq!(
+            Profile::for_device(0x1a86, 0x7584).map(|profile| profile.name),
+            Some(Profile::POS_80.name)
+        );
+        assert_eq!(
+            Profile::for_device(0x04b8, 0x0202).map(|profile| profile.name),
+            Some(Profile::EPSON_TM_T88.name)
+        );
+        assert!(Profile::for_device(0x04b8, 0xffff).is_none());
+    }
+}
@@ -0,0 +1,83 @@
+use std::cell::{Cell, RefCell};
+
+use crate::{device::Device, error::PrinterError, Printer};
+
+/// An in-memory buffer of ESC/POS commands
+///
+/// Build a document with a `Printer<Document>` and submit it to a real
+/// printer, or hand the bytes to the emulator for a preview.
+#[derive(Debug, Default)]
+pub struct Document {
+    data: RefCell<Vec<u8>>,
+}
+
+impl Document {
+    /// Create an empty document
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a document from previously recorded command bytes
+    pub fn from_bytes(data: Vec<u8>) -> Self {
+        Self {
+            data: RefCell::new(data),
+        }
+    }
+
+    /// A copy of the command bytes recorded so far
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.data.borrow().clone()
+    }
+
+    /// Take the recorded command bytes
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data.into_inner()
+    }
+
+    /// The amount of bytes recorded so far
+    pub fn len(&self) -> usize {
+        self.data.borrow().len()
+    }
+
+    /// If nothing has been recorded yet
+    pub fn is_empty(&self) -> bool {
+        self.data.borrow().is_empty()
+    }
+}
+
+impl Device for Document {
+    fn write(&self, data: &[u8]) -> Result<(), PrinterError> {
+        self.data.borrow_mut().extend_from_slice(data);
+
+        Ok(())
+    }
+}
+
+impl Printer<Document> {
+    /// Create a printer that records commands into a [`Document`]
+    pub fn document() -> Self {
+        Printer::new(Document::new())
+    }
+}
+
+impl<D: Device> Printer<D> {
+    /// A printer recording into a new document, starting from the profile and
+    /// layout state of this printer
+    pub(crate) fn buffer(&self) -> Printer<Document> {
+        Printer {
+            device: Document::new(),
+            profile: self.profile.clone(),
+            state: Cell::new(self.state.get()),
+            glyphs: self.glyphs.clone(),
+        }
+    }
+
+    /// Send the commands recorded by a [`buffer`](Self::buffer) and take over
+    /// its layout state
+    pub(crate) fn submit_buffer(&self, buffer: &Printer<Document>) -> Result<(), PrinterError> {
+        self.submit(buffer.device())?;
+        self.state.set(buffer.state.get());
+
+        Ok(())
+    }
+}
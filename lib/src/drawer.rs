@@ -0,0 +1,183 @@
+//! The cash drawer and the buzzer
+//!
+//! Cash drawers are connected to the drawer kick-out connector (usually an
+//! RJ-12 socket) of the printer, which can drive two drawers, one on pin 2
+//! and one on pin 5. Pin 3 reports the drawer switch back to the printer.
+
+use std::time::Duration;
+
+use crate::{error::PrinterError, profile::Buzzer, Device, Printer};
+
+const DLE: u8 = 0x10;
+const DC4: u8 = 0x14;
+
+/// The drawer kick-out connector pin a drawer is wired to
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DrawerPin {
+    /// Pin 2, where a single drawer is normally connected
+    #[default]
+    Pin2 = 0x00,
+    /// Pin 5, for a second drawer
+    Pin5 = 0x01,
+}
+
+/// Convert a duration to a count of `unit`, rounding down and clamping it
+fn units(duration: Duration, unit: Duration, max: u8) -> u8 {
+    (duration.as_millis() / unit.as_millis()).min(max as u128) as u8
+}
+
+impl<D: Device> Printer<D> {
+    /// Generate pulse
+    ///
+    /// Drives the given pin for **t1** × 2 ms, then waits **t2** × 2 ms.
+    /// Times are rounded down to 2 ms and limited to 510 ms. The pulse is
+    /// generated in order with the other commands, after printing before it.
+    ///
+    /// ASCII: ESC p **m** **t1** **t2**
+    pub fn drawer_pulse(
+        &self,
+        pin: DrawerPin,
+        on: Duration,
+        off: Duration,
+    ) -> Result<(), PrinterError> {
+        let unit = Duration::from_millis(2);
+
+        self.raw([
+            Self::ESC,
+            b'p',
+            pin as u8,
+            units(on, unit, 255),
+            units(off, unit, 255),
+        ])
+    }
+
+    /// Generate pulse in real-time
+    ///
+    /// Drives the given pin for [**t** × 100 ms] right away, even while the
+    /// printer is busy. The time is rounded down to 100 ms and kept between
+    /// 100 ms and 800 ms.
+    ///
+    /// ASCII: DLE DC4 1 **m** **t**
+    pub fn drawer_pulse_now(&self, pin: DrawerPin, on: Duration) -> Result<(), PrinterError> {
+        let on = units(on, Duration::from_millis(100), 8).max(1);
+
+        self.raw([DLE, DC4, 1, pin as u8, on])
+    }
+
+    /// Open the drawer on the given pin, with a pulse most drawers accept
+    pub fn open_drawer(&self, pin: DrawerPin) -> Result<(), PrinterError> {
+        self.drawer_pulse(pin, Duration::from_millis(100), Duration::from_millis(500))
+    }
+
+    /// If the drawer is open, as reported by the real-time status
+    ///
+    /// Most drawers close the switch on pin 3 when they are open. For drawers
+    /// wired the other way around, this reports the opposite.
+    pub fn drawer_open(&self) -> Result<bool, PrinterError> {
+        Ok(self.status()?.drawer_sensor)
+    }
+
+    /// Sound the buzzer `times` times, each for `duration`
+    ///
+    /// Epson printers take the count (1-63) and the duration in 100 ms units,
+    /// the clones take the count (1-9) and the duration in 50 ms units.
+    ///
+    /// ASCII: ESC ( A 3 0 97 **n** **t**, or ESC B **n** **t** on the clones
+    pub fn beep(&self, times: u8, duration: Duration) -> Result<(), PrinterError> {
+        match self.profile.buzzer {
+            Some(Buzzer::Epson) => {
+                let duration = units(duration, Duration::from_millis(100), 255).max(1);
+
+                self.raw([
+                    Self::ESC,
+                    b'(',
+                    b'A',
+                    3,
+                    0,
+                    97,
+                    times.clamp(1, 63),
+                    duration,
+                ])
+            }
+            Some(Buzzer::Clone) => {
+                let duration = units(duration, Duration::from_millis(50), 255).max(1);
+
+                self.raw([Self::ESC, b'B', times.clamp(1, 9), duration])
+            }
+            None => Err(PrinterError::Unsupported {
+                feature: "the buzzer",
+                profile: self.profile.name,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Document, Profile};
+
+    #[test]
+    fn pulse() {
+        let printer = Printer::document();
+        printer.open_drawer(DrawerPin::Pin2).unwrap();
+        // Longer than 510 ms is clamped, shorter than 2 ms rounds down
+        printer
+            .drawer_pulse(
+                DrawerPin::Pin5,
+                Duration::from_secs(1),
+                Duration::from_millis(3),
+            )
+            .unwrap();
+
+        assert_eq!(
+            printer.device().to_vec(),
+            [0x1B, b'p', 0, 50, 250, 0x1B, b'p', 1, 255, 1]
+        );
+    }
+
+    #[test]
+    fn pulse_now() {
+        let printer = Printer::document();
+        printer
+            .drawer_pulse_now(DrawerPin::Pin2, Duration::from_millis(50))
+            .unwrap();
+        printer
+            .drawer_pulse_now(DrawerPin::Pin5, Duration::from_secs(2))
+            .unwrap();
+
+        assert_eq!(
+            printer.device().to_vec(),
+            [0x10, 0x14, 1, 0, 1, 0x10, 0x14, 1, 1, 8]
+        );
+    }
+
+    #[test]
+    fn beep() {
+        let epson = Printer::document();
+        epson.beep(70, Duration::from_millis(250)).unwrap();
+        assert_eq!(epson.device().to_vec(), [0x1B, b'(', b'A', 3, 0, 97, 63, 2]);
+
+        let clone = Printer::with_profile(Document::new(), Profile::POS_80);
+        clone.beep(12, Duration::from_millis(120)).unwrap();
+        clone.beep(0, Duration::ZERO).unwrap();
+        assert_eq!(
+            clone.device().to_vec(),
+            [0x1B, b'B', 9, 2, 0x1B, b'B', 1, 1]
+        );
+
+        let silent = Printer::with_profile(
+            Document::new(),
+            Profile {
+                buzzer: None,
+                ..Profile::GENERIC
+            },
+        );
+        assert!(matches!(
+            silent.beep(1, Duration::from_millis(100)),
+            Err(PrinterError::Unsupported { .. })
+        ));
+        assert!(silent.device().is_empty());
+    }
+}
@@ -0,0 +1,1211 @@
+//! A virtual printer that renders ESC/POS streams to an image
+//!
+//! Useful for previewing a [`Document`](crate::Document) before it is sent to
+//! a real printer, or for comparing the output of a layout against a known
+//! good image.
+
+use std::{collections::HashMap, io::Write, ops::RangeInclusive};
+
+use qrcode::{EcLevel, QrCode};
+
+use crate::{
+    decode::{self, digit, Command},
+    Font, Justification, Profile,
+};
+
+mod barcode;
+mod font;
+pub mod text;
+
+/// Dot density of the emulated print head unless a profile says otherwise,
+/// in dots per inch
+pub const DPI: u32 = 203;
+
+/// The paper width of an 80mm roll, in dots
+pub const PAPER_WIDTH_80MM: u32 = 576;
+
+/// The paper width of a 58mm roll, in dots
+pub const PAPER_WIDTH_58MM: u32 = 384;
+
+/// A rectangle of dots waiting to be placed on the paper
+#[derive(Debug, Clone)]
+pub(crate) struct Block {
+    width: u32,
+    height: u32,
+    pixels: Vec<bool>,
+}
+
+impl Block {
+    pub(crate) fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![false; (width * height) as usize],
+        }
+    }
+
+    pub(crate) fn set(&mut self, x: u32, y: u32) {
+        if x < self.width && y < self.height {
+            self.pixels[(y * self.width + x) as usize] = true;
+        }
+    }
+
+    fn get(&self, x: u32, y: u32) -> bool {
+        x < self.width && y < self.height && self.pixels[(y * self.width + x) as usize]
+    }
+
+    fn scale(&self, scale_x: u32, scale_y: u32) -> Block {
+        let mut scaled = Block::new(self.width * scale_x, self.height * scale_y);
+
+        for y in 0..scaled.height {
+            for x in 0..scaled.width {
+                if self.get(x / scale_x, y / scale_y) {
+                    scaled.set(x, y);
+                }
+            }
+        }
+
+        scaled
+    }
+
+    /// Thicken every dot one to the right, like emphasized and double-strike
+    /// printing does
+    fn embolden(&mut self) {
+        for y in 0..self.height {
+            for x in (1..self.width).rev() {
+                if self.get(x - 1, y) {
+                    self.set(x, y);
+                }
+            }
+        }
+    }
+
+    fn invert(&mut self) {
+        for pixel in &mut self.pixels {
+            *pixel = !*pixel;
+        }
+    }
+
+    fn rotate_180(&mut self) {
+        self.pixels.reverse();
+    }
+
+    /// Copy another block on top of this one
+    fn blit(&mut self, block: &Block, left: u32, top: u32) {
+        for y in 0..block.height {
+            for x in 0..block.width {
+                if block.get(x, y) {
+                    self.set(left + x, top + y);
+                }
+            }
+        }
+    }
+}
+
+/// A cut made by the virtual printer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cut {
+    /// Distance from the top of the receipt, in dots
+    pub position: u32,
+    /// If a part of the paper is left uncut
+    pub partial: bool,
+}
+
+/// The paper printed by the virtual printer
+#[derive(Debug, Clone)]
+pub struct Receipt {
+    width: u32,
+    height: u32,
+    pixels: Vec<bool>,
+    cuts: Vec<Cut>,
+}
+
+impl Receipt {
+    fn new(width: u32) -> Self {
+        Self {
+            width,
+            height: 0,
+            pixels: Vec::new(),
+            cuts: Vec::new(),
+        }
+    }
+
+    fn grow(&mut self, height: u32) {
+        if height > self.height {
+            self.height = height;
+            self.pixels.resize((self.width * height) as usize, false);
+        }
+    }
+
+    fn set(&mut self, x: u32, y: u32) {
+        if x < self.width {
+            self.grow(y + 1);
+            self.pixels[(y * self.width + x) as usize] = true;
+        }
+    }
+
+    /// Width of the paper in dots
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Length of paper that has been fed out, in dots
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// If the dot at the given position was burnt
+    pub fn pixel(&self, x: u32, y: u32) -> bool {
+        x < self.width && y < self.height && self.pixels[(y * self.width + x) as usize]
+    }
+
+    /// All the cuts made, from top to bottom
+    pub fn cuts(&self) -> &[Cut] {
+        &self.cuts
+    }
+
+    /// The receipt as 8 bit grayscale rows, with cuts drawn as dashed gray lines
+    pub fn to_grayscale(&self) -> Vec<u8> {
+        let height = self.height.max(1);
+        let mut image: Vec<u8> = (0..self.width * height)
+            .map(|i| {
+                if self.pixel(i % self.width, i / self.width) {
+                    0x00
+                } else {
+                    0xFF
+                }
+            })
+            .collect();
+
+        for cut in &self.cuts {
+            let y = cut.position.min(height - 1);
+            let dash = if cut.partial { 4 } else { 8 };
+
+            for x in 0..self.width {
+                if x / dash % 2 == 0 {
+                    image[(y * self.width + x) as usize] = 0x80;
+                }
+            }
+        }
+
+        image
+    }
+
+    /// Encode the receipt as a PNG image
+    pub fn write_png<W: Write>(&self, writer: W) -> Result<(), png::EncodingError> {
+        let mut encoder = png::Encoder::new(writer, self.width, self.height.max(1));
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.to_grayscale())?;
+        writer.finish()
+    }
+
+    /// Encode the receipt as a PNG image in memory
+    pub fn to_png(&self) -> Result<Vec<u8>, png::EncodingError> {
+        let mut png = Vec::new();
+        self.write_png(&mut png)?;
+
+        Ok(png)
+    }
+}
+
+/// The page mode print buffer
+#[derive(Debug, Clone)]
+struct Page {
+    /// Everything buffered so far, across all the print areas used
+    buffer: Receipt,
+    /// Length of the page, the bottom of the lowest print area used
+    length: u32,
+    /// Print area in dots: x, y, width and height
+    area: (u32, u32, u32, u32),
+    /// Print direction (ESC T)
+    direction: u8,
+    /// Top of the current print line, in print direction coordinates
+    y: u32,
+}
+
+impl Page {
+    fn new(paper_width: u32) -> Self {
+        Self {
+            buffer: Receipt::new(paper_width),
+            length: 0,
+            area: (0, 0, paper_width, 0),
+            direction: 0,
+            y: 0,
+        }
+    }
+
+    /// Size of the print area in print direction coordinates
+    fn size(&self) -> (u32, u32) {
+        let (_, _, width, height) = self.area;
+
+        match self.direction {
+            1 | 3 => (height, width),
+            _ => (width, height),
+        }
+    }
+
+    /// Set a dot given in print direction coordinates
+    fn set(&mut self, x: u32, y: u32) {
+        let (left, top, width, height) = self.area;
+
+        // The default print area has no fixed height and grows with the data
+        let height = if height == 0 { y + 1 } else { height };
+
+        let (x, y) = match self.direction {
+            1 => (y, height.wrapping_sub(1 + x)),
+            2 => (width.wrapping_sub(1 + x), height.wrapping_sub(1 + y)),
+            3 => (width.wrapping_sub(1 + y), x),
+            _ => (x, y),
+        };
+
+        if x < width && y < height {
+            self.buffer.set(left + x, top + y);
+            self.length = self.length.max(top + y + 1);
+        }
+    }
+
+    /// Delete the data in the current print area
+    fn cancel(&mut self) {
+        let (left, top, width, height) = self.area;
+
+        for y in top..(top + height).min(self.buffer.height) {
+            for x in left..(left + width).min(self.buffer.width) {
+                self.buffer.pixels[(y * self.buffer.width + x) as usize] = false;
+            }
+        }
+    }
+}
+
+/// The macro stored on a printer
+///
+/// Bytes between a pair of GS : are recorded instead of executed, to be
+/// replayed by GS ^.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Macro {
+    /// The macro being recorded
+    definition: Option<Vec<u8>>,
+    commands: Vec<u8>,
+}
+
+impl Macro {
+    /// Record the bytes of a command if a macro is being defined, returning
+    /// if the command was consumed
+    pub(crate) fn record(&mut self, command: &Command, bytes: &[u8]) -> bool {
+        match (command, &mut self.definition) {
+            (Command::MacroDefinition, definition @ Some(_)) => {
+                self.commands = definition.take().unwrap_or_default();
+            }
+            (Command::MacroDefinition, definition) => *definition = Some(Vec::new()),
+            // A macro can not run itself
+            (Command::ExecuteMacro { .. }, Some(_)) => {}
+            (_, Some(definition)) => definition.extend_from_slice(bytes),
+            (_, None) => return false,
+        }
+
+        true
+    }
+
+    /// The commands of the stored macro
+    pub(crate) fn commands(&self) -> Vec<u8> {
+        self.commands.clone()
+    }
+}
+
+/// Settings reset by ESC @
+#[derive(Debug, Clone)]
+struct State {
+    font: Font,
+    emphasized: bool,
+    double_strike: bool,
+    underline: u8,
+    width: u32,
+    height: u32,
+    reverse: bool,
+    upside_down: bool,
+    justification: Justification,
+    line_spacing: u32,
+    /// Left margin and print area width in dots
+    left_margin: u32,
+    print_width: Option<u32>,
+    /// Right-side character spacing in dots
+    character_spacing: u32,
+    /// Horizontal tab positions in dots, in ascending order
+    tab_stops: Vec<u32>,
+    /// Motion units, in units per inch
+    horizontal_unit: u32,
+    vertical_unit: u32,
+    barcode_height: u32,
+    barcode_width: u32,
+    hri_position: u8,
+    hri_font: Font,
+    qr_module_size: u32,
+    qr_error_correction: EcLevel,
+    qr_data: Vec<u8>,
+    /// If the user-defined character set is selected
+    user_defined: bool,
+}
+
+impl State {
+    /// The settings after power on, on a printer with the given dot density
+    fn new(dpi: u32) -> Self {
+        Self {
+            font: Font::A,
+            emphasized: false,
+            double_strike: false,
+            underline: 0,
+            width: 1,
+            height: 1,
+            reverse: false,
+            upside_down: false,
+            justification: Justification::Left,
+            line_spacing: 30,
+            left_margin: 0,
+            print_width: None,
+            character_spacing: 0,
+            // Every 8 characters of font A
+            tab_stops: (1..=32).map(|n| n * 8 * font::cell(Font::A).0).collect(),
+            horizontal_unit: dpi,
+            vertical_unit: dpi,
+            barcode_height: 162,
+            barcode_width: 3,
+            hri_position: 0,
+            hri_font: Font::A,
+            qr_module_size: 3,
+            qr_error_correction: EcLevel::L,
+            qr_data: Vec::new(),
+            user_defined: false,
+        }
+    }
+}
+
+/// An emulated printer which interprets ESC/POS commands onto a paper roll
+///
+/// Commands are printed the way a standard mode printer would, buffering a
+/// line at a time so justification and mixed character sizes line up.
+/// Commands that have no visual effect are ignored.
+#[derive(Debug, Clone)]
+pub struct VirtualPrinter {
+    receipt: Receipt,
+    /// Dot density of the print head, in dots per inch
+    dpi: u32,
+    state: State,
+    /// Top of the current print line
+    y: u32,
+    /// Contents of the line buffer, with their horizontal position
+    line: Vec<(u32, Block)>,
+    /// Horizontal print position within the line buffer
+    x: u32,
+    /// The page buffer, while in page mode
+    page: Option<Page>,
+    /// User-defined characters by font and character code
+    characters: HashMap<(Font, u8), Block>,
+    stored_macro: Macro,
+}
+
+/// Render an ESC/POS stream onto a roll of the given width
+pub fn render(data: &[u8], paper_width: u32) -> Receipt {
+    let mut printer = VirtualPrinter::new(paper_width);
+    printer.write(data);
+    printer.finish()
+}
+
+impl VirtualPrinter {
+    /// Create a virtual printer with a roll of the given width, in dots
+    pub fn new(paper_width: u32) -> Self {
+        Self::with_dpi(paper_width, DPI)
+    }
+
+    /// Create a virtual printer with a roll of the given width, in dots, and
+    /// a print head of the given dot density
+    pub fn with_dpi(paper_width: u32, dpi: u32) -> Self {
+        Self {
+            receipt: Receipt::new(paper_width),
+            dpi,
+            state: State::new(dpi),
+            y: 0,
+            line: Vec::new(),
+            x: 0,
+            page: None,
+            characters: HashMap::new(),
+            stored_macro: Macro::default(),
+        }
+    }
+
+    /// Create a virtual printer with the paper width and dot density of a
+    /// printer model
+    pub fn for_profile(profile: &Profile) -> Self {
+        Self::with_dpi(profile.paper_width, profile.dpi)
+    }
+
+    /// The paper printed so far, not including the line buffer
+    pub fn receipt(&self) -> &Receipt {
+        &self.receipt
+    }
+
+    /// Print any data left in the line buffer and tear off the receipt
+    pub fn finish(mut self) -> Receipt {
+        if !self.line.is_empty() {
+            self.print_line();
+        }
+
+        self.receipt
+    }
+
+    /// Interpret a chunk of an ESC/POS stream
+    pub fn write(&mut self, data: &[u8]) {
+        let mut decoder = decode::decode(data);
+        let mut start = 0;
+
+        while let Some(command) = decoder.next() {
+            let bytes = &data[start..decoder.position()];
+            start = decoder.position();
+
+            if !self.stored_macro.record(&command, bytes) {
+                self.execute(command);
+            }
+        }
+    }
+
+    /// Interpret a single decoded command
+    pub fn execute(&mut self, command: Command) {
+        let state = &mut self.state;
+
+        match command {
+            Command::Text(text) => {
+                for &c in text {
+                    self.character(c);
+                }
+            }
+            Command::LineFeed => self.print_line(),
+            Command::FormFeed => {
+                self.print_line();
+
+                // In page mode, prints the page and returns to standard mode
+                if self.page.is_some() {
+                    self.print_page();
+                    self.page = None;
+                }
+            }
+            Command::PageMode | Command::DoubleDensityPageMode if self.page.is_none() => {
+                self.line.clear();
+                self.x = 0;
+                self.page = Some(Page::new(self.receipt.width));
+            }
+            Command::StandardMode => {
+                self.page = None;
+                self.line.clear();
+                self.x = 0;
+            }
+            Command::PageArea {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                let area = (
+                    self.horizontal(x),
+                    self.vertical_u16(y),
+                    self.horizontal(width),
+                    self.vertical_u16(height),
+                );
+
+                if let Some(page) = &mut self.page {
+                    page.area = area;
+                    page.y = 0;
+                }
+            }
+            Command::PrintDirection(n) => {
+                if let Some(page) = &mut self.page {
+                    page.direction = digit(n) & 0x03;
+                    page.y = 0;
+                }
+            }
+            Command::AbsoluteVerticalPosition(n) => {
+                let dots = self.vertical_u16(n);
+
+                if let Some(page) = &mut self.page {
+                    page.y = dots;
+                }
+            }
+            Command::RelativeVerticalPosition(n) => {
+                let dots = self.vertical_u16(n.unsigned_abs()) as i64 * n.signum() as i64;
+
+                if let Some(page) = &mut self.page {
+                    page.y = (page.y as i64 + dots).max(0) as u32;
+                }
+            }
+            Command::PrintPage => self.print_page(),
+            Command::CancelPage => {
+                if let Some(page) = &mut self.page {
+                    page.cancel();
+                }
+            }
+            Command::HorizontalTab => {
+                let x = self.x;
+
+                // Tabs past the last tab stop or the print area are ignored
+                if let Some(&stop) = self.state.tab_stops.iter().find(|&&stop| stop > x) {
+                    if stop < self.print_area() {
+                        self.x = stop;
+                    }
+                }
+            }
+            Command::TabStops(stops) => {
+                let width = self.character_size().0 + self.state.character_spacing;
+
+                self.state.tab_stops = stops.iter().take(32).map(|&n| n as u32 * width).collect();
+            }
+            Command::Initialize => {
+                self.line.clear();
+                self.x = 0;
+                self.page = None;
+                self.state = State::new(self.dpi);
+                self.characters.clear();
+            }
+            Command::DefineCharacters {
+                rows,
+                first,
+                last,
+                data,
+            } => self.define_characters(rows as u32, first..=last, data),
+            Command::UserDefinedCharacters(on) => state.user_defined = on,
+            Command::ExecuteMacro { times, .. } => {
+                let commands = self.stored_macro.commands();
+
+                for _ in 0..times {
+                    self.write(&commands);
+                }
+            }
+            Command::CancelCharacter(code) => {
+                self.characters.remove(&(state.font, code));
+            }
+            Command::Justify(justification) => state.justification = justification,
+            Command::Emphasized(on) => state.emphasized = on,
+            Command::DoubleStrike(on) => state.double_strike = on,
+            Command::Underline(n) => state.underline = digit(n).min(2),
+            Command::Font(font) => state.font = font,
+            Command::PrintMode(n) => {
+                state.font = if n & 0x01 != 0 { Font::B } else { Font::A };
+                state.emphasized = n & 0x08 != 0;
+                state.height = if n & 0x10 != 0 { 2 } else { 1 };
+                state.width = if n & 0x20 != 0 { 2 } else { 1 };
+                state.underline = if n & 0x80 != 0 { 1 } else { 0 };
+            }
+            Command::UpsideDown(on) => state.upside_down = on,
+            Command::Reverse(on) => state.reverse = on,
+            Command::CharacterSize { width, height } => {
+                state.width = width as u32;
+                state.height = height as u32;
+            }
+            Command::LineSpacing(spacing) => {
+                self.state.line_spacing = match spacing {
+                    Some(n) => self.vertical(n),
+                    None => State::new(self.dpi).line_spacing,
+                }
+            }
+            Command::FeedLines(lines) => {
+                self.print_line();
+                self.y += self.state.line_spacing * lines.saturating_sub(1) as u32;
+            }
+            Command::FeedUnits(n) => {
+                self.flush_line(0);
+                self.y += self.vertical(n);
+            }
+            Command::ReverseFeed(n) => {
+                self.flush_line(0);
+                self.y = self.y.saturating_sub(self.vertical(n));
+            }
+            Command::MotionUnits {
+                horizontal,
+                vertical,
+            } => {
+                let dpi = self.dpi;
+                let unit = |n: u8| if n == 0 { dpi } else { n as u32 };
+
+                state.horizontal_unit = unit(horizontal);
+                state.vertical_unit = unit(vertical);
+            }
+            Command::LeftMargin(n) => self.state.left_margin = self.horizontal(n),
+            Command::PrintWidth(n) => self.state.print_width = Some(self.horizontal(n)),
+            Command::AbsolutePosition(n) => self.x = self.horizontal(n),
+            Command::RelativePosition(n) => {
+                let dots = self.horizontal(n.unsigned_abs()) as i64 * n.signum() as i64;
+
+                self.x = (self.x as i64 + dots).max(0) as u32;
+            }
+            Command::CharacterSpacing(n) => {
+                self.state.character_spacing = self.horizontal(n as u16)
+            }
+            Command::Cut { mode, feed } => {
+                self.flush_line(0);
+                self.y += self.vertical(feed.unwrap_or(0));
+                self.receipt.grow(self.y);
+                self.receipt.cuts.push(Cut {
+                    position: self.y,
+                    partial: matches!(mode, 1 | 49 | 66 | 98 | 104),
+                });
+            }
+            Command::CarriageReset {
+                cancel_print_buffer,
+            } => {
+                if cancel_print_buffer {
+                    self.line.clear();
+                    self.x = 0;
+                } else {
+                    self.flush_line(0);
+                }
+            }
+            Command::HriPosition(n) => state.hri_position = digit(n),
+            Command::HriFont(n) => state.hri_font = if digit(n) == 1 { Font::B } else { Font::A },
+            Command::BarcodeHeight(n) => state.barcode_height = (n as u32).max(1),
+            Command::BarcodeWidth(n) => state.barcode_width = (n as u32).clamp(1, 6),
+            Command::Barcode { system, data } => self.barcode(system, data),
+            Command::RasterImage {
+                mode,
+                width_bytes,
+                height,
+                data,
+            } => {
+                // Hand-built commands can declare more data than they have
+                if data.len() < width_bytes as usize * height as usize {
+                    return;
+                }
+
+                let mut block = Block::new(width_bytes as u32 * 8, height as u32);
+
+                for y in 0..block.height {
+                    for x in 0..block.width {
+                        let byte = data[(y * width_bytes as u32 + x / 8) as usize];
+
+                        if byte >> (7 - x % 8) & 1 == 1 {
+                            block.set(x, y);
+                        }
+                    }
+                }
+
+                let (scale_x, scale_y) = match digit(mode) {
+                    1 => (2, 1),
+                    2 => (1, 2),
+                    3 => (2, 2),
+                    _ => (1, 1),
+                };
+
+                self.print_block(block.scale(scale_x, scale_y));
+            }
+            Command::BitImage { mode, width, data } => {
+                let dots = if mode >= 32 { 24 } else { 8 };
+
+                if data.len() < (width as u32 * dots / 8) as usize {
+                    return;
+                }
+
+                let mut block = Block::new(width as u32, dots);
+
+                for x in 0..block.width {
+                    for y in 0..dots {
+                        let byte = data[(x * dots / 8 + y / 8) as usize];
+
+                        if byte >> (7 - y % 8) & 1 == 1 {
+                            block.set(x, y);
+                        }
+                    }
+                }
+
+                let scale_x = if mode == 0 || mode == 32 { 2 } else { 1 };
+                let scale_y = if mode < 32 { 3 } else { 1 };
+
+                self.push(block.scale(scale_x, scale_y));
+            }
+            Command::Extended {
+                prefix: 0x1D,
+                class: b'k',
+                data,
+            } => self.qr_code(data),
+            _ => {}
+        }
+    }
+
+    /// Convert horizontal motion units to dots
+    fn horizontal(&self, n: u16) -> u32 {
+        n as u32 * self.dpi / self.state.horizontal_unit
+    }
+
+    /// Convert vertical motion units to dots
+    fn vertical(&self, n: u8) -> u32 {
+        self.vertical_u16(n as u16)
+    }
+
+    fn vertical_u16(&self, n: u16) -> u32 {
+        n as u32 * self.dpi / self.state.vertical_unit
+    }
+
+    /// Size of a character cell with the current character size
+    fn character_size(&self) -> (u32, u32) {
+        let (width, height) = font::cell(self.state.font);
+
+        (width * self.state.width, height * self.state.height)
+    }
+
+    /// Width of the printable area of a line
+    fn print_area(&self) -> u32 {
+        if let Some(page) = &self.page {
+            return page.size().0;
+        }
+
+        let available = self.receipt.width.saturating_sub(self.state.left_margin);
+
+        self.state
+            .print_width
+            .map_or(available, |width| width.min(available))
+    }
+
+    /// Store user-defined characters for the selected font, from columns of
+    /// `rows` bytes each
+    fn define_characters(&mut self, rows: u32, codes: RangeInclusive<u8>, mut data: &[u8]) {
+        let (width, height) = font::cell(self.state.font);
+
+        for code in codes {
+            let Some((&columns, rest)) = data.split_first() else {
+                return;
+            };
+            let len = (columns as u32 * rows) as usize;
+            let (bitmap, rest) = rest.split_at(len.min(rest.len()));
+
+            let mut block = Block::new(width, height);
+            for (i, &byte) in bitmap.iter().enumerate() {
+                let (x, row) = (i as u32 / rows, i as u32 % rows);
+
+                for bit in (0..8).filter(|bit| byte & 0x80 >> bit != 0) {
+                    block.set(x, row * 8 + bit);
+                }
+            }
+
+            self.characters.insert((self.state.font, code), block);
+            data = rest;
+        }
+    }
+
+    fn character(&mut self, c: u8) {
+        let state = &self.state;
+        let mut glyph = match self.characters.get(&(state.font, c)) {
+            Some(glyph) if state.user_defined => glyph.clone(),
+            _ => font::render(c, state.font),
+        };
+
+        if state.emphasized || state.double_strike {
+            glyph.embolden();
+        }
+
+        if state.character_spacing > 0 {
+            let mut spaced = Block::new(glyph.width + state.character_spacing, glyph.height);
+            spaced.blit(&glyph, 0, 0);
+            glyph = spaced;
+        }
+
+        let mut glyph = glyph.scale(state.width, state.height);
+
+        if state.underline > 0 {
+            for y in glyph.height - state.underline as u32..glyph.height {
+                for x in 0..glyph.width {
+                    glyph.set(x, y);
+                }
+            }
+        }
+
+        if state.reverse {
+            glyph.invert();
+        }
+
+        if state.upside_down {
+            glyph.rotate_180();
+        }
+
+        self.push(glyph);
+    }
+
+    /// Add a block to the line buffer, wrapping onto the next line if needed
+    fn push(&mut self, block: Block) {
+        if self.x + block.width > self.print_area() && !self.line.is_empty() {
+            self.print_line();
+        }
+
+        let width = block.width;
+        self.line.push((self.x, block));
+        self.x += width;
+    }
+
+    /// Print a block on a line of its own, as barcodes and images are
+    fn print_block(&mut self, block: Block) {
+        if !self.line.is_empty() {
+            self.print_line();
+        }
+
+        self.line.push((0, block));
+        self.flush_line(0);
+    }
+
+    /// Print the line buffer and feed one line
+    fn print_line(&mut self) {
+        self.flush_line(self.state.line_spacing);
+    }
+
+    /// Print the line buffer, feeding at least `min_height` dots
+    fn flush_line(&mut self, min_height: u32) {
+        let extent = self
+            .line
+            .iter()
+            .map(|(x, block)| x + block.width)
+            .max()
+            .unwrap_or(0);
+        let height = self
+            .line
+            .iter()
+            .map(|(_, block)| block.height)
+            .max()
+            .unwrap_or(0)
+            .max(min_height);
+
+        if let Some(page) = &mut self.page {
+            // Justification and the left margin only apply in standard mode
+            for (x, block) in self.line.drain(..) {
+                let top = page.y + height - block.height;
+
+                for by in 0..block.height {
+                    for bx in 0..block.width {
+                        if block.get(bx, by) {
+                            page.set(x + bx, top + by);
+                        }
+                    }
+                }
+            }
+
+            page.y += height;
+            self.x = 0;
+
+            return;
+        }
+
+        let free = self.print_area().saturating_sub(extent);
+        let offset = self.state.left_margin
+            + match self.state.justification {
+                Justification::Left => 0,
+                Justification::Center => free / 2,
+                Justification::Right => free,
+            };
+
+        for (x, block) in self.line.drain(..) {
+            // Characters of different heights share a baseline at the bottom of the line
+            let top = self.y + height - block.height;
+
+            for by in 0..block.height {
+                for bx in 0..block.width {
+                    if block.get(bx, by) {
+                        self.receipt.set(offset + x + bx, top + by);
+                    }
+                }
+            }
+        }
+
+        self.y += height;
+        self.receipt.grow(self.y);
+        self.x = 0;
+    }
+
+    /// Print the page buffer onto the paper, keeping the buffered data
+    fn print_page(&mut self) {
+        let page = match &self.page {
+            Some(page) => page,
+            None => return,
+        };
+
+        for y in 0..page.length.min(page.buffer.height) {
+            for x in 0..page.buffer.width {
+                if page.buffer.pixel(x, y) {
+                    self.receipt.set(x, self.y + y);
+                }
+            }
+        }
+
+        self.y += page.length;
+        self.receipt.grow(self.y);
+    }
+
+    fn text_block(&self, text: &[u8], font: Font) -> Block {
+        let (width, height) = font::cell(font);
+        let mut block = Block::new(width * text.len() as u32, height);
+
+        for (i, &c) in text.iter().enumerate() {
+            block.blit(&font::render(c, font), i as u32 * width, 0);
+        }
+
+        block
+    }
+
+    fn barcode(&mut self, system: u8, data: &[u8]) {
+        let state = &self.state;
+
+        // The printer ignores data it cannot encode, and so does the emulator
+        let symbol = match barcode::encode(system, data) {
+            Some(symbol) => symbol,
+            None => return,
+        };
+
+        let module = state.barcode_width;
+        let mut bars = Block::new(symbol.modules.len() as u32 * module, state.barcode_height);
+        for (i, _) in symbol.modules.iter().enumerate().filter(|(_, &bar)| bar) {
+            for x in 0..module {
+                for y in 0..bars.height {
+                    bars.set(i as u32 * module + x, y);
+                }
+            }
+        }
+
+        let hri = self.text_block(&symbol.hri, state.hri_font);
+        let (above, below) = match state.hri_position {
+            1 => (true, false),
+            2 => (false, true),
+            3 => (true, true),
+            _ => (false, false),
+        };
+
+        let width = bars.width.max(hri.width);
+        let height = bars.height + hri.height * (above as u32 + below as u32);
+        let hri_left = (width - hri.width) / 2;
+        let mut block = Block::new(width, height);
+        let mut top = 0;
+
+        if above {
+            block.blit(&hri, hri_left, top);
+            top += hri.height;
+        }
+        block.blit(&bars, (width - bars.width) / 2, top);
+        top += bars.height;
+        if below {
+            block.blit(&hri, hri_left, top);
+        }
+
+        self.print_block(block);
+    }
+
+    /// GS ( k, QR code functions
+    fn qr_code(&mut self, data: &[u8]) {
+        let state = &mut self.state;
+
+        // cn = 49 selects QR code, other symbologies are not emulated
+        match data {
+            [49, 67, n, ..] => state.qr_module_size = (*n as u32).clamp(1, 16),
+            [49, 69, n, ..] => {
+                state.qr_error_correction = match n {
+                    49 => EcLevel::M,
+                    50 => EcLevel::Q,
+                    51 => EcLevel::H,
+                    _ => EcLevel::L,
+                }
+            }
+            [49, 80, _, payload @ ..] => state.qr_data = payload.to_vec(),
+            [49, 81, ..] => {
+                let code = match QrCode::with_error_correction_level(
+                    &state.qr_data,
+                    state.qr_error_correction,
+                ) {
+                    Ok(code) => code,
+                    Err(_) => return,
+                };
+
+                let size = code.width() as u32;
+                let colors = code.to_colors();
+                let mut block = Block::new(size, size);
+
+                for y in 0..size {
+                    for x in 0..size {
+                        if colors[(y * size + x) as usize] == qrcode::Color::Dark {
+                            block.set(x, y);
+                        }
+                    }
+                }
+
+                let module = state.qr_module_size;
+                self.print_block(block.scale(module, module));
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf};
+
+    use super::*;
+    use crate::{
+        glyph::GlyphSet, BarcodeSystem, CutMode, CutType, Document, Image, PrintDirection, Printer,
+        QrCorrection, Underline,
+    };
+
+    /// Compare a receipt to `tests/golden/<name>.png`
+    ///
+    /// Run with `UPDATE_GOLDEN=1` to write the golden image instead, after
+    /// checking the new output by eye.
+    fn assert_golden(name: &str, receipt: &Receipt) {
+        let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "golden", name]
+            .iter()
+            .collect::<PathBuf>()
+            .with_extension("png");
+        let png = receipt.to_png().unwrap();
+
+        if env::var_os("UPDATE_GOLDEN").is_some() {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, png).unwrap();
+            return;
+        }
+
+        let golden = fs::read(&path)
+            .unwrap_or_else(|err| panic!("missing golden image {}: {}", path.display(), err));
+
+        assert_eq!(decode_png(&png), decode_png(&golden), "{} differs", name);
+    }
+
+    /// Width, height and grayscale pixels of a PNG
+    fn decode_png(png: &[u8]) -> (u32, u32, Vec<u8>) {
+        let mut reader = png::Decoder::new(png).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        pixels.truncate(info.buffer_size());
+
+        (info.width, info.height, pixels)
+    }
+
+    fn render_with<F: FnOnce(&Printer<Document>)>(profile: &Profile, print: F) -> Receipt {
+        let printer = Printer::with_profile(Document::new(), profile.clone());
+        print(&printer);
+
+        let mut virtual_printer = VirtualPrinter::for_profile(profile);
+        virtual_printer.write(&printer.into_device().into_bytes());
+        virtual_printer.finish()
+    }
+
+    fn checkerboard(size: u32) -> Image {
+        Image::from_fn(size, size, |x, y| (x / 4 + y / 4) % 2 == 0)
+    }
+
+    #[test]
+    fn text_styles() {
+        let receipt = render_with(&Profile::GENERIC, |printer| {
+            printer.println("Plain text").unwrap();
+            printer.emphasized(true).unwrap();
+            printer.println("Emphasized").unwrap();
+            printer.emphasized(false).unwrap();
+            printer.underline(Underline::Double).unwrap();
+            printer.println("Underlined").unwrap();
+            printer.underline(Underline::None).unwrap();
+            printer.font(Font::B).unwrap();
+            printer.println("Font B").unwrap();
+            printer.font(Font::A).unwrap();
+            printer.justify(Justification::Center).unwrap();
+            printer.character_size(2, 2).unwrap();
+            printer.println("Big").unwrap();
+            printer.character_size(1, 1).unwrap();
+            printer.justify(Justification::Right).unwrap();
+            printer.println("Right").unwrap();
+            printer.cut(CutMode::Feed(CutType::Partial, 10)).unwrap();
+        });
+
+        assert_eq!(receipt.cuts().len(), 1);
+        assert!(receipt.cuts()[0].partial);
+        assert_golden("text_styles", &receipt);
+    }
+
+    #[test]
+    fn images() {
+        let receipt = render_with(&Profile::GENERIC, |printer| {
+            printer.image(&checkerboard(64)).unwrap();
+            // An 8 dot single density bit image
+            printer
+                .raw([ESC, b'*', 1, 4, 0, 0xFF, 0x81, 0x81, 0xFF])
+                .unwrap();
+            printer.println("").unwrap();
+        });
+
+        assert_golden("images", &receipt);
+    }
+
+    #[test]
+    fn codes() {
+        let receipt = render_with(&Profile::GENERIC, |printer| {
+            printer
+                .barcode(BarcodeSystem::Ean13, b"401234567890")
+                .unwrap();
+            printer.qr_code("thermal", 4, QrCorrection::M).unwrap();
+        });
+
+        assert_golden("codes", &receipt);
+    }
+
+    #[test]
+    fn page_mode() {
+        let receipt = render_with(&Profile::GENERIC, |printer| {
+            printer.page_mode().unwrap();
+            printer.page_area(0, 0, 200, 120).unwrap();
+            printer.println("Across").unwrap();
+            printer
+                .print_direction(PrintDirection::BottomToTop)
+                .unwrap();
+            printer.println("Up").unwrap();
+            printer.print_page().unwrap();
+            printer.standard_mode().unwrap();
+        });
+
+        assert_golden("page_mode", &receipt);
+    }
+
+    #[test]
+    fn user_defined_characters() {
+        let receipt = render_with(&Profile::GENERIC, |printer| {
+            let mut glyphs = GlyphSet::new(Font::A);
+            glyphs.insert('★', checkerboard(12));
+            printer.define_glyphs(&glyphs).unwrap();
+            printer.println("A ★ B").unwrap();
+        });
+
+        assert_golden("user_defined_characters", &receipt);
+    }
+
+    #[test]
+    fn profile_dpi() {
+        // Feed one inch, in 1/90 inch units, and cut there
+        let data = [GS, b'P', 90, 90, ESC, b'J', 90, GS, b'V', 0];
+
+        let mut tm_t88 = VirtualPrinter::for_profile(&Profile::EPSON_TM_T88);
+        tm_t88.write(&data);
+        assert_eq!(tm_t88.finish().height(), 180);
+
+        let mut generic = VirtualPrinter::for_profile(&Profile::GENERIC);
+        generic.write(&data);
+        assert_eq!(generic.finish().height(), 203);
+    }
+
+    #[test]
+    fn short_image_data_is_skipped() {
+        let mut printer = VirtualPrinter::new(PAPER_WIDTH_80MM);
+
+        printer.execute(Command::RasterImage {
+            mode: 0,
+            width_bytes: 4,
+            height: 4,
+            data: &[0xFF; 3],
+        });
+        printer.execute(Command::BitImage {
+            mode: 33,
+            width: 8,
+            data: &[0xFF; 5],
+        });
+
+        assert_eq!(printer.finish().height(), 0);
+    }
+
+    const ESC: u8 = 0x1B;
+    const GS: u8 = 0x1D;
+}
@@ -0,0 +1,371 @@
+/// Left-hand odd parity (L) patterns for EAN/UPC digits. Right-hand (R)
+/// patterns are the complement and even parity (G) patterns are R reversed
+const EAN_L: [&str; 10] = [
+    "0001101", "0011001", "0010011", "0111101", "0100011", "0110001", "0101111", "0111011",
+    "0110111", "0001011",
+];
+
+/// Parity of the left half of an EAN-13, selected by its first digit
+const EAN_PARITY: [&str; 10] = [
+    "LLLLLL", "LLGLGG", "LLGGLG", "LLGGGL", "LGLLGG", "LGGLLG", "LGGGLL", "LGLGLG", "LGLGGL",
+    "LGGLGL",
+];
+
+/// CODE39 characters and their bar/space patterns (n = narrow, w = wide)
+const CODE39: [(u8, &str); 44] = [
+    (b'0', "nnnwwnwnn"),
+    (b'1', "wnnwnnnnw"),
+    (b'2', "nnwwnnnnw"),
+    (b'3', "wnwwnnnnn"),
+    (b'4', "nnnwwnnnw"),
+    (b'5', "wnnwwnnnn"),
+    (b'6', "nnwwwnnnn"),
+    (b'7', "nnnwnnwnw"),
+    (b'8', "wnnwnnwnn"),
+    (b'9', "nnwwnnwnn"),
+    (b'A', "wnnnnwnnw"),
+    (b'B', "nnwnnwnnw"),
+    (b'C', "wnwnnwnnn"),
+    (b'D', "nnnnwwnnw"),
+    (b'E', "wnnnwwnnn"),
+    (b'F', "nnwnwwnnn"),
+    (b'G', "nnnnnwwnw"),
+    (b'H', "wnnnnwwnn"),
+    (b'I', "nnwnnwwnn"),
+    (b'J', "nnnnwwwnn"),
+    (b'K', "wnnnnnnww"),
+    (b'L', "nnwnnnnww"),
+    (b'M', "wnwnnnnwn"),
+    (b'N', "nnnnwnnww"),
+    (b'O', "wnnnwnnwn"),
+    (b'P', "nnwnwnnwn"),
+    (b'Q', "nnnnnnwww"),
+    (b'R', "wnnnnnwwn"),
+    (b'S', "nnwnnnwwn"),
+    (b'T', "nnnnwnwwn"),
+    (b'U', "wwnnnnnnw"),
+    (b'V', "nwwnnnnnw"),
+    (b'W', "wwwnnnnnn"),
+    (b'X', "nwnnwnnnw"),
+    (b'Y', "wwnnwnnnn"),
+    (b'Z', "nwwnwnnnn"),
+    (b'-', "nwnnnnwnw"),
+    (b'.', "wwnnnnwnn"),
+    (b' ', "nwwnnnwnn"),
+    (b'*', "nwnnwnwnn"),
+    (b'$', "nwnwnwnnn"),
+    (b'/', "nwnwnnnwn"),
+    (b'+', "nwnnnwnwn"),
+    (b'%', "nnnwnwnwn"),
+];
+
+/// Interleaved 2 of 5 digit patterns
+const ITF: [&str; 10] = [
+    "nnwwn", "wnnnw", "nwnnw", "wwnnn", "nnwnw", "wnwnn", "nwwnn", "nnnww", "wnnwn", "nwnwn",
+];
+
+/// CODE128 symbol patterns as bar/space widths, indexed by symbol value
+#[rustfmt::skip]
+const CODE128: [&str; 106] = [
+    "212222", "222122", "222221", "121223", "121322", "131222", "122213", "122312", "132212", "221213",
+    "221312", "231212", "112232", "122132", "122231", "113222", "123122", "123221", "223211", "221132",
+    "221231", "213212", "223112", "312131", "311222", "321122", "321221", "312212", "322112", "322211",
+    "212123", "212321", "232121", "111323", "131123", "131321", "112313", "132113", "132311", "211313",
+    "231113", "231311", "112133", "112331", "132131", "113123", "113321", "133121", "313121", "211331",
+    "231131", "213113", "213311", "213131", "311123", "311321", "331121", "312113", "312311", "332111",
+    "314111", "221411", "431111", "111224", "111422", "121124", "121421", "141122", "141221", "112214",
+    "112412", "122114", "122411", "142112", "142211", "241211", "221114", "413111", "241112", "134111",
+    "111242", "121142", "121241", "114212", "124112", "124211", "411212", "421112", "421211", "212141",
+    "214121", "412121", "111143", "111341", "131141", "114113", "114311", "411113", "411311", "113141",
+    "114131", "311141", "411131", "211412", "211214", "211232",
+];
+
+const CODE128_STOP: &str = "2331112";
+
+/// Width of a wide element relative to a narrow one
+const WIDE: usize = 3;
+
+/// A barcode symbol, as a row of modules (true = bar), along with its human
+/// readable interpretation
+pub(crate) struct Symbol {
+    pub modules: Vec<bool>,
+    pub hri: Vec<u8>,
+}
+
+/// Encode barcode data for the given GS k system
+///
+/// Returns `None` for unsupported systems or data the printer would reject.
+pub(crate) fn encode(system: u8, data: &[u8]) -> Option<Symbol> {
+    match system {
+        0 | 65 => upc_a(data),
+        2 | 67 => ean_13(data),
+        3 | 68 => ean_8(data),
+        4 | 69 => code39(data),
+        5 | 70 => itf(data),
+        73 => code128(data),
+        _ => None,
+    }
+}
+
+fn digits(data: &[u8]) -> Option<Vec<u8>> {
+    data.iter()
+        .map(|&c| {
+            if c.is_ascii_digit() {
+                Some(c - b'0')
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Modulo 10 check digit, weighting the rightmost digit by 3
+fn check_digit(digits: &[u8]) -> u8 {
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &d)| d as u32 * if i % 2 == 0 { 3 } else { 1 })
+        .sum();
+
+    ((10 - sum % 10) % 10) as u8
+}
+
+fn push_pattern(modules: &mut Vec<bool>, pattern: &str) {
+    modules.extend(pattern.bytes().map(|b| b == b'1'));
+}
+
+/// Alternating bars and spaces, starting with a bar, with the given widths
+fn push_widths(modules: &mut Vec<bool>, widths: impl Iterator<Item = usize>) {
+    for (i, width) in widths.enumerate() {
+        modules.extend(std::iter::repeat_n(i % 2 == 0, width));
+    }
+}
+
+fn narrow_wide(pattern: &str) -> impl Iterator<Item = usize> + '_ {
+    pattern.bytes().map(|b| if b == b'w' { WIDE } else { 1 })
+}
+
+fn with_check_digit(data: &[u8], len: usize) -> Option<Vec<u8>> {
+    let mut digits = digits(data)?;
+
+    if digits.len() == len - 1 {
+        digits.push(check_digit(&digits));
+    } else if digits.len() != len {
+        return None;
+    }
+
+    Some(digits)
+}
+
+fn ean_digit(modules: &mut Vec<bool>, digit: u8, parity: u8) {
+    let l = EAN_L[digit as usize];
+
+    match parity {
+        b'L' => push_pattern(modules, l),
+        b'R' => modules.extend(l.bytes().map(|b| b == b'0')),
+        _ => modules.extend(l.bytes().rev().map(|b| b == b'0')),
+    }
+}
+
+fn ean(digits: &[u8], parity: &str) -> Vec<bool> {
+    let half = digits.len() / 2;
+    let mut modules = Vec::new();
+
+    push_pattern(&mut modules, "101");
+    for (&digit, parity) in digits[..half].iter().zip(parity.bytes()) {
+        ean_digit(&mut modules, digit, parity);
+    }
+    push_pattern(&mut modules, "01010");
+    for &digit in &digits[half..] {
+        ean_digit(&mut modules, digit, b'R');
+    }
+    push_pattern(&mut modules, "101");
+
+    modules
+}
+
+fn hri(digits: &[u8]) -> Vec<u8> {
+    digits.iter().map(|d| d + b'0').collect()
+}
+
+fn upc_a(data: &[u8]) -> Option<Symbol> {
+    let digits = with_check_digit(data, 12)?;
+
+    Some(Symbol {
+        modules: ean(&digits, EAN_PARITY[0]),
+        hri: hri(&digits),
+    })
+}
+
+fn ean_13(data: &[u8]) -> Option<Symbol> {
+    let digits = with_check_digit(data, 13)?;
+
+    Some(Symbol {
+        modules: ean(&digits[1..], EAN_PARITY[digits[0] as usize]),
+        hri: hri(&digits),
+    })
+}
+
+fn ean_8(data: &[u8]) -> Option<Symbol> {
+    let digits = with_check_digit(data, 8)?;
+
+    Some(Symbol {
+        modules: ean(&digits, "LLLL"),
+        hri: hri(&digits),
+    })
+}
+
+fn code39(data: &[u8]) -> Option<Symbol> {
+    let pattern = |c: u8| CODE39.iter().find(|(k, _)| *k == c).map(|(_, p)| *p);
+
+    // The printer adds the start/stop characters if they are missing
+    let inner = data
+        .strip_prefix(b"*")
+        .and_then(|d| d.strip_suffix(b"*"))
+        .unwrap_or(data);
+
+    let mut modules = Vec::new();
+    for &c in std::iter::once(&b'*')
+        .chain(inner)
+        .chain(std::iter::once(&b'*'))
+    {
+        push_widths(&mut modules, narrow_wide(pattern(c)?));
+        // Inter-character gap
+        modules.push(false);
+    }
+    modules.pop();
+
+    let mut hri = vec![b'*'];
+    hri.extend_from_slice(inner);
+    hri.push(b'*');
+
+    Some(Symbol { modules, hri })
+}
+
+fn itf(data: &[u8]) -> Option<Symbol> {
+    let digits = digits(data)?;
+
+    if digits.is_empty() || digits.len() % 2 != 0 {
+        return None;
+    }
+
+    let mut modules = Vec::new();
+    push_widths(&mut modules, [1, 1, 1, 1].iter().copied());
+
+    for pair in digits.chunks(2) {
+        let bars = narrow_wide(ITF[pair[0] as usize]);
+        let spaces = narrow_wide(ITF[pair[1] as usize]);
+
+        push_widths(
+            &mut modules,
+            bars.zip(spaces)
+                .flat_map(|(b, s)| std::iter::once(b).chain(std::iter::once(s))),
+        );
+    }
+
+    push_widths(&mut modules, [WIDE, 1, 1].iter().copied());
+
+    Some(Symbol {
+        modules,
+        hri: hri(&digits),
+    })
+}
+
+/// CODE128 data as sent to the printer, where the code set is selected with
+/// `{A`, `{B` or `{C` and a literal `{` is `{{`
+fn code128(data: &[u8]) -> Option<Symbol> {
+    let mut symbols = Vec::new();
+    let mut hri = Vec::new();
+    let mut set = None;
+    let mut i = 0;
+
+    while i < data.len() {
+        if data[i] == b'{' {
+            let selector = *data.get(i + 1)?;
+            i += 2;
+
+            match selector {
+                b'A' | b'B' | b'C' => {
+                    let start = 103 + (selector - b'A') as usize;
+                    // Code set changes use CODE A/B/C (101/100/99) after the start
+                    symbols.push(match (set, selector) {
+                        (None, _) => start,
+                        (_, b'A') => 101,
+                        (_, b'B') => 100,
+                        _ => 99,
+                    });
+                    set = Some(selector);
+                }
+                b'{' => {
+                    symbols.push((b'{' - 32) as usize);
+                    hri.push(b'{');
+                }
+                // FNC1-4 and SHIFT are encoded but not shown
+                b'1' => symbols.push(102),
+                b'S' => symbols.push(98),
+                _ => {}
+            }
+
+            continue;
+        }
+
+        let c = data[i];
+        i += 1;
+
+        match set? {
+            b'C' => {
+                let tens = c.checked_sub(b'0').filter(|d| *d <= 9);
+                let value = match tens {
+                    // Printers accept either digit pairs or raw values in code set C
+                    Some(tens) => {
+                        let ones = data.get(i)?.checked_sub(b'0').filter(|d| *d <= 9)?;
+                        i += 1;
+                        (tens * 10 + ones) as usize
+                    }
+                    None => c as usize,
+                };
+
+                if value > 99 {
+                    return None;
+                }
+
+                symbols.push(value);
+                hri.extend_from_slice(format!("{:02}", value).as_bytes());
+            }
+            b'A' if c < 32 => {
+                symbols.push(c as usize + 64);
+            }
+            _ => {
+                if !(32..=127).contains(&c) {
+                    return None;
+                }
+
+                symbols.push((c - 32) as usize);
+                hri.push(c);
+            }
+        }
+    }
+
+    let checksum = symbols
+        .iter()
+        .enumerate()
+        .map(|(i, &s)| s * i.max(1))
+        .sum::<usize>()
+        % 103;
+    symbols.push(checksum);
+
+    let mut modules = Vec::new();
+    for symbol in symbols {
+        push_widths(
+            &mut modules,
+            CODE128[symbol].bytes().map(|b| (b - b'0') as usize),
+        );
+    }
+    push_widths(
+        &mut modules,
+        CODE128_STOP.bytes().map(|b| (b - b'0') as usize),
+    );
+
+    Some(Symbol { modules, hri })
+}
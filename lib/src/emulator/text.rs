@@ -0,0 +1,498 @@
+//! A lightweight text renderer for ESC/POS streams
+//!
+//! Prints the receipt as plain text, keeping the justification and column
+//! width of the printer. Barcodes, QR codes, images, cuts and user-defined
+//! characters show up as placeholders, and styles can be shown with ANSI
+//! escapes for terminals.
+
+use std::collections::HashSet;
+
+use codepage_437::CP437_CONTROL;
+
+use super::Macro;
+use crate::{
+    decode::{self, digit, Command},
+    Document, Justification,
+};
+
+/// Width of a column in dots, the width of a font A character
+const COLUMN_WIDTH: usize = 12;
+
+const BOLD: &str = "\x1b[1m";
+const UNDERLINE: &str = "\x1b[4m";
+const REVERSE: &str = "\x1b[7m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Style {
+    bold: bool,
+    underline: bool,
+    reverse: bool,
+}
+
+impl Style {
+    fn escape(self) -> String {
+        let mut escape = String::from(RESET);
+
+        if self.bold {
+            escape.push_str(BOLD);
+        }
+        if self.underline {
+            escape.push_str(UNDERLINE);
+        }
+        if self.reverse {
+            escape.push_str(REVERSE);
+        }
+
+        escape
+    }
+}
+
+/// Settings reset by ESC @
+#[derive(Debug, Clone)]
+struct State {
+    style: Style,
+    emphasized: bool,
+    double_strike: bool,
+    width: usize,
+    justification: Justification,
+    /// Left margin and print area width in columns
+    left_margin: usize,
+    print_width: Option<usize>,
+    /// Horizontal tab positions in columns
+    tab_stops: Vec<usize>,
+    qr_data: String,
+    /// If the user-defined character set is selected
+    user_defined: bool,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            style: Style::default(),
+            emphasized: false,
+            double_strike: false,
+            width: 1,
+            justification: Justification::Left,
+            left_margin: 0,
+            print_width: None,
+            tab_stops: (1..=32).map(|n| n * 8).collect(),
+            qr_data: String::new(),
+            user_defined: false,
+        }
+    }
+}
+
+/// An emulated printer that prints onto a string
+#[derive(Debug, Clone)]
+pub struct TextPrinter {
+    columns: usize,
+    ansi: bool,
+    output: String,
+    state: State,
+    line: Vec<(char, Style)>,
+    /// Codes with a user-defined character
+    characters: HashSet<u8>,
+    stored_macro: Macro,
+}
+
+/// Render an ESC/POS stream as text, `columns` characters wide
+///
+/// Styles are shown with ANSI escapes if `ansi` is set.
+pub fn render(data: &[u8], columns: usize, ansi: bool) -> String {
+    let mut printer = TextPrinter::new(columns).ansi(ansi);
+    printer.write(data);
+    printer.finish()
+}
+
+/// Render the commands recorded in a document as text
+pub fn render_document(document: &Document, columns: usize, ansi: bool) -> String {
+    render(&document.to_vec(), columns, ansi)
+}
+
+impl TextPrinter {
+    /// Create a text printer with lines of the given amount of characters
+    pub fn new(columns: usize) -> Self {
+        Self {
+            columns: columns.max(1),
+            ansi: false,
+            output: String::new(),
+            state: State::default(),
+            line: Vec::new(),
+            characters: HashSet::new(),
+            stored_macro: Macro::default(),
+        }
+    }
+
+    /// Show bold, underlined and reversed text with ANSI escapes
+    pub fn ansi(mut self, ansi: bool) -> Self {
+        self.ansi = ansi;
+        self
+    }
+
+    /// Print any data left in the line buffer and take the printed text
+    pub fn finish(mut self) -> String {
+        if !self.line.is_empty() {
+            self.print_line();
+        }
+
+        self.output
+    }
+
+    /// Interpret a chunk of an ESC/POS stream
+    pub fn write(&mut self, data: &[u8]) {
+        let mut decoder = decode::decode(data);
+        let mut start = 0;
+
+        while let Some(command) = decoder.next() {
+            let bytes = &data[start..decoder.position()];
+            start = decoder.position();
+
+            if !self.stored_macro.record(&command, bytes) {
+                self.execute(command);
+            }
+        }
+    }
+
+    /// Interpret a single decoded command
+    pub fn execute(&mut self, command: Command) {
+        let state = &mut self.state;
+
+        match command {
+            Command::Text(text) => {
+                for &c in text {
+                    match c {
+                        c if self.state.user_defined && self.characters.contains(&c) => {
+                            self.character('□')
+                        }
+                        c => self.character(CP437_CONTROL.decode(c)),
+                    }
+                }
+            }
+            Command::LineFeed | Command::FormFeed => self.print_line(),
+            Command::FeedUnits(_) | Command::ReverseFeed(_) if !self.line.is_empty() => {
+                self.print_line()
+            }
+            Command::FeedLines(lines) => {
+                self.print_line();
+                for _ in 1..lines {
+                    self.output.push('\n');
+                }
+            }
+            Command::HorizontalTab => {
+                let column = self.line.len();
+
+                if let Some(&stop) = state.tab_stops.iter().find(|&&stop| stop > column) {
+                    self.pad_to(stop);
+                }
+            }
+            Command::TabStops(stops) => {
+                let width = state.width;
+
+                state.tab_stops = stops.iter().take(32).map(|&n| n as usize * width).collect();
+            }
+            Command::Initialize => {
+                self.line.clear();
+                self.state = State::default();
+                self.characters.clear();
+            }
+            Command::DefineCharacters { first, last, .. } => self.characters.extend(first..=last),
+            Command::UserDefinedCharacters(on) => state.user_defined = on,
+            Command::ExecuteMacro { times, .. } => {
+                let commands = self.stored_macro.commands();
+
+                for _ in 0..times {
+                    self.write(&commands);
+                }
+            }
+            Command::CancelCharacter(code) => {
+                self.characters.remove(&code);
+            }
+            Command::Justify(justification) => state.justification = justification,
+            Command::Emphasized(on) => {
+                state.emphasized = on;
+                state.style.bold = state.emphasized || state.double_strike;
+            }
+            Command::DoubleStrike(on) => {
+                state.double_strike = on;
+                state.style.bold = state.emphasized || state.double_strike;
+            }
+            Command::Underline(n) => state.style.underline = digit(n) != 0,
+            Command::Reverse(on) => state.style.reverse = on,
+            Command::PrintMode(n) => {
+                state.emphasized = n & 0x08 != 0;
+                state.style.bold = state.emphasized || state.double_strike;
+                state.style.underline = n & 0x80 != 0;
+                state.width = if n & 0x20 != 0 { 2 } else { 1 };
+            }
+            Command::CharacterSize { width, .. } => state.width = width as usize,
+            Command::LeftMargin(dots) => state.left_margin = dots as usize / COLUMN_WIDTH,
+            Command::PrintWidth(dots) => state.print_width = Some(dots as usize / COLUMN_WIDTH),
+            Command::AbsolutePosition(dots) => self.pad_to(dots as usize / COLUMN_WIDTH),
+            Command::RelativePosition(dots) if dots > 0 => {
+                self.pad_to(self.line.len() + dots as usize / COLUMN_WIDTH)
+            }
+            Command::Cut { mode, .. } => {
+                let partial = matches!(mode, 1 | 49 | 66 | 98 | 104);
+
+                self.placeholder(if partial { "partial cut" } else { "cut" }, '-');
+            }
+            Command::Barcode { system, data } => {
+                let name = match system {
+                    0 | 65 => "UPC-A",
+                    1 | 66 => "UPC-E",
+                    2 | 67 => "EAN13",
+                    3 | 68 => "EAN8",
+                    4 | 69 => "CODE39",
+                    5 | 70 => "ITF",
+                    6 | 71 => "CODABAR",
+                    72 => "CODE93",
+                    73 => "CODE128",
+                    _ => "barcode",
+                };
+
+                self.block(&format!(
+                    "[{} {}]",
+                    name,
+                    data.iter()
+                        .map(|&c| CP437_CONTROL.decode(c))
+                        .collect::<String>()
+                ));
+            }
+            Command::Extended {
+                prefix: 0x1D,
+                class: b'k',
+                data: [49, 80, _, payload @ ..],
+            } => state.qr_data = String::from_utf8_lossy(payload).into_owned(),
+            Command::Extended {
+                prefix: 0x1D,
+                class: b'k',
+                data: [49, 81, ..],
+            } => {
+                let text = format!("[QR {}]", self.state.qr_data);
+                self.block(&text);
+            }
+            Command::RasterImage {
+                width_bytes,
+                height,
+                ..
+            } => self.block(&format!("[image {}x{}]", width_bytes as u32 * 8, height)),
+            Command::BitImage { width, .. } => self.block(&format!("[image {}]", width)),
+            _ => {}
+        }
+    }
+
+    /// Columns available between the left margin and the end of the print area
+    fn print_area(&self) -> usize {
+        let available = self.columns.saturating_sub(self.state.left_margin).max(1);
+
+        self.state
+            .print_width
+            .map_or(available, |width| width.clamp(1, available))
+    }
+
+    /// Move the print position to a column by padding with spaces
+    fn pad_to(&mut self, column: usize) {
+        while self.line.len() < column.min(self.print_area()) {
+            self.line.push((' ', Style::default()));
+        }
+    }
+
+    fn character(&mut self, c: char) {
+        let width = self.state.width;
+
+        if self.line.len() + width > self.print_area() && !self.line.is_empty() {
+            self.print_line();
+        }
+
+        let style = self.state.style;
+        self.line.push((c, style));
+        for _ in 1..width {
+            self.line.push((' ', style));
+        }
+    }
+
+    /// Print a placeholder for a barcode or image on a line of its own
+    fn block(&mut self, text: &str) {
+        if !self.line.is_empty() {
+            self.print_line();
+        }
+
+        let style = Style::default();
+        self.line = text
+            .chars()
+            .take(self.print_area())
+            .map(|c| (c, style))
+            .collect();
+        self.print_line();
+    }
+
+    /// Print a label centered in a line of filler characters
+    fn placeholder(&mut self, label: &str, filler: char) {
+        if !self.line.is_empty() {
+            self.print_line();
+        }
+
+        let label = format!(" {} ", label);
+        let side = self.columns.saturating_sub(label.chars().count());
+        let left = filler.to_string().repeat(side / 2);
+        let right = filler.to_string().repeat(side - side / 2);
+
+        self.output.push_str(&left);
+        self.output.push_str(&label);
+        self.output.push_str(&right);
+        self.output.push('\n');
+    }
+
+    fn print_line(&mut self) {
+        let free = self.print_area().saturating_sub(self.line.len());
+        let offset = self.state.left_margin
+            + match self.state.justification {
+                Justification::Left => 0,
+                Justification::Center => free / 2,
+                Justification::Right => free,
+            };
+
+        let mut line = " ".repeat(offset);
+        let mut current = Style::default();
+
+        for (c, style) in self.line.drain(..) {
+            if self.ansi && style != current {
+                line.push_str(&style.escape());
+                current = style;
+            }
+
+            line.push(c);
+        }
+
+        if self.ansi && current != Style::default() {
+            line.push_str(RESET);
+        }
+
+        self.output.push_str(line.trim_end_matches(' '));
+        self.output.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        error::MacroError, macros::MacroMode, BarcodeSystem, CutMode, CutType, Image, Printer,
+        QrCorrection, Underline,
+    };
+
+    fn text(printer: Printer<Document>, columns: usize) -> String {
+        render_document(printer.device(), columns, false)
+    }
+
+    #[test]
+    fn justification() {
+        let printer = Printer::document();
+        printer.println("abc").unwrap();
+        printer.justify(Justification::Center).unwrap();
+        printer.println("abc").unwrap();
+        printer.justify(Justification::Right).unwrap();
+        printer.println("abc").unwrap();
+
+        assert_eq!(text(printer, 11), "abc\n    abc\n        abc\n");
+    }
+
+    #[test]
+    fn justification_in_print_area() {
+        let printer = Printer::document();
+        printer.left_margin(24).unwrap();
+        printer.print_width(72).unwrap();
+        printer.justify(Justification::Center).unwrap();
+        printer.println("ab").unwrap();
+        printer.justify(Justification::Right).unwrap();
+        printer.println("ab").unwrap();
+
+        assert_eq!(text(printer, 20), "    ab\n      ab\n");
+    }
+
+    #[test]
+    fn margins() {
+        let printer = Printer::document();
+        printer.left_margin(24).unwrap();
+        printer.print_width(60).unwrap();
+        printer.println("abcdefgh").unwrap();
+
+        assert_eq!(text(printer, 20), "  abcde\n  fgh\n");
+    }
+
+    #[test]
+    fn tab_stops() {
+        let printer = Printer::document();
+        printer.println_tabbed(&["a", "b"]).unwrap();
+        printer.tab_stops(&[4, 10]).unwrap();
+        printer.println_tabbed(&["a", "b", "c"]).unwrap();
+
+        assert_eq!(text(printer, 20), "a       b\na   b     c\n");
+    }
+
+    #[test]
+    fn double_width() {
+        let printer = Printer::document();
+        printer.character_size(2, 2).unwrap();
+        printer.println("abc").unwrap();
+
+        assert_eq!(text(printer, 5), "a b\nc\n");
+    }
+
+    #[test]
+    fn ansi_styles() {
+        let printer = Printer::document();
+        printer.emphasized(true).unwrap();
+        printer.print("a").unwrap();
+        printer.emphasized(false).unwrap();
+        printer.underline(Underline::Single).unwrap();
+        printer.print("b").unwrap();
+        printer.underline(Underline::None).unwrap();
+        printer.println("c").unwrap();
+        let data = printer.device().to_vec();
+
+        assert_eq!(render(&data, 20, false), "abc\n");
+        assert_eq!(
+            render(&data, 20, true),
+            "\x1b[0m\x1b[1ma\x1b[0m\x1b[4mb\x1b[0mc\n"
+        );
+    }
+
+    #[test]
+    fn placeholders() {
+        let printer = Printer::document();
+        printer.barcode(BarcodeSystem::Ean8, "1234567").unwrap();
+        printer.qr_code("hello", 4, QrCorrection::M).unwrap();
+        printer.image(&Image::from_fn(16, 2, |_, _| true)).unwrap();
+        printer.cut(CutMode::Immediate(CutType::Full)).unwrap();
+        printer.cut(CutMode::Immediate(CutType::Partial)).unwrap();
+
+        assert_eq!(
+            text(printer, 20),
+            "[EAN8 1234567]\n\
+             [QR hello]\n\
+             [image 16x2]\n\
+             ------- cut --------\n\
+             --- partial cut ----\n"
+        );
+    }
+
+    #[test]
+    fn macro_replay() {
+        let printer = Printer::document();
+        printer
+            .record_macro(|header| -> Result<(), MacroError> {
+                header.println("Corner Cafe").unwrap();
+                Ok(())
+            })
+            .unwrap();
+        printer
+            .execute_macro(2, Duration::ZERO, MacroMode::Continuous)
+            .unwrap();
+
+        assert_eq!(text(printer, 20), "Corner Cafe\nCorner Cafe\n");
+    }
+}
@@ -1,20 +1,46 @@
-use std::{fmt::Debug, ops::RangeInclusive};
+//! The errors returned by the printer, its connection and the helpers built
+//! on top of it
+
+use std::{fmt::Debug, ops::RangeInclusive, time::Duration};
 
 use codepage_437::Cp437Error;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum ConnectionError {
     #[error("libusb error: {0}")]
     USB(#[from] rusb::Error),
     #[error("no bulk endpoint could be found")]
     NoBulkEndpoint,
+    #[error("no matching printer was found")]
+    NotFound,
 }
 
 #[derive(Error, Debug)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum PrinterError {
     #[error("failed to write to printer: {0}")]
     USB(#[from] rusb::Error),
+    #[error("{feature} is not supported by the {profile}")]
+    Unsupported {
+        feature: &'static str,
+        profile: &'static str,
+    },
+    #[error("{0} is only available in page mode")]
+    PageModeOnly(&'static str),
+    #[error("the printer can not send data back")]
+    NotReadable,
+    #[error("the printer did not respond")]
+    NoResponse,
+    #[error("invalid status byte from the printer: {0:#04x}")]
+    InvalidStatus(u8),
+    #[error("a distance of {0} motion units does not fit in the command")]
+    OutOfRange(i64),
+    #[error("failed to reconnect to the printer: {0}")]
+    Reconnect(#[from] ConnectionError),
+    #[error("the printer job was cancelled by the runtime shutting down")]
+    Cancelled,
 }
 
 #[derive(Error, Debug)]
@@ -25,6 +51,150 @@ pub enum TextError<S: AsRef<str> + Debug> {
     Cp437 { text: S, err: Cp437Error },
 }
 
+#[derive(Error, Debug)]
+pub enum SlipError {
+    #[error("{0}")]
+    Printer(#[from] PrinterError),
+    #[error("no paper was inserted within {0:?}")]
+    Timeout(Duration),
+}
+
+#[derive(Error, Debug)]
+pub enum MicrError {
+    #[error("{0}")]
+    Printer(#[from] PrinterError),
+    #[error("no check was read within {0:?}")]
+    Timeout(Duration),
+    #[error("invalid MICR reading result: {0:02x?}")]
+    InvalidResult(Vec<u8>),
+    #[error("the MICR line could not be read (status {0:#04x})")]
+    ReadFailed(u8),
+}
+
+#[derive(Error, Debug)]
+pub enum GlyphError {
+    #[error("{0}")]
+    Printer(#[from] PrinterError),
+    #[error("{count} characters can not be downloaded, at most {available} fit")]
+    TooMany { count: usize, available: usize },
+    #[error("character codes {first} to {last} are outside of 32 to 126")]
+    OutOfRange { first: u8, last: usize },
+    #[error("a {width}x{height} character does not fit in the {max_width}x{max_height} font")]
+    TooLarge {
+        width: u32,
+        height: u32,
+        max_width: u32,
+        max_height: u32,
+    },
+}
+
+#[derive(Error, Debug)]
+pub enum MacroError {
+    #[error("{0}")]
+    Printer(#[from] PrinterError),
+    #[error("the macro is {0} bytes long, at most 2048 bytes can be stored")]
+    TooLong(usize),
+}
+
+#[derive(Error, Debug)]
+pub enum NvError {
+    #[error("{0}")]
+    Printer(#[from] PrinterError),
+    #[error("unexpected reply from the printer: {0:02x?}")]
+    InvalidReply(Vec<u8>),
+    #[error("memory switch {0} does not exist, switches are numbered 1 to 8")]
+    InvalidSwitch(u8),
+    #[error("bit {0} of a memory switch does not exist, bits are numbered 1 to 8")]
+    InvalidBit(u8),
+}
+
+#[derive(Error, Debug)]
+pub enum TabError {
+    #[error("{0}")]
+    Printer(#[from] PrinterError),
+    #[error("too many tab stops: {0} provided, at most 32 can be set")]
+    TooMany(usize),
+    #[error("tab stop {stop} at position {position} must be after the previous tab stop")]
+    NotAscending { stop: u8, position: usize },
+    #[error("tab stop {stop} at position {position} is past the last column ({columns})")]
+    OutOfRange {
+        stop: u8,
+        position: usize,
+        columns: usize,
+    },
+}
+
+#[derive(Error, Debug)]
+pub enum ImageError {
+    #[error("failed to decode image: {0}")]
+    Decode(#[from] png::DecodingError),
+}
+
+#[derive(Error, Debug)]
+#[error("line {line}, column {column}: {kind}")]
+pub struct MarkupError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: MarkupErrorKind,
+}
+
+#[derive(Error, Debug)]
+pub enum MarkupErrorKind {
+    #[error("{0}")]
+    Syntax(&'static str),
+    #[error("unknown entity '&{0};'")]
+    UnknownEntity(String),
+    #[error("unknown tag <{0}>")]
+    UnknownTag(String),
+    #[error("<{0}> is never closed")]
+    Unclosed(String),
+    #[error("expected </{expected}>, found </{found}>")]
+    Mismatched { expected: String, found: String },
+    #[error("</{0}> does not close any tag")]
+    UnexpectedClose(String),
+    #[error("<{0}> is not allowed here")]
+    Misplaced(String),
+    #[error("<{tag}> is missing the {attribute} attribute")]
+    MissingAttribute {
+        tag: String,
+        attribute: &'static str,
+    },
+    #[error("invalid value '{value}' for the {attribute} attribute of <{tag}>")]
+    InvalidAttribute {
+        tag: String,
+        attribute: String,
+        value: String,
+    },
+    #[error("{0}")]
+    Text(String),
+    #[error("{0}")]
+    Barcode(String),
+    #[error("images are not available to this markup")]
+    NoImages,
+    #[error("image '{0}' is outside the image directory")]
+    ImagePath(String),
+    #[error("failed to open image: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("{0}")]
+    Image(#[from] ImageError),
+    #[error("{0}")]
+    Printer(#[from] PrinterError),
+}
+
+#[derive(Error, Debug)]
+pub enum TemplateError {
+    #[error("line {line}, column {column}: {message}")]
+    Template {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    #[error("failed to serialize the template data: {0}")]
+    Data(#[from] serde_json::Error),
+    #[error("the rendered template is not valid markup: {0}")]
+    Markup(#[from] MarkupError),
+}
+
 #[derive(Error, Debug)]
 pub enum BarcodeError<D: AsRef<[u8]> + Debug> {
     #[error("{0}")]
@@ -41,3 +211,17 @@ pub enum BarcodeError<D: AsRef<[u8]> + Debug> {
         provided: usize,
     },
 }
+
+#[derive(Error, Debug)]
+pub enum QueueError {
+    #[error("{0}")]
+    Printer(#[from] PrinterError),
+    #[error("failed to access the job journal: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid job journal entry on line {line}: {err}")]
+    Journal { line: usize, err: serde_json::Error },
+    #[error("the job was cancelled")]
+    Cancelled,
+    #[error("the queue stopped before the job was printed")]
+    Stopped,
+}
@@ -0,0 +1,297 @@
+//! User-defined characters
+//!
+//! Characters missing from the code page, like `✓` or `€`, can be drawn as
+//! bitmaps and downloaded to the printer, after which [`Printer::print`]
+//! prints them in place of the Unicode characters:
+//!
+//! ```
+//! use thermal::{glyph::GlyphSet, Font, Image, Printer};
+//!
+//! let check = Image::from_fn(12, 24, |x, y| (x + 14 == y * 2) || (x + y == 18 && x > 4));
+//!
+//! let mut glyphs = GlyphSet::new(Font::A);
+//! glyphs.insert('✓', check);
+//!
+//! let printer = Printer::document();
+//! printer.define_glyphs(&glyphs).unwrap();
+//! printer.println("Paid ✓").unwrap();
+//! ```
+//!
+//! The printer forgets its user-defined characters when it is initialized or
+//! turned off.
+
+use std::{borrow::Cow, collections::HashMap};
+
+use codepage_437::{Cp437Error, ToCp437, CP437_CONTROL};
+
+use crate::{
+    error::{GlyphError, PrinterError},
+    Device, Font, Image, Printer,
+};
+
+/// The character codes that can be user-defined
+const CODES: std::ops::RangeInclusive<u8> = 0x20..=0x7E;
+
+/// Bitmaps for characters the code page is missing, for a single font
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GlyphSet {
+    font: Font,
+    glyphs: Vec<(char, Image)>,
+}
+
+/// The user-defined characters currently downloaded to the printer
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Glyphs {
+    font: Option<Font>,
+    codes: HashMap<char, u8>,
+}
+
+impl GlyphSet {
+    /// Create an empty set of characters for the given font
+    pub fn new(font: Font) -> Self {
+        Self {
+            font,
+            glyphs: Vec::new(),
+        }
+    }
+
+    /// The font the characters are drawn for
+    pub fn font(&self) -> Font {
+        self.font
+    }
+
+    /// Draw a character, replacing an earlier bitmap for it
+    ///
+    /// Images narrower or shorter than the font are aligned to the top left.
+    pub fn insert(&mut self, character: char, image: Image) -> &mut Self {
+        match self.glyphs.iter_mut().find(|(c, _)| *c == character) {
+            Some((_, existing)) => *existing = image,
+            None => self.glyphs.push((character, image)),
+        }
+
+        self
+    }
+
+    /// The amount of characters in the set
+    pub fn len(&self) -> usize {
+        self.glyphs.len()
+    }
+
+    /// If no characters have been drawn
+    pub fn is_empty(&self) -> bool {
+        self.glyphs.is_empty()
+    }
+}
+
+impl<D: Device> Printer<D> {
+    /// Define user-defined characters
+    ///
+    /// Defines consecutive character codes starting at `first` for the
+    /// selected font, one for each image. Codes range from 32 to 126.
+    /// Nothing is sent without images.
+    ///
+    /// ASCII: ESC & **y** **c1** **c2** [**x** **d1**...**d(y × x)**]...
+    pub fn define_characters(&self, first: u8, images: &[Image]) -> Result<(), GlyphError> {
+        let font = self.state.get().font.unwrap_or(Font::A);
+        let max_width = self.profile.character_width(font);
+        let max_height = self.profile.character_height(font);
+
+        let last = first as usize + images.len().max(1) - 1;
+        if !CODES.contains(&first) || last > *CODES.end() as usize {
+            return Err(GlyphError::OutOfRange { first, last });
+        }
+
+        // The command defines at least one character
+        if images.is_empty() {
+            return Ok(());
+        }
+
+        for image in images {
+            if image.width() > max_width || image.height() > max_height {
+                return Err(GlyphError::TooLarge {
+                    width: image.width(),
+                    height: image.height(),
+                    max_width,
+                    max_height,
+                });
+            }
+        }
+
+        let rows = max_height.div_ceil(8);
+        let mut data = vec![Self::ESC, b'&', rows as u8, first, last as u8];
+
+        // Columns from left to right, each from top to bottom
+        for image in images {
+            data.push(image.width() as u8);
+
+            for x in 0..image.width() {
+                for row in 0..rows {
+                    let byte = (0..8)
+                        .filter(|bit| image.pixel(x, row * 8 + bit))
+                        .fold(0, |byte, bit| byte | 0x80 >> bit);
+
+                    data.push(byte);
+                }
+            }
+        }
+
+        self.raw(data)?;
+
+        Ok(())
+    }
+
+    /// Select/cancel user-defined character set
+    ///
+    /// While selected, defined character codes print their user-defined
+    /// character instead of the one in the code page.
+    ///
+    /// ASCII: ESC % **n**
+    pub fn user_defined_characters(&self, enable: bool) -> Result<(), PrinterError> {
+        self.raw([Self::ESC, b'%', enable as u8])
+    }
+
+    /// Cancel user-defined characters
+    ///
+    /// ASCII: ESC ? **n**
+    pub fn cancel_character(&self, code: u8) -> Result<(), PrinterError> {
+        self.raw([Self::ESC, b'?', code])?;
+        self.glyphs.borrow_mut().codes.retain(|_, c| *c != code);
+
+        Ok(())
+    }
+
+    /// Download a set of characters to be printed in place of their Unicode
+    /// characters
+    ///
+    /// Selects the font of the set and replaces any characters downloaded
+    /// before. The characters are only printed while that font is selected.
+    pub fn define_glyphs(&self, glyphs: &GlyphSet) -> Result<(), GlyphError> {
+        let available = CODES.len();
+        if glyphs.len() > available {
+            return Err(GlyphError::TooMany {
+                count: glyphs.len(),
+                available,
+            });
+        }
+
+        self.font(glyphs.font)?;
+
+        let images: Vec<_> = glyphs
+            .glyphs
+            .iter()
+            .map(|(_, image)| image.clone())
+            .collect();
+        self.define_characters(*CODES.start(), &images)?;
+
+        *self.glyphs.borrow_mut() = Glyphs {
+            font: Some(glyphs.font),
+            codes: glyphs
+                .glyphs
+                .iter()
+                .zip(CODES)
+                .map(|((character, _), code)| (*character, code))
+                .collect(),
+        };
+
+        Ok(())
+    }
+
+    /// Forget the user-defined characters, after the printer cleared them
+    pub(crate) fn clear_glyphs(&self) {
+        *self.glyphs.borrow_mut() = Glyphs::default();
+    }
+
+    /// Whether a character can be printed in the current font, from the code
+    /// page or as a user-defined character
+    pub(crate) fn can_print(&self, character: char) -> bool {
+        let glyphs = self.glyphs.borrow();
+        let font = self.state.get().font.unwrap_or(Font::A);
+
+        CP437_CONTROL.encode(character).is_some()
+            || (glyphs.font == Some(font) && glyphs.codes.contains_key(&character))
+    }
+
+    /// Convert text to the code page, switching to the user-defined character
+    /// set for the characters that were downloaded
+    pub(crate) fn encode_text<'t>(&self, text: &'t str) -> Result<Cow<'t, [u8]>, Cp437Error> {
+        let glyphs = self.glyphs.borrow();
+        let font = self.state.get().font.unwrap_or(Font::A);
+
+        if glyphs.codes.is_empty() || glyphs.font != Some(font) {
+            return text.to_cp437(&CP437_CONTROL);
+        }
+
+        let mut data = Vec::with_capacity(text.len());
+        let mut user_defined = false;
+        let mut start = 0;
+
+        let push_text = |data: &mut Vec<u8>, start: usize, end: usize, user_defined: &mut bool| {
+            if start == end {
+                return Ok(());
+            }
+
+            let encoded = text[start..end]
+                .to_cp437(&CP437_CONTROL)
+                .map_err(|err| Cp437Error {
+                    representable_up_to: start + err.representable_up_to,
+                })?;
+
+            if *user_defined {
+                data.extend([Self::ESC, b'%', 0]);
+                *user_defined = false;
+            }
+            data.extend_from_slice(&encoded);
+
+            Ok(())
+        };
+
+        for (i, character) in text.char_indices() {
+            if let Some(&code) = glyphs.codes.get(&character) {
+                push_text(&mut data, start, i, &mut user_defined)?;
+
+                if !user_defined {
+                    data.extend([Self::ESC, b'%', 1]);
+                    user_defined = true;
+                }
+                data.push(code);
+
+                start = i + character.len_utf8();
+            }
+        }
+
+        push_text(&mut data, start, text.len(), &mut user_defined)?;
+
+        if user_defined {
+            data.extend([Self::ESC, b'%', 0]);
+        }
+
+        Ok(Cow::Owned(data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_characters() {
+        let printer = Printer::document();
+
+        printer.define_characters(0x41, &[]).unwrap();
+        assert!(printer.device().is_empty());
+
+        assert!(matches!(
+            printer.define_characters(0x7F, &[]),
+            Err(GlyphError::OutOfRange { first: 0x7F, .. })
+        ));
+    }
+
+    #[test]
+    fn empty_glyph_set() {
+        let printer = Printer::document();
+
+        printer.define_glyphs(&GlyphSet::new(Font::B)).unwrap();
+
+        assert_eq!(printer.device().to_vec(), [0x1B, b'M', 1]);
+    }
+}
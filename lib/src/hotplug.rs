@@ -0,0 +1,403 @@
+//! Noticing printers being plugged in and out, and reconnecting to them
+//!
+//! [`watch`] reports printers as they come and go, through libusb hotplug
+//! events where the platform has them and by polling otherwise.
+//! [`ReconnectingPrinter`] keeps a connection to one printer alive across
+//! unplugging and power cycles:
+//!
+//! ```no_run
+//! use thermal::{hotplug::ReconnectingPrinter, rusb::Context, Printer};
+//!
+//! let context = Context::new().unwrap();
+//! let mut printer =
+//!     ReconnectingPrinter::connect(&context, Printer::builder(0x04b8, 0x0202)).unwrap();
+//!
+//! // Waits for the printer to come back if it was unplugged
+//! printer.run(|printer| printer.println("Hello")).unwrap();
+//! ```
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use rusb::{Context, Device, Hotplug, Registration, UsbContext};
+use tracing::{debug, info, warn};
+
+use crate::{
+    discovery::{self, PrinterInfo},
+    error::{ConnectionError, PrinterError},
+    Printer, PrinterBuilder,
+};
+
+/// How often the printers are listed without hotplug events, and how long
+/// to wait for hotplug events before checking if the watcher was stopped
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A printer being plugged in or out
+#[derive(Debug, Clone)]
+pub enum HotplugEvent {
+    Arrived(PrinterInfo),
+    Left(PrinterInfo),
+}
+
+/// Watches for printers being plugged in and out until dropped
+pub struct Watcher {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// Flags that the devices changed, to be listed outside of the libusb
+/// callback where opening devices is not allowed
+struct Changed(Arc<AtomicBool>);
+
+impl Hotplug<Context> for Changed {
+    fn device_arrived(&mut self, _device: Device<Context>) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    fn device_left(&mut self, _device: Device<Context>) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Call `callback` from a background thread whenever a printer is plugged
+/// in or out
+///
+/// Printers attached when watching starts are reported as arrived.
+pub fn watch<F>(context: &Context, mut callback: F) -> Result<Watcher, ConnectionError>
+where
+    F: FnMut(HotplugEvent) + Send + 'static,
+{
+    let context = context.clone();
+    let stop = Arc::new(AtomicBool::new(false));
+    let changed = Arc::new(AtomicBool::new(true));
+
+    let registration: Option<Registration<Context>> = if rusb::has_hotplug() {
+        debug!("Watching for printers with hotplug events");
+
+        Some(context.register_callback(None, None, None, Box::new(Changed(changed.clone())))?)
+    } else {
+        debug!("Hotplug events are not supported, polling for printers");
+
+        None
+    };
+
+    let thread = thread::spawn({
+        let stop = stop.clone();
+
+        move || {
+            let mut known = HashMap::new();
+
+            while !stop.load(Ordering::Relaxed) {
+                if registration.is_some() {
+                    if let Err(err) = context.handle_events(Some(POLL_INTERVAL)) {
+                        warn!(%err, "Failed to handle USB events");
+                    }
+                } else {
+                    changed.store(true, Ordering::Relaxed);
+                }
+
+                if changed.swap(false, Ordering::Relaxed) {
+                    match discovery::discover(&context) {
+                        Ok(printers) => update(&mut known, printers, &mut callback),
+                        Err(err) => warn!(%err, "Failed to list the printers"),
+                    }
+                }
+
+                if registration.is_none() {
+                    thread::sleep(POLL_INTERVAL);
+                }
+            }
+        }
+    });
+
+    Ok(Watcher {
+        stop,
+        thread: Some(thread),
+    })
+}
+
+/// Report the difference between the known printers and the attached ones
+fn update<F>(known: &mut HashMap<String, PrinterInfo>, printers: Vec<PrinterInfo>, callback: &mut F)
+where
+    F: FnMut(HotplugEvent),
+{
+    let mut attached: HashMap<_, _> = printers
+        .into_iter()
+        .map(|info| (info.path.clone(), info))
+        .collect();
+
+    // A different printer plugged into the same port left and arrived
+    let left: Vec<_> = known
+        .keys()
+        .filter(|path| match attached.get(*path) {
+            Some(info) => !same_printer(&known[*path], info),
+            None => true,
+        })
+        .cloned()
+        .collect();
+
+    for path in left {
+        let info = known.remove(&path).expect("the printer is known");
+        info!(path = %info.path, serial_number = ?info.serial_number, "Printer left");
+
+        callback(HotplugEvent::Left(info));
+    }
+
+    for (path, info) in attached.drain() {
+        if known.contains_key(&path) {
+            continue;
+        }
+
+        info!(path = %info.path, serial_number = ?info.serial_number, "Printer arrived");
+        known.insert(path, info.clone());
+
+        callback(HotplugEvent::Arrived(info));
+    }
+}
+
+fn same_printer(a: &PrinterInfo, b: &PrinterInfo) -> bool {
+    a.vendor_id == b.vendor_id
+        && a.product_id == b.product_id
+        && a.address == b.address
+        && a.serial_number == b.serial_number
+}
+
+impl Watcher {
+    /// Stop watching and wait for the background thread to finish
+    pub fn stop(mut self) {
+        self.join();
+    }
+
+    fn join(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        self.join();
+    }
+}
+
+/// A printer that reconnects by itself after being unplugged or power
+/// cycled
+///
+/// Once connected, the printer is found again by its serial number, or by
+/// the port it is plugged into if it has none. Every connection starts with
+/// [`Printer::init`].
+pub struct ReconnectingPrinter {
+    context: Context,
+    builder: PrinterBuilder,
+    printer: Option<Printer>,
+    reconnect_timeout: Duration,
+    retry_interval: Duration,
+}
+
+impl ReconnectingPrinter {
+    /// Connect to a printer and keep it connected
+    ///
+    /// Fails with [`ConnectionError::NotFound`] if the printer is not there
+    /// to begin with.
+    pub fn connect(context: &Context, builder: PrinterBuilder) -> Result<Self, PrinterError> {
+        let mut printer = Self {
+            context: context.clone(),
+            builder,
+            printer: None,
+            reconnect_timeout: Duration::from_secs(60),
+            retry_interval: Duration::from_secs(1),
+        };
+
+        printer.try_connect()?.ok_or(ConnectionError::NotFound)?;
+
+        Ok(printer)
+    }
+
+    /// How long to wait for the printer to come back, 1 minute by default
+    pub fn reconnect_timeout(mut self, timeout: Duration) -> Self {
+        self.reconnect_timeout = timeout;
+        self
+    }
+
+    /// How often to look for the printer while it is gone, every second by
+    /// default
+    pub fn retry_interval(mut self, interval: Duration) -> Self {
+        self.retry_interval = interval;
+        self
+    }
+
+    /// If the printer is connected, as far as is known
+    pub fn is_connected(&self) -> bool {
+        self.printer.is_some()
+    }
+
+    /// The connected printer, waiting for it to come back if it was
+    /// disconnected
+    pub fn printer(&mut self) -> Result<&Printer, PrinterError> {
+        if self.printer.is_none() {
+            self.reconnect()?;
+        }
+
+        Ok(self.printer.as_ref().expect("the printer is connected"))
+    }
+
+    /// Run a job on the printer
+    ///
+    /// If the job fails because the printer was disconnected, it is run
+    /// again from the start once the printer is back, which can print the
+    /// part that made it out before the printer went away twice.
+    pub fn run<T, E, F>(&mut self, mut job: F) -> Result<T, E>
+    where
+        F: FnMut(&Printer) -> Result<T, E>,
+        E: From<PrinterError>,
+    {
+        loop {
+            let printer = self.printer()?;
+
+            match job(printer) {
+                Ok(value) => return Ok(value),
+                Err(err) if printer.device().is_attached() => return Err(err),
+                Err(_) => {
+                    warn!("Printer disconnected, resuming the job once it is back");
+                    self.printer = None;
+                }
+            }
+        }
+    }
+
+    /// Wait for the printer to come back
+    fn reconnect(&mut self) -> Result<(), PrinterError> {
+        let start = Instant::now();
+
+        info!("Reconnecting to the printer");
+
+        loop {
+            match self.try_connect() {
+                Ok(Some(())) => return Ok(()),
+                Ok(None) => {}
+                Err(err) => debug!(%err, "Failed to reconnect to the printer"),
+            }
+
+            if start.elapsed() >= self.reconnect_timeout {
+                warn!("The printer did not come back");
+
+                return Err(ConnectionError::NotFound.into());
+            }
+
+            thread::sleep(self.retry_interval);
+        }
+    }
+
+    fn try_connect(&mut self) -> Result<Option<()>, PrinterError> {
+        let printer = match self.builder.clone().connect(&self.context)? {
+            Some(printer) => printer,
+            None => return Ok(None),
+        };
+
+        // Find the same printer again, not just the same model
+        if self.builder.serial_number.is_none() && self.builder.path.is_none() {
+            self.builder = match printer.device().serial_number() {
+                Some(serial_number) => self.builder.clone().serial_number(serial_number),
+                None => self
+                    .builder
+                    .clone()
+                    .path(discovery::path(&printer.device().handle.device())),
+            };
+        }
+
+        printer.init()?;
+
+        info!(serial_number = ?self.builder.serial_number, path = ?self.builder.path, "Connected to the printer");
+        self.printer = Some(printer);
+
+        Ok(Some(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn printer(path: &str, address: u8) -> PrinterInfo {
+        PrinterInfo {
+            vendor_id: 0x04b8,
+            product_id: 0x0202,
+            bus: 1,
+            address,
+            path: path.to_owned(),
+            manufacturer: None,
+            product: None,
+            serial_number: None,
+            printer_class: true,
+            profile: None,
+        }
+    }
+
+    /// Run an update, describing the events as `+path@address` for arrivals
+    /// and `-path@address` for printers that left
+    fn events(known: &mut HashMap<String, PrinterInfo>, printers: Vec<PrinterInfo>) -> Vec<String> {
+        let mut events = Vec::new();
+        update(known, printers, &mut |event| {
+            events.push(match event {
+                HotplugEvent::Arrived(info) => format!("+{}@{}", info.path, info.address),
+                HotplugEvent::Left(info) => format!("-{}@{}", info.path, info.address),
+            })
+        });
+
+        events
+    }
+
+    #[test]
+    fn arrived() {
+        let mut known = HashMap::new();
+
+        assert_eq!(events(&mut known, vec![printer("1-2", 5)]), ["+1-2@5"]);
+        assert!(events(&mut known, vec![printer("1-2", 5)]).is_empty());
+        assert_eq!(
+            events(&mut known, vec![printer("1-2", 5), printer("1-3", 6)]),
+            ["+1-3@6"]
+        );
+    }
+
+    #[test]
+    fn left() {
+        let mut known = HashMap::new();
+        events(&mut known, vec![printer("1-2", 5), printer("1-3", 6)]);
+
+        assert_eq!(events(&mut known, vec![printer("1-3", 6)]), ["-1-2@5"]);
+        assert_eq!(events(&mut known, Vec::new()), ["-1-3@6"]);
+        assert!(known.is_empty());
+    }
+
+    #[test]
+    fn replugged() {
+        let mut known = HashMap::new();
+        events(&mut known, vec![printer("1-2", 5)]);
+
+        // Plugged back into the same port between two checks, which gives it
+        // a new address
+        assert_eq!(
+            events(&mut known, vec![printer("1-2", 7)]),
+            ["-1-2@5", "+1-2@7"]
+        );
+        assert_eq!(known["1-2"].address, 7);
+
+        // Another printer got the same port and address
+        let other = PrinterInfo {
+            serial_number: Some("B".to_owned()),
+            ..printer("1-2", 7)
+        };
+        assert_eq!(events(&mut known, vec![other]), ["-1-2@7", "+1-2@7"]);
+        assert_eq!(known["1-2"].serial_number.as_deref(), Some("B"));
+    }
+}
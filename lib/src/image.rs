@@ -0,0 +1,140 @@
+use std::io::Read;
+
+use png::{ColorType, Decoder, Transformations};
+
+use crate::error::ImageError;
+
+/// A monochrome raster image
+///
+/// Rows are packed 8 pixels to a byte, most significant bit first, with set
+/// bits printed black.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+}
+
+impl Image {
+    /// Rows sent to the printer in a single raster image command
+    pub(crate) const MAX_BAND_HEIGHT: u32 = 256;
+
+    /// Create an image, asking `black` for the color of every pixel
+    pub fn from_fn<F: FnMut(u32, u32) -> bool>(width: u32, height: u32, mut black: F) -> Self {
+        let width_bytes = (width as usize).div_ceil(8);
+        let mut data = vec![0; width_bytes * height as usize];
+
+        for y in 0..height {
+            for x in 0..width {
+                if black(x, y) {
+                    data[y as usize * width_bytes + x as usize / 8] |= 0x80 >> (x % 8);
+                }
+            }
+        }
+
+        Self {
+            width,
+            height,
+            data,
+        }
+    }
+
+    /// Decode a PNG image, dithering it to black and white
+    ///
+    /// Transparent pixels are printed as white.
+    pub fn from_png<R: Read>(reader: R) -> Result<Self, ImageError> {
+        let mut decoder = Decoder::new(reader);
+        decoder.set_transformations(Transformations::EXPAND | Transformations::STRIP_16);
+
+        let mut reader = decoder.read_info()?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer)?;
+
+        let channels = match info.color_type {
+            ColorType::Grayscale | ColorType::Indexed => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        };
+        let (width, height) = (info.width as usize, info.height as usize);
+
+        // Luminance of every pixel, blended onto white paper
+        let mut luminance: Vec<f32> = buffer[..info.buffer_size()]
+            .chunks_exact(channels)
+            .map(|pixel| {
+                let (value, alpha) = match *pixel {
+                    [l] => (l as f32, 255.0),
+                    [l, a] => (l as f32, a as f32),
+                    [r, g, b] => (
+                        0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32,
+                        255.0,
+                    ),
+                    [r, g, b, a, ..] => (
+                        0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32,
+                        a as f32,
+                    ),
+                    [] => (255.0, 255.0),
+                };
+
+                value * alpha / 255.0 + 255.0 * (1.0 - alpha / 255.0)
+            })
+            .collect();
+
+        // Floyd-Steinberg dithering
+        let mut black = vec![false; width * height];
+        for y in 0..height {
+            for x in 0..width {
+                let i = y * width + x;
+                let old = luminance[i];
+                let new = if old < 128.0 { 0.0 } else { 255.0 };
+                let error = old - new;
+
+                black[i] = new == 0.0;
+
+                let mut spread = |dx: isize, dy: usize, weight: f32| {
+                    let nx = x as isize + dx;
+                    if nx >= 0 && (nx as usize) < width && y + dy < height {
+                        luminance[(y + dy) * width + nx as usize] += error * weight;
+                    }
+                };
+
+                spread(1, 0, 7.0 / 16.0);
+                spread(-1, 1, 3.0 / 16.0);
+                spread(0, 1, 5.0 / 16.0);
+                spread(1, 1, 1.0 / 16.0);
+            }
+        }
+
+        Ok(Self::from_fn(info.width, info.height, |x, y| {
+            black[y as usize * width + x as usize]
+        }))
+    }
+
+    /// Width in dots
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Height in dots
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Whether the pixel at the given position is black
+    pub fn pixel(&self, x: u32, y: u32) -> bool {
+        x < self.width
+            && y < self.height
+            && self.data[y as usize * self.width_bytes() + x as usize / 8] & (0x80 >> (x % 8)) != 0
+    }
+
+    /// Bytes per row
+    pub(crate) fn width_bytes(&self) -> usize {
+        (self.width as usize).div_ceil(8)
+    }
+
+    /// The packed rows, split into bands of at most `rows` rows
+    pub(crate) fn bands(&self, rows: u32) -> impl Iterator<Item = &[u8]> {
+        self.data
+            .chunks((self.width_bytes() * rows as usize).max(1))
+    }
+}
@@ -0,0 +1,308 @@
+//! Multi-column text layout
+//!
+//! Column widths are computed from the amount of characters that fit on a
+//! line with the printer's current font, character size and margins, so
+//! tables stay aligned when printing double width text or on narrower paper.
+
+use crate::{
+    error::TextError,
+    wrap::{self, width},
+    Device, Justification, Printer,
+};
+
+/// What to do with text that does not fit in its column
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Cut the text off at the column edge
+    Truncate,
+    /// Cut the text off and mark it with an ellipsis
+    Ellipsis,
+}
+
+const ELLIPSIS: &str = "...";
+
+/// A column of a [`Table`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Column {
+    /// Width in characters, or `None` to share the remaining width
+    width: Option<usize>,
+    justification: Justification,
+    overflow: Overflow,
+}
+
+impl Column {
+    /// A column of a fixed amount of characters
+    pub fn fixed(width: usize) -> Self {
+        Self {
+            width: Some(width),
+            justification: Justification::Left,
+            overflow: Overflow::Ellipsis,
+        }
+    }
+
+    /// A column sharing the width left over by the fixed columns
+    pub fn fill() -> Self {
+        Self {
+            width: None,
+            justification: Justification::Left,
+            overflow: Overflow::Ellipsis,
+        }
+    }
+
+    /// Align the text within the column
+    pub fn justify(mut self, justification: Justification) -> Self {
+        self.justification = justification;
+        self
+    }
+
+    /// Select what happens to text that does not fit
+    pub fn overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+}
+
+/// A layout of columns for printing rows of text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Table {
+    columns: Vec<Column>,
+    gap: usize,
+}
+
+impl Table {
+    /// Create a table with the given columns and a one character gap between them
+    pub fn new(columns: Vec<Column>) -> Self {
+        Self { columns, gap: 1 }
+    }
+
+    /// A left aligned column followed by a right aligned column, as used for
+    /// an item and its price
+    pub fn pair() -> Self {
+        Self::new(vec![
+            Column::fill(),
+            Column::fill().justify(Justification::Right),
+        ])
+    }
+
+    /// Set the amount of spaces between columns
+    pub fn gap(mut self, gap: usize) -> Self {
+        self.gap = gap;
+        self
+    }
+
+    /// The width of each column, in characters, for a line of `total` characters
+    ///
+    /// Fixed columns are shrunk from the right if they do not fit.
+    pub fn widths(&self, total: usize) -> Vec<usize> {
+        let gaps = self.gap * self.columns.len().saturating_sub(1);
+        let mut available = total.saturating_sub(gaps);
+
+        let mut widths: Vec<usize> = self
+            .columns
+            .iter()
+            .map(|column| match column.width {
+                Some(width) => {
+                    let width = width.min(available);
+                    available -= width;
+                    width
+                }
+                None => 0,
+            })
+            .collect();
+
+        let fills = self.columns.iter().filter(|c| c.width.is_none()).count();
+        let mut fill_index = 0;
+
+        for (width, column) in widths.iter_mut().zip(&self.columns) {
+            if column.width.is_none() {
+                // Hand out the remainder to the leftmost fill columns
+                *width = available / fills + (fill_index < available % fills) as usize;
+                fill_index += 1;
+            }
+        }
+
+        widths
+    }
+
+    /// Lay out a row of cells on a line of `total` characters
+    ///
+    /// Missing cells are left blank and extra cells are ignored.
+    pub fn format_row<S: AsRef<str>>(&self, total: usize, cells: &[S]) -> String {
+        let widths = self.widths(total);
+        let gap = " ".repeat(self.gap);
+
+        let row = self
+            .columns
+            .iter()
+            .zip(widths)
+            .enumerate()
+            .map(|(i, (column, width))| {
+                let cell = cells.get(i).map_or("", |cell| cell.as_ref());
+
+                fit(cell, width, column.justification, column.overflow)
+            })
+            .collect::<Vec<_>>()
+            .join(&gap);
+
+        row.trim_end().to_owned()
+    }
+}
+
+/// Fit text into a column, padding or cutting it off as needed
+fn fit(text: &str, columns: usize, justification: Justification, overflow: Overflow) -> String {
+    // Taking text always takes at least one character
+    if columns == 0 {
+        return String::new();
+    }
+
+    let text = if width(text) <= columns {
+        text.to_owned()
+    } else {
+        match overflow {
+            Overflow::Ellipsis if columns > ELLIPSIS.len() => {
+                let mut text = wrap::take(text, columns - ELLIPSIS.len()).0.to_owned();
+                text.push_str(ELLIPSIS);
+                text
+            }
+            _ => wrap::take(text, columns).0.to_owned(),
+        }
+    };
+
+    let free = columns.saturating_sub(width(&text));
+    let left = match justification {
+        Justification::Left => 0,
+        Justification::Center => free / 2,
+        Justification::Right => free,
+    };
+
+    format!("{}{}{}", " ".repeat(left), text, " ".repeat(free - left))
+}
+
+impl<D: Device> Printer<D> {
+    /// Print a row of a table, followed by a newline
+    pub fn table_row<S: AsRef<str>>(
+        &self,
+        table: &Table,
+        cells: &[S],
+    ) -> Result<(), TextError<String>> {
+        self.println(table.format_row(self.columns(), cells))
+    }
+
+    /// Print a left aligned and a right aligned piece of text on one line,
+    /// like an item and its price
+    ///
+    /// The left text is cut off with an ellipsis if both do not fit.
+    pub fn pair<L: AsRef<str>, R: AsRef<str>>(
+        &self,
+        left: L,
+        right: R,
+    ) -> Result<(), TextError<String>> {
+        let right = right.as_ref();
+        let table = Table::new(vec![
+            Column::fill(),
+            Column::fixed(width(right)).justify(Justification::Right),
+        ]);
+
+        self.table_row(&table, &[left.as_ref(), right])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockDevice;
+
+    #[test]
+    fn fixed_columns_shrink() {
+        let table = Table::new(vec![
+            Column::fixed(10),
+            Column::fixed(10),
+            Column::fixed(10),
+        ]);
+
+        assert_eq!(table.widths(32), [10, 10, 10]);
+        assert_eq!(table.widths(25), [10, 10, 3]);
+        assert_eq!(table.widths(12), [10, 0, 0]);
+        assert_eq!(table.widths(1), [0, 0, 0]);
+    }
+
+    #[test]
+    fn leftover_columns_spread() {
+        let table = Table::new(vec![
+            Column::fill(),
+            Column::fixed(4),
+            Column::fill(),
+            Column::fill(),
+        ]);
+
+        assert_eq!(table.widths(20), [5, 4, 4, 4]);
+        assert_eq!(table.widths(21), [5, 4, 5, 4]);
+        assert_eq!(table.gap(0).widths(16), [4, 4, 4, 4]);
+    }
+
+    #[test]
+    fn fit_justification() {
+        assert_eq!(
+            fit("Hi", 6, Justification::Left, Overflow::Truncate),
+            "Hi    "
+        );
+        assert_eq!(
+            fit("Hi", 6, Justification::Center, Overflow::Truncate),
+            "  Hi  "
+        );
+        assert_eq!(
+            fit("Hi", 6, Justification::Right, Overflow::Truncate),
+            "    Hi"
+        );
+    }
+
+    #[test]
+    fn fit_overflow() {
+        assert_eq!(
+            fit("Hello world", 8, Justification::Left, Overflow::Ellipsis),
+            "Hello..."
+        );
+        assert_eq!(
+            fit("Hello world", 8, Justification::Left, Overflow::Truncate),
+            "Hello wo"
+        );
+        // No room for an ellipsis
+        assert_eq!(
+            fit("Hello", 3, Justification::Left, Overflow::Ellipsis),
+            "Hel"
+        );
+        assert_eq!(fit("Hello", 0, Justification::Left, Overflow::Truncate), "");
+        assert_eq!(fit("", 0, Justification::Right, Overflow::Ellipsis), "");
+    }
+
+    #[test]
+    fn fit_wide_characters() {
+        assert_eq!(
+            fit("日本語", 7, Justification::Right, Overflow::Truncate),
+            " 日本語"
+        );
+        assert_eq!(
+            fit("日本語", 5, Justification::Left, Overflow::Truncate),
+            "日本 "
+        );
+        assert_eq!(
+            fit("日本語です", 8, Justification::Left, Overflow::Ellipsis),
+            "日本... "
+        );
+    }
+
+    #[test]
+    fn pair() {
+        let printer = Printer::new(MockDevice::new());
+        let columns = printer.columns();
+
+        printer.pair("Coffee", "3.50").unwrap();
+        let expected = format!("Coffee{}3.50\n", " ".repeat(columns - 10));
+        assert_eq!(printer.device().take_written(), expected.as_bytes());
+
+        let item = "x".repeat(columns);
+        printer.pair(&item, "3.50").unwrap();
+        let expected = format!("{}... 3.50\n", "x".repeat(columns - 8));
+        assert_eq!(printer.device().take_written(), expected.as_bytes());
+    }
+}
@@ -1,45 +1,191 @@
-use std::{fmt::Debug, time::Duration};
+use std::{
+    cell::{Cell, RefCell},
+    convert::TryFrom,
+    fmt::Debug,
+    ops::RangeInclusive,
+    time::Duration,
+};
 
-use codepage_437::{ToCp437, CP437_CONTROL};
-use error::{BarcodeError, ConnectionError, PrinterError, TextError};
-use rusb::{Context, DeviceHandle, Direction, TransferType, UsbContext};
-use tracing::{instrument, warn};
+use error::{BarcodeError, ConnectionError, PrinterError, TabError, TextError};
+use rusb::{Context, UsbContext};
+use tracing::instrument;
 
+pub use device::{Device, MockDevice, UsbDevice};
+pub use document::Document;
+pub use image::Image;
+pub use profile::Profile;
 pub use rusb;
 
-mod error;
+#[cfg(feature = "tokio")]
+pub mod asynchronous;
+pub mod decode;
+pub mod density;
+mod device;
+pub mod discovery;
+mod document;
+pub mod drawer;
+pub mod emulator;
+pub mod error;
+pub mod glyph;
+pub mod hotplug;
+mod image;
+pub mod layout;
+pub mod macros;
+pub mod maintenance;
+pub mod markdown;
+pub mod markup;
+pub mod micr;
+pub mod nv;
+pub mod profile;
+pub mod queue;
+pub mod slip;
+pub mod status;
+pub mod template;
+pub mod wrap;
 
 /// Text justification
 #[repr(u8)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Justification {
     Left = 0x00,
     Center = 0x01,
     Right = 0x02,
 }
 
+/// Character font
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Font {
+    A = 0x00,
+    B = 0x01,
+}
+
 /// The type of paper to print to
 #[repr(u8)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PaperType {
     Roll = 0b0011,
     Slip = 0b0100,
     Validation = 0b1000, // TODO: Unsupported on my models
 }
 
+/// How much of the paper to cut
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CutType {
+    /// Cut the paper completely
+    Full,
+    /// Cut the paper leaving one point uncut
+    Partial,
+}
+
+/// When to cut the paper
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CutMode {
+    /// Cut at the current position without feeding
+    Immediate(CutType),
+    /// Feed to the cutting position plus **n** vertical motion units, then cut
+    Feed(CutType, u8),
+    /// Preset the cutting position plus **n** vertical motion units as the
+    /// cut position, cutting once printing of the next job reaches it
+    Reserve(CutType, u8),
+    /// Feed to the cutting position plus **n** vertical motion units, cut,
+    /// then reverse feed to the print start position
+    FeedAndReverse(CutType, u8),
+}
+
+impl Default for CutMode {
+    /// Feed past the last printed line and cut the paper completely
+    ///
+    /// Printers that only cut partially reject this, see
+    /// [`Profile::default_cut`] for a cut every printer with a cutter makes.
+    fn default() -> Self {
+        CutMode::Feed(CutType::Full, 0x96)
+    }
+}
+
+/// Print direction and starting position in page mode
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrintDirection {
+    /// Left to right, starting at the upper left
+    LeftToRight = 0x00,
+    /// Bottom to top, starting at the lower left
+    BottomToTop = 0x01,
+    /// Right to left, starting at the lower right
+    RightToLeft = 0x02,
+    /// Top to bottom, starting at the upper right
+    TopToBottom = 0x03,
+}
+
 /// The side of the slip paper to print to
 #[repr(u8)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SlipSide {
     Face = 0x04,
     Back = 0x44,
 }
 
-#[derive(Debug)]
+/// Underline thickness
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Underline {
+    None = 0x00,
+    Single = 0x01,
+    Double = 0x02,
+}
+
+/// Bar code symbology
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BarcodeSystem {
+    UpcA = 65,
+    UpcE = 66,
+    Ean13 = 67,
+    Ean8 = 68,
+    Code39 = 69,
+    Itf = 70,
+    Codabar = 71,
+    Code93 = 72,
+    /// Data must start with a code set selection (`{A`, `{B` or `{C`)
+    Code128 = 73,
+}
+
+impl BarcodeSystem {
+    /// Bytes allowed in the bar code data
+    fn charset(self) -> RangeInclusive<u8> {
+        match self {
+            BarcodeSystem::UpcA
+            | BarcodeSystem::UpcE
+            | BarcodeSystem::Ean13
+            | BarcodeSystem::Ean8
+            | BarcodeSystem::Itf => b'0'..=b'9',
+            _ => 0x00..=0x7F,
+        }
+    }
+}
+
+/// QR code error correction level
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QrCorrection {
+    /// Recovers 7% of the data
+    L = 48,
+    /// Recovers 15% of the data
+    M = 49,
+    /// Recovers 25% of the data
+    Q = 50,
+    /// Recovers 30% of the data
+    H = 51,
+}
+
+#[derive(Debug, Clone)]
 pub struct PrinterBuilder {
     vendor_id: u16,
     product_id: u16,
     timeout: Duration,
+    profile: Option<Profile>,
+    serial_number: Option<String>,
+    path: Option<String>,
 }
 
 impl PrinterBuilder {
@@ -48,6 +194,25 @@ impl PrinterBuilder {
         self
     }
 
+    /// Use the given capability profile instead of looking one up by the USB ids
+    pub fn profile(mut self, profile: Profile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    /// Only connect to the printer with this serial number
+    pub fn serial_number(mut self, serial_number: impl Into<String>) -> Self {
+        self.serial_number = Some(serial_number.into());
+        self
+    }
+
+    /// Only connect to the printer plugged into this port, as given by
+    /// [`PrinterInfo::path`](discovery::PrinterInfo::path)
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
     /// Finalize the builder and connect to the printer at the given path
     #[instrument(skip(context))]
     pub fn connect(self, context: &Context) -> Result<Option<Printer>, ConnectionError> {
@@ -60,53 +225,88 @@ impl PrinterBuilder {
                 continue;
             }
 
-            let config_descriptor = device.active_config_descriptor()?;
-
-            let mut detected_endpoint = None;
-
-            // TODO: ew
-            'epl: for interface in config_descriptor.interfaces() {
-                for descriptor in interface.descriptors() {
-                    for endpoint in descriptor.endpoint_descriptors() {
-                        if endpoint.transfer_type() == TransferType::Bulk
-                            && endpoint.direction() == Direction::Out
-                        {
-                            detected_endpoint = Some(endpoint.number());
-                            break 'epl;
-                        }
-                    }
+            if let Some(path) = &self.path {
+                if discovery::path(&device) != *path {
+                    continue;
                 }
             }
 
-            let endpoint = detected_endpoint.ok_or(ConnectionError::NoBulkEndpoint)?;
-
-            let mut handle = device.open()?;
-
-            if let Ok(active) = handle.kernel_driver_active(0) {
-                if active {
-                    handle.detach_kernel_driver(0)?;
+            if let Some(serial_number) = &self.serial_number {
+                if discovery::serial_number(&device, &dd).as_ref() != Some(serial_number) {
+                    continue;
                 }
-            } else {
-                warn!("Unable to detect kernel driver. This may cause issues later");
             }
 
-            handle.claim_interface(0)?;
+            let usb_device = UsbDevice::open(&device, self.timeout)?;
+
+            let profile = self.profile.clone().unwrap_or_else(|| {
+                Profile::for_device(self.vendor_id, self.product_id)
+                    .cloned()
+                    .unwrap_or_default()
+            });
 
-            return Ok(Some(Printer {
-                endpoint,
-                handle,
-                timeout: self.timeout,
-            }));
+            return Ok(Some(Printer::with_profile(usb_device, profile)));
         }
 
         Ok(None)
     }
 }
 
-pub struct Printer {
-    endpoint: u8,
-    handle: DeviceHandle<Context>,
-    timeout: Duration,
+/// Printer settings that affect layout, tracked so positions can be given in dots
+#[derive(Debug, Clone, Copy, Default)]
+struct State {
+    /// Motion units in units per inch, 0 for the printer default (the dot pitch)
+    horizontal_unit: u8,
+    vertical_unit: u8,
+    /// Left margin in dots
+    left_margin: u32,
+    /// Print area width in dots, `None` for the full paper width
+    print_width: Option<u32>,
+    /// If page mode (or double-density page mode) is selected
+    page_mode: bool,
+    /// Selected font and character size multipliers (0 for 1x)
+    font: Option<Font>,
+    width: u8,
+    height: u8,
+}
+
+pub struct Printer<D: Device = UsbDevice> {
+    device: D,
+    profile: Profile,
+    state: Cell<State>,
+    glyphs: RefCell<glyph::Glyphs>,
+}
+
+impl<D: Device> Printer<D> {
+    /// Create a printer that writes its commands to the given device
+    pub fn new(device: D) -> Self {
+        Self::with_profile(device, Profile::default())
+    }
+
+    /// Create a printer with the capabilities of a specific printer model
+    pub fn with_profile(device: D, profile: Profile) -> Self {
+        Self {
+            device,
+            profile,
+            state: Cell::new(State::default()),
+            glyphs: RefCell::default(),
+        }
+    }
+
+    /// The device the printer writes to
+    pub fn device(&self) -> &D {
+        &self.device
+    }
+
+    /// The capabilities of the printer
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    /// Take back the device the printer writes to
+    pub fn into_device(self) -> D {
+        self.device
+    }
 }
 
 impl Printer {
@@ -116,31 +316,110 @@ impl Printer {
             vendor_id,
             product_id,
             timeout: Duration::from_secs(10),
+            profile: None,
+            serial_number: None,
+            path: None,
         }
     }
 }
 
-impl Printer {
+impl<D: Device> Printer<D> {
     const ESC: u8 = 0x1B;
     const GS: u8 = 0x1D;
     const FS: u8 = 0x1C;
     const FF: u8 = 0x0C;
+    const CAN: u8 = 0x18;
 
-    // TODO: 2 column
+    /// How long to wait for the printer to answer a request
+    const REPLY_TIMEOUT: Duration = Duration::from_secs(1);
 
-    /// Write raw bytes to the printer
-    fn raw<D: AsRef<[u8]>>(&self, data: D) -> Result<(), PrinterError> {
-        self.handle
-            .write_bulk(self.endpoint, data.as_ref(), self.timeout)?;
+    /// Update the tracked printer settings
+    fn update_state(&self, update: impl FnOnce(&mut State)) {
+        let mut state = self.state.get();
+        update(&mut state);
+        self.state.set(state);
+    }
+
+    /// Convert dots to horizontal motion units
+    fn horizontal_units(&self, dots: u32) -> u32 {
+        match self.state.get().horizontal_unit {
+            0 => dots,
+            unit => dots * unit as u32 / self.profile.dpi,
+        }
+    }
+
+    /// Convert dots to vertical motion units
+    fn vertical_units(&self, dots: u32) -> u32 {
+        match self.state.get().vertical_unit {
+            0 => dots,
+            unit => dots * unit as u32 / self.profile.dpi,
+        }
+    }
+
+    /// Convert dots to motion units with `to_units`, failing if they do not
+    /// fit in a command
+    fn units(dots: u16, to_units: impl Fn(u32) -> u32) -> Result<u16, PrinterError> {
+        let units = to_units(dots as u32);
+
+        u16::try_from(units).map_err(|_| PrinterError::OutOfRange(units as i64))
+    }
+
+    /// Convert a signed distance in dots to motion units with `to_units`,
+    /// failing if they do not fit in a command
+    fn signed_units(dots: i16, to_units: impl Fn(u32) -> u32) -> Result<i16, PrinterError> {
+        let units = to_units(dots.unsigned_abs() as u32) as i64 * dots.signum() as i64;
+
+        i16::try_from(units).map_err(|_| PrinterError::OutOfRange(units))
+    }
+
+    /// Fail unless the printer is in page mode
+    fn require_page_mode(&self, command: &'static str) -> Result<(), PrinterError> {
+        if !self.profile.page_mode {
+            return Err(PrinterError::Unsupported {
+                feature: "page mode",
+                profile: self.profile.name,
+            });
+        }
+
+        if !self.state.get().page_mode {
+            return Err(PrinterError::PageModeOnly(command));
+        }
 
         Ok(())
     }
 
+    /// The amount of characters that fit on a line
+    ///
+    /// Takes the selected font, character width, left margin and print area
+    /// width into account.
+    pub fn columns(&self) -> usize {
+        let state = self.state.get();
+        let font = state.font.unwrap_or(Font::A);
+
+        let available = self.profile.paper_width.saturating_sub(state.left_margin);
+        let width = state
+            .print_width
+            .map_or(available, |width| width.min(available));
+        let character = self.profile.character_width(font) * (state.width as u32 + 1);
+
+        (width / character) as usize
+    }
+
+    /// Write raw bytes to the printer
+    fn raw<B: AsRef<[u8]>>(&self, data: B) -> Result<(), PrinterError> {
+        self.device.write(data.as_ref())
+    }
+
+    /// Send the commands recorded in a document to the printer
+    pub fn submit(&self, document: &Document) -> Result<(), PrinterError> {
+        self.raw(document.to_vec())
+    }
+
     // Print some text
     pub fn print<S: AsRef<str> + Debug>(&self, text: S) -> Result<(), TextError<S>> {
-        let cp437 = text.as_ref().to_cp437(&CP437_CONTROL);
+        let encoded = self.encode_text(text.as_ref());
 
-        match cp437 {
+        match encoded {
             Ok(data) => Ok(self.raw(&data)?),
             Err(err) => Err(TextError::Cp437 { err, text }),
         }
@@ -161,7 +440,11 @@ impl Printer {
     //
     // ASCII: FF
     pub fn form_feed(&self) -> Result<(), PrinterError> {
-        self.raw([Self::FF])
+        self.raw([Self::FF])?;
+        // In page mode, FF prints the page and returns to standard mode
+        self.update_state(|state| state.page_mode = false);
+
+        Ok(())
     }
 
     /// Initialize printer
@@ -180,19 +463,291 @@ impl Printer {
     ///
     /// ASCII: ESC @
     pub fn init(&self) -> Result<(), PrinterError> {
-        self.raw([Self::ESC, b'@'])
+        self.raw([Self::ESC, b'@'])?;
+        self.state.set(State::default());
+        self.clear_glyphs();
+
+        Ok(())
     }
 
     /// Select cut mode and cut paper
     ///
-    /// Executes paper cutting
+    /// Executes paper cutting in the given mode, failing if the cutter of the
+    /// printer does not support it
+    ///
+    /// ASCII: GS V **m** [**n**]
+    pub fn cut(&self, mode: CutMode) -> Result<(), PrinterError> {
+        let unsupported = |feature| PrinterError::Unsupported {
+            feature,
+            profile: self.profile.name,
+        };
+
+        let cutter = self
+            .profile
+            .cutter
+            .ok_or_else(|| unsupported("paper cutting"))?;
+
+        let cut_type = match mode {
+            CutMode::Immediate(cut_type) | CutMode::Feed(cut_type, _) => cut_type,
+            CutMode::Reserve(cut_type, _) | CutMode::FeedAndReverse(cut_type, _) => {
+                if !cutter.reserve {
+                    return Err(unsupported("reserving the cut position"));
+                }
+
+                cut_type
+            }
+        };
+
+        match cut_type {
+            CutType::Full if !cutter.full => return Err(unsupported("full cut")),
+            CutType::Partial if !cutter.partial => return Err(unsupported("partial cut")),
+            _ => {}
+        }
+
+        // Each function has its full cut variant followed by the partial cut variant
+        let partial = (cut_type == CutType::Partial) as u8;
+
+        match mode {
+            CutMode::Immediate(_) => self.raw([Self::GS, b'V', 48 + partial]),
+            CutMode::Feed(_, n) => self.raw([Self::GS, b'V', 65 + partial, n]),
+            CutMode::Reserve(_, n) => self.raw([Self::GS, b'V', 97 + partial, n]),
+            CutMode::FeedAndReverse(_, n) => self.raw([Self::GS, b'V', 103 + partial, n]),
+        }
+    }
+
+    /// Print and feed n lines
+    ///
+    /// Prints the data in the print buffer and feeds the paper **n** lines,
+    /// using the current line spacing
+    ///
+    /// ASCII: ESC d **n**
+    pub fn feed_lines(&self, lines: u8) -> Result<(), PrinterError> {
+        self.raw([Self::ESC, b'd', lines])
+    }
+
+    /// Print and feed paper
+    ///
+    /// Prints the data in the print buffer and feeds the paper
+    /// [**n** × vertical motion unit]
+    ///
+    /// ASCII: ESC J **n**
+    pub fn feed(&self, units: u8) -> Result<(), PrinterError> {
+        self.raw([Self::ESC, b'J', units])
+    }
+
+    /// Print and reverse feed
+    ///
+    /// Prints the data in the print buffer and feeds the paper
+    /// [**n** × vertical motion unit] in the reverse direction
+    ///
+    /// ASCII: ESC K **n**
+    pub fn reverse_feed(&self, units: u8) -> Result<(), PrinterError> {
+        if !self.profile.reverse_feed {
+            return Err(PrinterError::Unsupported {
+                feature: "reverse feeding",
+                profile: self.profile.name,
+            });
+        }
+
+        self.raw([Self::ESC, b'K', units])
+    }
+
+    /// Select line spacing
+    ///
+    /// Sets the line spacing to [**n** × vertical motion unit], or back to the
+    /// default line spacing (approximately 1/6 inch) when `None`.
+    /// Setting the spacing to the height of an image band lets consecutive
+    /// bands print without gaps.
+    ///
+    /// ASCII: ESC 2 / ESC 3 **n**
+    pub fn line_spacing(&self, spacing: Option<u8>) -> Result<(), PrinterError> {
+        match spacing {
+            Some(n) => self.raw([Self::ESC, b'3', n]),
+            None => self.raw([Self::ESC, b'2']),
+        }
+    }
+
+    /// Set horizontal and vertical motion units
+    ///
+    /// Sets the horizontal and vertical motion units to approximately
+    /// 1/**x** inch and 1/**y** inch. A value of 0 restores the default of the printer.
+    ///
+    /// ASCII: GS P **x** **y**
+    pub fn motion_units(&self, horizontal: u8, vertical: u8) -> Result<(), PrinterError> {
+        self.raw([Self::GS, b'P', horizontal, vertical])?;
+        self.update_state(|state| {
+            state.horizontal_unit = horizontal;
+            state.vertical_unit = vertical;
+        });
+
+        Ok(())
+    }
+
+    /// Set left margin
+    ///
+    /// In standard mode, sets the left margin to the given amount of dots
+    /// from the left edge of the printable area
+    ///
+    /// ASCII: GS L **nL** **nH**
+    pub fn left_margin(&self, dots: u16) -> Result<(), PrinterError> {
+        let units = Self::units(dots, |dots| self.horizontal_units(dots))?;
+        let [low, high] = units.to_le_bytes();
+
+        self.raw([Self::GS, b'L', low, high])?;
+        self.update_state(|state| state.left_margin = dots as u32);
+
+        Ok(())
+    }
+
+    /// Set print area width
+    ///
+    /// In standard mode, sets the print area width, starting from the left
+    /// margin, to the given amount of dots
     ///
-    /// ASCII: GS V **m** **n**
-    // TODO: cut pad, cut default pad, cut no pad
-    pub fn cut(&self) -> Result<(), PrinterError> {
-        // Feeds paper to (cutting position + [n × vertical motion unit]) and
-        // executes a full cut (cuts the paper completely).
-        self.raw([Self::GS, b'V', 65, 0x96])
+    /// ASCII: GS W **nL** **nH**
+    pub fn print_width(&self, dots: u16) -> Result<(), PrinterError> {
+        let units = Self::units(dots, |dots| self.horizontal_units(dots))?;
+        let [low, high] = units.to_le_bytes();
+
+        self.raw([Self::GS, b'W', low, high])?;
+        self.update_state(|state| state.print_width = Some(dots as u32));
+
+        Ok(())
+    }
+
+    /// Set absolute print position
+    ///
+    /// Moves the print position to the given amount of dots from the start of
+    /// the line (the left margin)
+    ///
+    /// ASCII: ESC $ **nL** **nH**
+    pub fn position(&self, dots: u16) -> Result<(), PrinterError> {
+        let units = Self::units(dots, |dots| self.horizontal_units(dots))?;
+        let [low, high] = units.to_le_bytes();
+
+        self.raw([Self::ESC, b'$', low, high])
+    }
+
+    /// Set relative print position
+    ///
+    /// Moves the print position the given amount of dots to the right (or to
+    /// the left when negative) of the current position
+    ///
+    /// ASCII: ESC \ **nL** **nH**
+    pub fn relative_position(&self, dots: i16) -> Result<(), PrinterError> {
+        let units = Self::signed_units(dots, |dots| self.horizontal_units(dots))?;
+        let [low, high] = units.to_le_bytes();
+
+        self.raw([Self::ESC, b'\\', low, high])
+    }
+
+    /// Set right-side character spacing
+    ///
+    /// Sets the spacing to the right of each character to the given amount of
+    /// dots. The spacing is scaled with the character size.
+    ///
+    /// ASCII: ESC SP **n**
+    pub fn character_spacing(&self, dots: u8) -> Result<(), PrinterError> {
+        let units = self.horizontal_units(dots as u32);
+        let units = u8::try_from(units).map_err(|_| PrinterError::OutOfRange(units as i64))?;
+
+        self.raw([Self::ESC, b' ', units])
+    }
+
+    /// Set horizontal tab positions
+    ///
+    /// Sets up to 32 tab stops, in characters from the start of the line. The
+    /// positions are fixed in dots using the character size at the time they
+    /// are set, and must be ascending and within the current line width.
+    /// Passing no positions clears all the tab stops.
+    ///
+    /// ASCII: ESC D **n1** ... **nk** NUL
+    pub fn tab_stops(&self, stops: &[u8]) -> Result<(), TabError> {
+        if stops.len() > 32 {
+            return Err(TabError::TooMany(stops.len()));
+        }
+
+        let columns = self.columns();
+        let mut previous = 0;
+
+        for (position, &stop) in stops.iter().enumerate() {
+            if stop <= previous {
+                return Err(TabError::NotAscending { stop, position });
+            }
+            if stop as usize >= columns {
+                return Err(TabError::OutOfRange {
+                    stop,
+                    position,
+                    columns,
+                });
+            }
+
+            previous = stop;
+        }
+
+        self.raw([Self::ESC, b'D'])?;
+        self.raw(stops)?;
+        self.raw([0x00])?;
+
+        Ok(())
+    }
+
+    /// Print cells separated by horizontal tabs, followed by a newline
+    ///
+    /// Each cell after the first starts at the next tab stop set with
+    /// [`tab_stops`](Self::tab_stops).
+    ///
+    /// ASCII: HT
+    pub fn println_tabbed<S: AsRef<str>>(&self, cells: &[S]) -> Result<(), TextError<String>> {
+        let line = cells
+            .iter()
+            .map(|cell| cell.as_ref())
+            .collect::<Vec<_>>()
+            .join("\t");
+
+        self.println(line)
+    }
+
+    /// Select character font
+    ///
+    /// ASCII: ESC M **n**
+    pub fn font(&self, font: Font) -> Result<(), PrinterError> {
+        self.raw([Self::ESC, b'M', font as u8])?;
+        self.update_state(|state| state.font = Some(font));
+
+        Ok(())
+    }
+
+    /// Select character size
+    ///
+    /// Selects the character width and height multipliers, each clamped to 1-8
+    ///
+    /// ASCII: GS ! **n**
+    pub fn character_size(&self, width: u8, height: u8) -> Result<(), PrinterError> {
+        let width = width.clamp(1, 8) - 1;
+        let height = height.clamp(1, 8) - 1;
+
+        self.raw([Self::GS, b'!', width << 4 | height])?;
+        self.update_state(|state| {
+            state.width = width;
+            state.height = height;
+        });
+
+        Ok(())
+    }
+
+    /// Turn emphasized (bold) mode on or off
+    ///
+    /// ASCII: ESC E **n**
+    pub fn emphasized(&self, on: bool) -> Result<(), PrinterError> {
+        self.raw([Self::ESC, b'E', on as u8])
+    }
+
+    /// Select underline mode
+    ///
+    /// ASCII: ESC - **n**
+    pub fn underline(&self, underline: Underline) -> Result<(), PrinterError> {
+        self.raw([Self::ESC, b'-', underline as u8])
     }
 
     /// Select justification
@@ -241,7 +796,10 @@ impl Printer {
     ///
     /// ASCII: ESC S
     pub fn standard_mode(&self) -> Result<(), PrinterError> {
-        self.raw([Self::ESC, b'S'])
+        self.raw([Self::ESC, b'S'])?;
+        self.update_state(|state| state.page_mode = false);
+
+        Ok(())
     }
 
     /// Select page mode
@@ -250,7 +808,10 @@ impl Printer {
     ///
     /// ASCII: ESC L
     pub fn page_mode(&self) -> Result<(), PrinterError> {
-        self.raw([Self::ESC, b'L'])
+        self.raw([Self::ESC, b'L'])?;
+        self.update_state(|state| state.page_mode = true);
+
+        Ok(())
     }
 
     /// Select double-density page mode
@@ -259,10 +820,108 @@ impl Printer {
     ///
     /// ASCII: FS L
     pub fn double_density_page_mode(&self) -> Result<(), PrinterError> {
-        self.raw([Self::FS, b'L'])
+        self.raw([Self::FS, b'L'])?;
+        self.update_state(|state| state.page_mode = true);
+
+        Ok(())
+    }
+
+    /// Set print area in page mode
+    ///
+    /// Sets the position and size of the print area, in dots from the top
+    /// left of the printable area
+    ///
+    /// ASCII: ESC W **xL** **xH** **yL** **yH** **dxL** **dxH** **dyL** **dyH**
+    pub fn page_area(&self, x: u16, y: u16, width: u16, height: u16) -> Result<(), PrinterError> {
+        self.require_page_mode("setting the print area")?;
+
+        let horizontal = |dots| Self::units(dots, |dots| self.horizontal_units(dots));
+        let vertical = |dots| Self::units(dots, |dots| self.vertical_units(dots));
+
+        let [x_low, x_high] = horizontal(x)?.to_le_bytes();
+        let [y_low, y_high] = vertical(y)?.to_le_bytes();
+        let [dx_low, dx_high] = horizontal(width)?.to_le_bytes();
+        let [dy_low, dy_high] = vertical(height)?.to_le_bytes();
+
+        self.raw([
+            Self::ESC,
+            b'W',
+            x_low,
+            x_high,
+            y_low,
+            y_high,
+            dx_low,
+            dx_high,
+            dy_low,
+            dy_high,
+        ])
+    }
+
+    /// Select print direction in page mode
+    ///
+    /// Selects the print direction and starting position of the print area
+    ///
+    /// ASCII: ESC T **n**
+    pub fn print_direction(&self, direction: PrintDirection) -> Result<(), PrinterError> {
+        self.require_page_mode("selecting the print direction")?;
+
+        self.raw([Self::ESC, b'T', direction as u8])
+    }
+
+    /// Set absolute vertical print position in page mode
+    ///
+    /// Moves the vertical print position to the given amount of dots from the
+    /// starting position of the print area
+    ///
+    /// ASCII: GS $ **nL** **nH**
+    pub fn vertical_position(&self, dots: u16) -> Result<(), PrinterError> {
+        self.require_page_mode("vertical positioning")?;
+
+        let units = Self::units(dots, |dots| self.vertical_units(dots))?;
+        let [low, high] = units.to_le_bytes();
+
+        self.raw([Self::GS, b'$', low, high])
     }
 
-    pub fn barcode_upc_a<D: AsRef<[u8]> + Debug>(&self, data: D) -> Result<(), BarcodeError<D>> {
+    /// Set relative vertical print position in page mode
+    ///
+    /// Moves the vertical print position the given amount of dots down (or up
+    /// when negative) from the current position
+    ///
+    /// ASCII: GS \ **nL** **nH**
+    pub fn relative_vertical_position(&self, dots: i16) -> Result<(), PrinterError> {
+        self.require_page_mode("vertical positioning")?;
+
+        let units = Self::signed_units(dots, |dots| self.vertical_units(dots))?;
+        let [low, high] = units.to_le_bytes();
+
+        self.raw([Self::GS, b'\\', low, high])
+    }
+
+    /// Print data in page mode
+    ///
+    /// Prints all the data buffered in the print area, staying in page mode
+    /// and keeping the buffered data
+    ///
+    /// ASCII: ESC FF
+    pub fn print_page(&self) -> Result<(), PrinterError> {
+        self.require_page_mode("printing the page")?;
+
+        self.raw([Self::ESC, Self::FF])
+    }
+
+    /// Cancel print data in page mode
+    ///
+    /// Deletes all the print data in the current print area
+    ///
+    /// ASCII: CAN
+    pub fn cancel_page(&self) -> Result<(), PrinterError> {
+        self.require_page_mode("cancelling the page")?;
+
+        self.raw([Self::CAN])
+    }
+
+    pub fn barcode_upc_a<B: AsRef<[u8]> + Debug>(&self, data: B) -> Result<(), BarcodeError<B>> {
         let data_len = data.as_ref().len();
 
         if !(11..=12).contains(&data_len) {
@@ -272,17 +931,35 @@ impl Printer {
             });
         }
 
-        for (i, &c) in data.as_ref().iter().enumerate() {
-            match c {
-                b'0'..=b'9' => {}
-                _ => {
-                    return Err(BarcodeError::InvalidChar {
-                        data,
-                        position: i,
-                        range: b'0'..=b'9',
-                    })
-                }
-            }
+        self.barcode(BarcodeSystem::UpcA, data)
+    }
+
+    /// Print a bar code
+    ///
+    /// The human readable characters are printed below the bar code.
+    ///
+    /// ASCII: GS k **m** **n** **d1** ... **dn**
+    pub fn barcode<B: AsRef<[u8]> + Debug>(
+        &self,
+        system: BarcodeSystem,
+        data: B,
+    ) -> Result<(), BarcodeError<B>> {
+        let data_len = data.as_ref().len();
+
+        if !(1..=255).contains(&data_len) {
+            return Err(BarcodeError::InvalidSize {
+                expected: 1..=255,
+                provided: data_len,
+            });
+        }
+
+        let range = system.charset();
+        if let Some(position) = data.as_ref().iter().position(|c| !range.contains(c)) {
+            return Err(BarcodeError::InvalidChar {
+                data,
+                position,
+                range,
+            });
         }
 
         self.carriage_reset(false)?;
@@ -291,11 +968,175 @@ impl Printer {
         self.raw([0x1D, 0x48, 0x02])?; // GS H (Below the bar code)
 
         // Print bar code
-        self.raw([0x1D, 0x6B, 65, data_len as u8])?; // GS k UPC-A (B) n
+        self.raw([0x1D, 0x6B, system as u8, data_len as u8])?; // GS k m n
 
         // Bar code data
         self.raw(data.as_ref())?; // d1 ... dn
 
         Ok(())
     }
+
+    /// Print a QR code
+    ///
+    /// Stores the data in the symbol storage area with the given module size
+    /// (1-16 dots) and error correction level, then prints it.
+    ///
+    /// ASCII: GS ( k **pL** **pH** **cn** **fn** [**parameters**]
+    pub fn qr_code<B: AsRef<[u8]> + Debug>(
+        &self,
+        data: B,
+        module_size: u8,
+        correction: QrCorrection,
+    ) -> Result<(), BarcodeError<B>> {
+        let data_len = data.as_ref().len();
+
+        if !(1..=7089).contains(&data_len) {
+            return Err(BarcodeError::InvalidSize {
+                expected: 1..=7089,
+                provided: data_len,
+            });
+        }
+
+        let store_len = (data_len + 3) as u16;
+
+        // Select the model (model 2)
+        self.raw([Self::GS, b'(', b'k', 4, 0, 49, 65, 50, 0])?;
+        // Set the size of the module
+        self.raw([Self::GS, b'(', b'k', 3, 0, 49, 67, module_size.clamp(1, 16)])?;
+        // Select the error correction level
+        self.raw([Self::GS, b'(', b'k', 3, 0, 49, 69, correction as u8])?;
+        // Store the data
+        self.raw([Self::GS, b'(', b'k'])?;
+        self.raw(store_len.to_le_bytes())?;
+        self.raw([49, 80, 48])?;
+        self.raw(data.as_ref())?;
+        // Print the symbol
+        self.raw([Self::GS, b'(', b'k', 3, 0, 49, 81, 48])?;
+
+        Ok(())
+    }
+
+    /// Print a raster bit image
+    ///
+    /// Tall images are sent in bands, as printers limit the height of a
+    /// single image.
+    ///
+    /// ASCII: GS v 0 **m** **xL** **xH** **yL** **yH** **d1** ... **dk**
+    pub fn image(&self, image: &Image) -> Result<(), PrinterError> {
+        let width_bytes = image.width_bytes() as u16;
+
+        for band in image.bands(Image::MAX_BAND_HEIGHT) {
+            let height = (band.len() / width_bytes as usize) as u16;
+
+            self.raw([Self::GS, b'v', b'0', 0])?;
+            self.raw(width_bytes.to_le_bytes())?;
+            self.raw(height.to_le_bytes())?;
+            self.raw(band)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_position() {
+        let printer = Printer::document();
+
+        printer.relative_position(-10).unwrap();
+        printer.relative_position(i16::MIN).unwrap();
+        assert_eq!(
+            printer.device().to_vec(),
+            [0x1B, b'\\', 0xF6, 0xFF, 0x1B, b'\\', 0x00, 0x80]
+        );
+    }
+
+    #[test]
+    fn positions_out_of_range() {
+        let printer = Printer::document();
+
+        // Finer motion units than dots
+        printer.motion_units(255, 255).unwrap();
+
+        assert!(matches!(
+            printer.relative_position(i16::MIN),
+            Err(PrinterError::OutOfRange(_))
+        ));
+        assert!(matches!(
+            printer.relative_position(30000),
+            Err(PrinterError::OutOfRange(_))
+        ));
+        assert!(matches!(
+            printer.position(60000),
+            Err(PrinterError::OutOfRange(_))
+        ));
+        assert!(matches!(
+            printer.left_margin(60000),
+            Err(PrinterError::OutOfRange(_))
+        ));
+        assert!(matches!(
+            printer.print_width(60000),
+            Err(PrinterError::OutOfRange(_))
+        ));
+        assert!(matches!(
+            printer.character_spacing(255),
+            Err(PrinterError::OutOfRange(320))
+        ));
+        assert_eq!(printer.device().to_vec(), [0x1D, b'P', 255, 255]);
+
+        printer.relative_position(-203).unwrap();
+        printer.character_spacing(203).unwrap();
+        assert_eq!(
+            printer.device().to_vec()[4..],
+            [0x1B, b'\\', 0x01, 0xFF, 0x1B, b' ', 255]
+        );
+    }
+
+    #[test]
+    fn page_area_out_of_range() {
+        let printer = Printer::document();
+        printer.page_mode().unwrap();
+        printer.motion_units(255, 255).unwrap();
+
+        for (x, y, width, height) in [
+            (60000, 0, 100, 100),
+            (0, 60000, 100, 100),
+            (0, 0, 60000, 100),
+            (0, 0, 100, 60000),
+        ] {
+            assert!(matches!(
+                printer.page_area(x, y, width, height),
+                Err(PrinterError::OutOfRange(_))
+            ));
+        }
+        assert_eq!(printer.device().len(), 6);
+
+        printer.page_area(0, 203, 203, 406).unwrap();
+        assert_eq!(
+            printer.device().to_vec()[6..],
+            [0x1B, b'W', 0, 0, 0xFF, 0, 0xFF, 0, 0xFE, 0x01]
+        );
+    }
+
+    #[test]
+    fn vertical_positions_out_of_range() {
+        let printer = Printer::document();
+        printer.page_mode().unwrap();
+
+        printer.relative_vertical_position(i16::MIN).unwrap();
+        assert_eq!(printer.device().to_vec()[2..], [0x1D, b'\\', 0x00, 0x80]);
+
+        printer.motion_units(255, 255).unwrap();
+        assert!(matches!(
+            printer.relative_vertical_position(i16::MIN),
+            Err(PrinterError::OutOfRange(_))
+        ));
+        assert!(matches!(
+            printer.vertical_position(60000),
+            Err(PrinterError::OutOfRange(_))
+        ));
+    }
 }
@@ -0,0 +1,156 @@
+//! Macros stored on the printer
+//!
+//! A macro holds up to 2048 bytes of commands, like the header every receipt
+//! starts with, so it only has to be sent once:
+//!
+//! ```
+//! # use std::error::Error;
+//! use std::time::Duration;
+//! use thermal::{macros::MacroMode, Justification, Printer};
+//!
+//! # fn main() -> Result<(), Box<dyn Error>> {
+//! let printer = Printer::document();
+//! printer.record_macro(|header| -> Result<(), Box<dyn Error>> {
+//!     header.justify(Justification::Center)?;
+//!     header.println("Corner Cafe")?;
+//!     header.justify(Justification::Left)?;
+//!     Ok(())
+//! })?;
+//!
+//! printer.execute_macro(1, Duration::ZERO, MacroMode::Continuous)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The macro is kept until it is redefined or the printer is turned off.
+
+use std::time::Duration;
+
+use crate::{
+    error::{MacroError, PrinterError},
+    Device, Document, Printer,
+};
+
+/// The largest macro the printer can store, in bytes
+pub const MAX_MACRO_LEN: usize = 2048;
+
+/// How a macro is repeated
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MacroMode {
+    /// Wait between each run, then run again
+    Continuous = 0x00,
+    /// Wait, then blink the LED until the feed button is pressed before each
+    /// run
+    Button = 0x01,
+}
+
+impl<D: Device> Printer<D> {
+    /// Start/end of macro definition
+    ///
+    /// Starts recording a macro, or ends it when recording has started
+    ///
+    /// ASCII: GS :
+    pub fn macro_definition(&self) -> Result<(), PrinterError> {
+        self.raw([Self::GS, b':'])
+    }
+
+    /// Define a macro from the commands recorded in a document
+    pub fn define_macro(&self, document: &Document) -> Result<(), MacroError> {
+        if document.len() > MAX_MACRO_LEN {
+            return Err(MacroError::TooLong(document.len()));
+        }
+
+        self.macro_definition()?;
+        self.submit(document)?;
+        self.macro_definition()?;
+
+        Ok(())
+    }
+
+    /// Record the commands sent to `commands` as a macro
+    ///
+    /// The commands are only stored, so the layout state of the printer does
+    /// not change until the macro is executed.
+    pub fn record_macro<F, E>(&self, commands: F) -> Result<(), E>
+    where
+        F: FnOnce(&Printer<Document>) -> Result<(), E>,
+        E: From<MacroError>,
+    {
+        let buffer = self.buffer();
+        commands(&buffer)?;
+
+        Ok(self.define_macro(buffer.device())?)
+    }
+
+    /// Execute macro
+    ///
+    /// Runs the macro `times` times, waiting [**t** × 100 ms] before each run.
+    /// The wait is rounded down to 100 ms and limited to 25.5 s. Running a
+    /// macro moves the printer to an unknown layout state, so the tracked
+    /// state goes back to the defaults and settings the macro changes have to
+    /// be set again afterwards.
+    ///
+    /// ASCII: GS ^ **r** **t** **m**
+    pub fn execute_macro(
+        &self,
+        times: u8,
+        wait: Duration,
+        mode: MacroMode,
+    ) -> Result<(), PrinterError> {
+        let wait = (wait.as_millis() / 100).min(255) as u8;
+
+        self.raw([Self::GS, b'^', times, wait, mode as u8])?;
+        self.state.set(Default::default());
+        self.clear_glyphs();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockDevice;
+
+    #[test]
+    fn define() {
+        let printer = Printer::new(MockDevice::new());
+        let header = Printer::document();
+        header.println("Corner Cafe").unwrap();
+
+        printer.define_macro(header.device()).unwrap();
+
+        let mut expected = vec![0x1D, b':'];
+        expected.extend(b"Corner Cafe\n");
+        expected.extend([0x1D, b':']);
+        assert_eq!(printer.device().written(), expected);
+    }
+
+    #[test]
+    fn too_long() {
+        let printer = Printer::new(MockDevice::new());
+        let header = Printer::document();
+        header.raw(vec![b'-'; MAX_MACRO_LEN + 1]).unwrap();
+
+        assert!(matches!(
+            printer.define_macro(header.device()),
+            Err(MacroError::TooLong(2049))
+        ));
+        assert!(printer.device().written().is_empty());
+    }
+
+    #[test]
+    fn execute() {
+        let printer = Printer::new(MockDevice::new());
+        printer.left_margin(64).unwrap();
+        printer.device().take_written();
+
+        printer
+            .execute_macro(3, Duration::from_millis(1250), MacroMode::Button)
+            .unwrap();
+
+        assert_eq!(printer.device().written(), [0x1D, b'^', 3, 12, 1]);
+        assert_eq!(printer.state.get().left_margin, 0);
+    }
+}
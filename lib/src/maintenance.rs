@@ -0,0 +1,183 @@
+//! Maintenance counters
+//!
+//! The printer counts how much its wearing parts have been used. Every
+//! counter exists twice: a resettable one, to be reset when the part is
+//! serviced, and a cumulative one covering the lifetime of the printer.
+
+use crate::{
+    error::{NvError, PrinterError},
+    Device, Printer,
+};
+
+/// Added to the number of a resettable counter to get the cumulative one
+const CUMULATIVE: u16 = 0x80;
+
+/// A maintenance counter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaintenanceCounter {
+    /// Lines of paper fed
+    LineFeeds,
+    /// Times the print head was energized, a measure of head wear
+    HeadEnergizing,
+    /// Autocutter operations
+    Cuts,
+    /// Hours the printer has been turned on
+    OperatingHours,
+}
+
+/// All maintenance counters of a printer, resettable or cumulative
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MaintenanceCounters {
+    pub line_feeds: u32,
+    pub head_energizing: u32,
+    pub cuts: u32,
+    pub operating_hours: u32,
+}
+
+impl MaintenanceCounter {
+    /// Number of the resettable counter
+    fn number(self) -> u16 {
+        match self {
+            MaintenanceCounter::LineFeeds => 20,
+            MaintenanceCounter::HeadEnergizing => 21,
+            MaintenanceCounter::Cuts => 50,
+            MaintenanceCounter::OperatingHours => 70,
+        }
+    }
+}
+
+impl<D: Device> Printer<D> {
+    fn require_maintenance_counters(&self) -> Result<(), PrinterError> {
+        if !self.profile.maintenance_counters {
+            return Err(PrinterError::Unsupported {
+                feature: "maintenance counters",
+                profile: self.profile.name,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Initialize maintenance counter
+    ///
+    /// Resets the resettable counter to 0, leaving the cumulative one alone
+    ///
+    /// ASCII: GS g 0 **m** **nL** **nH**
+    pub fn reset_maintenance_counter(
+        &self,
+        counter: MaintenanceCounter,
+    ) -> Result<(), PrinterError> {
+        self.require_maintenance_counters()?;
+
+        let [n_low, n_high] = counter.number().to_le_bytes();
+        self.raw([Self::GS, b'g', b'0', 0, n_low, n_high])
+    }
+
+    /// Transmit maintenance counter
+    ///
+    /// ASCII: GS g 2 **m** **nL** **nH**
+    pub fn maintenance_counter(
+        &self,
+        counter: MaintenanceCounter,
+        cumulative: bool,
+    ) -> Result<u32, NvError> {
+        self.require_maintenance_counters()?;
+
+        let number = counter.number() + if cumulative { CUMULATIVE } else { 0 };
+        let [n_low, n_high] = number.to_le_bytes();
+        self.raw([Self::GS, b'g', b'2', 0, n_low, n_high])?;
+
+        // The value in decimal, after a header
+        let reply = self.read_reply(1, Self::REPLY_TIMEOUT)?;
+        match reply.as_slice() {
+            [0x5F, digits @ ..] => std::str::from_utf8(digits)
+                .ok()
+                .and_then(|digits| digits.parse().ok())
+                .ok_or_else(|| NvError::InvalidReply(reply.clone())),
+            _ => Err(NvError::InvalidReply(reply)),
+        }
+    }
+
+    /// Read the resettable maintenance counters
+    pub fn maintenance_counters(&self) -> Result<MaintenanceCounters, NvError> {
+        self.read_maintenance_counters(false)
+    }
+
+    /// Read the cumulative maintenance counters
+    pub fn cumulative_maintenance_counters(&self) -> Result<MaintenanceCounters, NvError> {
+        self.read_maintenance_counters(true)
+    }
+
+    fn read_maintenance_counters(&self, cumulative: bool) -> Result<MaintenanceCounters, NvError> {
+        Ok(MaintenanceCounters {
+            line_feeds: self.maintenance_counter(MaintenanceCounter::LineFeeds, cumulative)?,
+            head_energizing: self
+                .maintenance_counter(MaintenanceCounter::HeadEnergizing, cumulative)?,
+            cuts: self.maintenance_counter(MaintenanceCounter::Cuts, cumulative)?,
+            operating_hours: self
+                .maintenance_counter(MaintenanceCounter::OperatingHours, cumulative)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{profile::Profile, MockDevice};
+
+    #[test]
+    fn counter() {
+        let printer = Printer::with_profile(MockDevice::new(), Profile::EPSON_TM_H6000);
+        printer.device().reply(b"_1234\0");
+
+        assert_eq!(
+            printer
+                .maintenance_counter(MaintenanceCounter::LineFeeds, false)
+                .unwrap(),
+            1234
+        );
+        assert_eq!(printer.device().written(), [0x1D, b'g', b'2', 0, 20, 0]);
+    }
+
+    #[test]
+    fn cumulative_counter() {
+        let printer = Printer::with_profile(MockDevice::new(), Profile::EPSON_TM_H6000);
+        printer.device().reply(b"_7\0");
+
+        assert_eq!(
+            printer
+                .maintenance_counter(MaintenanceCounter::Cuts, true)
+                .unwrap(),
+            7
+        );
+        assert_eq!(
+            printer.device().written(),
+            [0x1D, b'g', b'2', 0, 50 + 0x80, 0]
+        );
+    }
+
+    #[test]
+    fn invalid_reply() {
+        let printer = Printer::with_profile(MockDevice::new(), Profile::EPSON_TM_H6000);
+        printer.device().reply(b"_12a\0");
+        printer.device().reply(b"x12\0");
+
+        for _ in 0..2 {
+            assert!(matches!(
+                printer.maintenance_counter(MaintenanceCounter::OperatingHours, false),
+                Err(NvError::InvalidReply(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn unsupported() {
+        let printer = Printer::with_profile(MockDevice::new(), Profile::POS_80);
+
+        assert!(matches!(
+            printer.maintenance_counter(MaintenanceCounter::Cuts, false),
+            Err(NvError::Printer(PrinterError::Unsupported { .. }))
+        ));
+        assert!(printer.device().written().is_empty());
+    }
+}
@@ -0,0 +1,541 @@
+//! Markdown rendering
+//!
+//! Prints CommonMark text the way it would look in a chat message:
+//!
+//! - Headings are printed in double size
+//! - **Strong** text is emphasized and *emphasized* text is underlined, as
+//!   printers have no italics
+//! - Lists and block quotes are indented, and paragraphs are wrapped between
+//!   words
+//! - Code blocks are printed in font B
+//! - Links are numbered, with a QR code for every link printed at the end
+//!
+//! Characters the printer does not have, like emoji, are printed as `?` so
+//! any message can be printed. Characters downloaded with
+//! [`define_glyphs`](Printer::define_glyphs) are printed as defined.
+
+use pulldown_cmark::{CodeBlockKind, Event, HeadingLevel, Options, Parser, Tag};
+
+use crate::{
+    error::{BarcodeError, PrinterError, TextError},
+    wrap::{Piece, Wrap},
+    Device, Document, Font, Justification, Printer, Profile, QrCorrection, Underline,
+};
+
+/// Module size of the QR codes printed for links, in dots
+const QR_MODULE_SIZE: u8 = 4;
+
+/// Render Markdown into a document for the given profile
+pub fn compile(source: &str, profile: &Profile) -> Result<Document, PrinterError> {
+    let printer = Printer::with_profile(Document::new(), profile.clone());
+
+    printer.markdown(source)?;
+
+    Ok(printer.into_device())
+}
+
+impl<D: Device> Printer<D> {
+    /// Print Markdown text
+    pub fn markdown(&self, source: &str) -> Result<(), PrinterError> {
+        let buffer = self.buffer();
+
+        Renderer::new(&buffer).run(source)?;
+
+        self.submit_buffer(&buffer)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Style {
+    bold: bool,
+    underline: bool,
+}
+
+/// A level of indentation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Indent {
+    Quote,
+    /// A list item, indented by the width of its marker
+    Item(usize),
+}
+
+struct Renderer<'a> {
+    printer: &'a Printer<Document>,
+    /// Inline text of the current block
+    spans: Vec<(char, Style)>,
+    strong: usize,
+    emphasis: usize,
+    heading: bool,
+    /// Style the printer is currently set to
+    current: Style,
+    indents: Vec<Indent>,
+    /// Next number of each ordered list, `None` for bullet lists
+    lists: Vec<Option<u64>>,
+    /// Marker to print on the first line of the current list item
+    marker: Option<String>,
+    code: Option<String>,
+    /// Destinations of the links being read
+    open_links: Vec<String>,
+    links: Vec<String>,
+    /// Separate the next block with a blank line
+    blank_line: bool,
+}
+
+impl<'a> Renderer<'a> {
+    fn new(printer: &'a Printer<Document>) -> Self {
+        Self {
+            printer,
+            spans: Vec::new(),
+            strong: 0,
+            emphasis: 0,
+            heading: false,
+            current: Style::default(),
+            indents: Vec::new(),
+            lists: Vec::new(),
+            marker: None,
+            code: None,
+            open_links: Vec::new(),
+            links: Vec::new(),
+            blank_line: false,
+        }
+    }
+
+    fn run(mut self, source: &str) -> Result<(), PrinterError> {
+        let options = Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+
+        for event in Parser::new_ext(source, options) {
+            self.event(event)?;
+        }
+
+        self.flush()?;
+        self.print_links()
+    }
+
+    fn event(&mut self, event: Event) -> Result<(), PrinterError> {
+        match event {
+            Event::Start(Tag::Paragraph) => self.start_block()?,
+            Event::End(Tag::Paragraph) => self.end_block()?,
+            Event::Start(Tag::Heading(level, ..)) => {
+                self.start_block()?;
+                self.heading = true;
+
+                match level {
+                    HeadingLevel::H1 | HeadingLevel::H2 => self.printer.character_size(2, 2)?,
+                    _ => self.printer.character_size(1, 2)?,
+                }
+            }
+            Event::End(Tag::Heading(..)) => {
+                self.end_block()?;
+                self.heading = false;
+                self.printer.character_size(1, 1)?;
+            }
+            Event::Start(Tag::BlockQuote) => {
+                self.start_block()?;
+                self.indents.push(Indent::Quote);
+            }
+            Event::End(Tag::BlockQuote) => {
+                self.end_block()?;
+                self.indents.pop();
+            }
+            Event::Start(Tag::CodeBlock(_)) => {
+                self.start_block()?;
+                self.printer.font(Font::B)?;
+                self.code = Some(String::new());
+            }
+            Event::End(Tag::CodeBlock(kind)) => {
+                let code = self.code.take().unwrap_or_default();
+
+                if let CodeBlockKind::Fenced(language) = kind {
+                    if !language.is_empty() {
+                        self.line(&format!("[{}]", language), Style::default())?;
+                    }
+                }
+                for line in code.lines() {
+                    self.line(line, Style::default())?;
+                }
+
+                self.printer.font(Font::A)?;
+                self.blank_line = true;
+            }
+            Event::Start(Tag::List(start)) => {
+                self.flush()?;
+                if self.blank_line {
+                    self.print_blank_line()?;
+                }
+                self.lists.push(start);
+            }
+            Event::End(Tag::List(_)) => {
+                self.lists.pop();
+                self.blank_line = true;
+            }
+            Event::Start(Tag::Item) => {
+                let marker = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}. ", *number - 1)
+                    }
+                    _ => "- ".to_owned(),
+                };
+
+                self.indents.push(Indent::Item(marker.chars().count()));
+                self.marker = Some(marker);
+            }
+            Event::End(Tag::Item) => {
+                self.flush()?;
+                self.indents.pop();
+                self.marker = None;
+            }
+            Event::Start(Tag::Strong) => self.strong += 1,
+            Event::End(Tag::Strong) => self.strong -= 1,
+            Event::Start(Tag::Emphasis) => self.emphasis += 1,
+            Event::End(Tag::Emphasis) => self.emphasis -= 1,
+            Event::Start(Tag::Link(_, destination, _))
+            | Event::Start(Tag::Image(_, destination, _)) => {
+                self.open_links.push(destination.into_string())
+            }
+            Event::End(Tag::Link(..)) | Event::End(Tag::Image(..)) => {
+                if let Some(destination) = self.open_links.pop() {
+                    self.link(destination);
+                }
+            }
+            Event::Text(text) => match &mut self.code {
+                Some(code) => code.push_str(&text),
+                None if self.open_links.is_empty() => self.text_with_links(&text),
+                None => self.text(&text),
+            },
+            Event::Code(code) => self.text(&code),
+            Event::Html(html) => self.text(&html),
+            Event::FootnoteReference(name) => self.text(&format!("[^{}]", name)),
+            Event::SoftBreak => self.text(" "),
+            Event::HardBreak => self.flush()?,
+            Event::Rule => {
+                self.start_block()?;
+
+                let width = self.printer.columns().saturating_sub(self.prefix_width());
+                self.line(&"─".repeat(width), Style::default())?;
+
+                self.blank_line = true;
+            }
+            Event::TaskListMarker(checked) => self.text(if checked { "[x] " } else { "[ ] " }),
+            Event::Start(_) | Event::End(_) => {}
+        }
+
+        Ok(())
+    }
+
+    fn style(&self) -> Style {
+        Style {
+            bold: self.strong > 0 || self.heading,
+            underline: self.emphasis > 0,
+        }
+    }
+
+    /// Add inline text to the current block
+    fn text(&mut self, text: &str) {
+        let style = self.style();
+
+        self.spans
+            .extend(self.printable(text).chars().map(|c| (c, style)));
+    }
+
+    /// Add inline text, treating bare URLs in it as links
+    fn text_with_links(&mut self, text: &str) {
+        for part in text.split_inclusive(char::is_whitespace) {
+            let word = part.trim_end();
+
+            if word.starts_with("http://") || word.starts_with("https://") {
+                let url = word.trim_end_matches(|c| ".,;:!?)".contains(c));
+
+                self.text(url);
+                self.link(url.to_owned());
+                self.text(&part[url.len()..]);
+            } else {
+                self.text(part);
+            }
+        }
+    }
+
+    /// Add a reference to a link, printed with a QR code at the end
+    fn link(&mut self, destination: String) {
+        if destination.is_empty() {
+            return;
+        }
+
+        let number = match self.links.iter().position(|link| *link == destination) {
+            Some(index) => index + 1,
+            None => {
+                self.links.push(destination);
+                self.links.len()
+            }
+        };
+
+        let style = Style::default();
+        self.spans
+            .extend(format!("[{}]", number).chars().map(|c| (c, style)));
+    }
+
+    fn start_block(&mut self) -> Result<(), PrinterError> {
+        self.flush()?;
+
+        if self.blank_line {
+            self.print_blank_line()?;
+        }
+
+        Ok(())
+    }
+
+    fn end_block(&mut self) -> Result<(), PrinterError> {
+        self.flush()?;
+        self.blank_line = true;
+
+        Ok(())
+    }
+
+    fn print_blank_line(&mut self) -> Result<(), PrinterError> {
+        let prefix = self.prefix(false);
+
+        self.write(&[(prefix.trim_end(), Style::default())])?;
+        self.blank_line = false;
+
+        Ok(())
+    }
+
+    /// Indentation of the next line, with the list marker if it is the first
+    /// line of a list item
+    fn prefix(&mut self, first: bool) -> String {
+        let mut prefix = String::new();
+        let marker = if first { self.marker.take() } else { None };
+
+        for (i, indent) in self.indents.iter().enumerate() {
+            match indent {
+                Indent::Quote => prefix.push_str("│ "),
+                Indent::Item(_) if i + 1 == self.indents.len() && marker.is_some() => {
+                    prefix.push_str(marker.as_deref().unwrap_or_default())
+                }
+                Indent::Item(width) => prefix.push_str(&" ".repeat(*width)),
+            }
+        }
+
+        prefix
+    }
+
+    fn prefix_width(&self) -> usize {
+        self.indents
+            .iter()
+            .map(|indent| match indent {
+                Indent::Quote => 2,
+                Indent::Item(width) => *width,
+            })
+            .sum()
+    }
+
+    /// Print a line as is, without wrapping
+    fn line(&mut self, text: &str, style: Style) -> Result<(), PrinterError> {
+        let prefix = self.prefix(true);
+
+        self.write(&[(&prefix, Style::default()), (text, style)])
+    }
+
+    /// Print the current block, wrapped between words
+    fn flush(&mut self) -> Result<(), PrinterError> {
+        if self.spans.is_empty() {
+            return Ok(());
+        }
+
+        let spans = std::mem::take(&mut self.spans);
+        let width = self
+            .printer
+            .columns()
+            .saturating_sub(self.prefix_width())
+            .max(1);
+
+        // The style of every byte of the text
+        let mut text = String::new();
+        let mut styles = Vec::new();
+        for (c, style) in spans {
+            text.push(c);
+            styles.resize(text.len(), style);
+        }
+
+        for (i, pieces) in Wrap::new().paragraph(&text, width).iter().enumerate() {
+            let mut runs = vec![(self.prefix(i == 0), Style::default())];
+
+            for piece in pieces {
+                match piece {
+                    Piece::Text(range) => {
+                        for (offset, c) in text[range.clone()].char_indices() {
+                            push(&mut runs, c, styles[range.start + offset]);
+                        }
+                    }
+                    Piece::Space(offset) => push(&mut runs, ' ', styles[*offset]),
+                    // In the style of the word it splits
+                    Piece::Hyphen => {
+                        let style = runs.last().map_or(Style::default(), |(_, style)| *style);
+                        push(&mut runs, '-', style);
+                    }
+                }
+            }
+
+            let runs: Vec<(&str, Style)> =
+                runs.iter().map(|(s, style)| (s.as_str(), *style)).collect();
+            self.write(&runs)?;
+        }
+
+        Ok(())
+    }
+
+    /// Replace the characters the printer does not have
+    fn printable(&self, text: &str) -> String {
+        text.chars()
+            .map(|c| if self.printer.can_print(c) { c } else { '?' })
+            .collect()
+    }
+
+    /// Print styled runs of text followed by a newline
+    fn write(&mut self, runs: &[(&str, Style)]) -> Result<(), PrinterError> {
+        for &(text, style) in runs.iter().chain([("\n", Style::default())].iter()) {
+            if style.bold != self.current.bold {
+                self.printer.emphasized(style.bold)?;
+            }
+            if style.underline != self.current.underline {
+                self.printer.underline(if style.underline {
+                    Underline::Single
+                } else {
+                    Underline::None
+                })?;
+            }
+            self.current = style;
+
+            match self.printer.print(self.printable(text)) {
+                Ok(()) => {}
+                Err(TextError::Printer(err)) => return Err(err),
+                Err(TextError::Cp437 { .. }) => unreachable!("unprintable characters are replaced"),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Print the destinations of the links, each with a QR code
+    fn print_links(&mut self) -> Result<(), PrinterError> {
+        let links = std::mem::take(&mut self.links);
+
+        for (i, link) in links.iter().enumerate() {
+            self.blank_line = true;
+            self.start_block()?;
+            self.text(&format!("[{}] {}", i + 1, link));
+            self.flush()?;
+
+            self.printer.justify(Justification::Center)?;
+            match self
+                .printer
+                .qr_code(link.as_bytes(), QR_MODULE_SIZE, QrCorrection::M)
+            {
+                // Links too long for a QR code are only printed as text
+                Ok(()) | Err(BarcodeError::InvalidSize { .. }) => {}
+                Err(BarcodeError::Printer(err)) => return Err(err),
+                Err(BarcodeError::InvalidChar { .. }) => unreachable!("QR codes take any byte"),
+            }
+            self.printer.justify(Justification::Left)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Add a character to styled runs of text
+fn push(runs: &mut Vec<(String, Style)>, c: char, style: Style) {
+    match runs.last_mut() {
+        Some((run, run_style)) if *run_style == style => run.push(c),
+        _ => runs.push((c.to_string(), style)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{glyph::GlyphSet, Image};
+
+    /// The printed lines of a document, without the commands
+    fn lines(document: &Document) -> Vec<String> {
+        let data = document.to_vec();
+        let mut text = Vec::new();
+        let mut rest = &data[..];
+
+        // Only ESC E, ESC - and ESC % are sent along with the text
+        while let Some((&byte, tail)) = rest.split_first() {
+            if byte == 0x1B {
+                rest = &tail[2..];
+            } else {
+                text.push(byte);
+                rest = tail;
+            }
+        }
+
+        String::from_utf8(text)
+            .unwrap()
+            .lines()
+            .map(str::to_owned)
+            .collect()
+    }
+
+    #[test]
+    fn paragraphs_are_wrapped() {
+        let profile = Profile::default();
+        let columns = Printer::with_profile(Document::new(), profile.clone()).columns();
+        let source = "lorem ipsum dolor sit amet ".repeat(10);
+
+        let lines = lines(&compile(&source, &profile).unwrap());
+
+        assert!(lines.len() > 1);
+        assert!(lines.iter().all(|line| line.len() <= columns));
+        assert_eq!(lines.join(" "), source.trim_end());
+    }
+
+    #[test]
+    fn long_words_are_hyphenated() {
+        let profile = Profile::default();
+        let columns = Printer::with_profile(Document::new(), profile.clone()).columns();
+        let word = "x".repeat(columns + 5);
+
+        let lines = lines(&compile(&format!("- {}", word), &profile).unwrap());
+
+        assert_eq!(
+            lines,
+            [
+                format!("- {}-", "x".repeat(columns - 3)),
+                format!("  {}", "x".repeat(8)),
+            ]
+        );
+    }
+
+    #[test]
+    fn styles_continue_through_spaces() {
+        let document = compile("a *b c* d", &Profile::default()).unwrap();
+
+        assert_eq!(document.to_vec(), b"a \x1B-\x01b c\x1B-\x00 d\n".to_vec());
+    }
+
+    #[test]
+    fn unprintable_characters() {
+        let document = compile("tea 🍵 ok", &Profile::default()).unwrap();
+
+        assert_eq!(document.to_vec(), b"tea ? ok\n".to_vec());
+    }
+
+    #[test]
+    fn glyphs_are_printed() {
+        let printer = Printer::document();
+        let mut glyphs = GlyphSet::new(Font::A);
+        glyphs.insert('🍵', Image::from_fn(12, 24, |x, y| x == y));
+        printer.define_glyphs(&glyphs).unwrap();
+        let start = printer.device().len();
+
+        printer.markdown("tea 🍵").unwrap();
+
+        assert_eq!(
+            &printer.device().to_vec()[start..],
+            b"tea \x1B%\x01 \x1B%\x00\n"
+        );
+    }
+}
@@ -0,0 +1,1065 @@
+//! A markup language for receipts
+//!
+//! Receipts are written as text with XML-like tags, and compiled into the
+//! commands for a printer:
+//!
+//! ```text
+//! <align value="center"><size width="2" height="2"><b>Corner Café</b></size>
+//! 12 Main Street</align>
+//! <hr/>
+//! <columns><col>Coffee</col><col width="8" align="right">3.50</col></columns>
+//! <columns><col>Croissant</col><col width="8" align="right">2.80</col></columns>
+//! <hr char="="/>
+//! <align value="center"><qr size="6">https://example.com/r/1234</qr></align>
+//! <cut/>
+//! ```
+//!
+//! Text is printed as it is written, including its line breaks, except that
+//! lines with nothing but tags on them are left out. `&lt;`, `&gt;`, `&amp;`, `&quot;`, `&apos;` and
+//! numeric character references can be used to escape text.
+//!
+//! | Tag | Attributes | |
+//! |-----|------------|-|
+//! | `<b>` | | Bold text |
+//! | `<u>` | `double` | Underlined text |
+//! | `<size>` | `width`, `height` (1-8) | Character size multipliers |
+//! | `<align>` | `value` (`left`, `center` or `right`) | Justification of whole lines, starting and ending a line |
+//! | `<barcode>` | `type` (`upca`, `upce`, `ean13`, `ean8`, `code39`, `itf`, `codabar`, `code93`, `code128`) | A bar code of the contents |
+//! | `<qr>` | `size` (1-16), `correction` (`l`, `m`, `q` or `h`) | A QR code of the contents |
+//! | `<image/>` | `src` | A PNG image, loaded through [`Images`] |
+//! | `<columns>` | `gap` | A row of `<col>` cells, see [`layout`](crate::layout) |
+//! | `<col>` | `width`, `align`, `overflow` (`truncate` or `ellipsis`) | A cell of a row |
+//! | `<hr/>` | `char` | A line across the paper |
+//! | `<br/>` | | A line break |
+//! | `<cut/>` | `type` (`full` or `partial`) | Feed and cut the paper, by default completely if the printer can |
+//!
+//! Markup does not read files by itself: images are only available when the
+//! caller passes [`Images`] to [`compile_with`] or [`Printer::markup_with`],
+//! such as a [`Directory`] of PNG files.
+
+use std::{fs::File, io::BufReader, path::PathBuf};
+
+use crate::{
+    error::{BarcodeError, MarkupError, MarkupErrorKind, TextError},
+    layout::{Column, Overflow, Table},
+    BarcodeSystem, CutMode, CutType, Device, Document, Image, Justification, Printer, Profile,
+    QrCorrection, Underline,
+};
+
+/// A piece of markup
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Text(String),
+    Open {
+        name: String,
+        attributes: Vec<(String, String)>,
+        empty: bool,
+    },
+    Close(String),
+}
+
+/// Loads the images of `<image src>` tags
+///
+/// Implemented for closures taking the `src` attribute.
+pub trait Images {
+    fn load(&self, src: &str) -> Result<Image, MarkupErrorKind>;
+}
+
+impl<F: Fn(&str) -> Result<Image, MarkupErrorKind>> Images for F {
+    fn load(&self, src: &str) -> Result<Image, MarkupErrorKind> {
+        self(src)
+    }
+}
+
+/// PNG images from a directory, with `src` relative to it
+///
+/// Paths that lead out of the directory, including through symbolic links,
+/// are rejected.
+#[derive(Debug, Clone)]
+pub struct Directory(PathBuf);
+
+impl Directory {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self(path.into())
+    }
+}
+
+impl Images for Directory {
+    fn load(&self, src: &str) -> Result<Image, MarkupErrorKind> {
+        let base = self.0.canonicalize()?;
+        let path = base.join(src).canonicalize()?;
+
+        if !path.starts_with(&base) {
+            return Err(MarkupErrorKind::ImagePath(src.to_owned()));
+        }
+
+        let file = File::open(path)?;
+
+        Ok(Image::from_png(BufReader::new(file))?)
+    }
+}
+
+/// Compile markup into a document of printer commands for the given profile
+///
+/// `<image>` tags are rejected, see [`compile_with`].
+pub fn compile(source: &str, profile: &Profile) -> Result<Document, MarkupError> {
+    let printer = Printer::with_profile(Document::new(), profile.clone());
+
+    printer.markup(source)?;
+
+    Ok(printer.into_device())
+}
+
+/// Compile markup, loading its images from `images`
+pub fn compile_with(
+    source: &str,
+    profile: &Profile,
+    images: &dyn Images,
+) -> Result<Document, MarkupError> {
+    let printer = Printer::with_profile(Document::new(), profile.clone());
+
+    printer.markup_with(source, images)?;
+
+    Ok(printer.into_device())
+}
+
+impl<D: Device> Printer<D> {
+    /// Print a receipt written in markup
+    ///
+    /// The markup is compiled completely before anything is sent, so nothing
+    /// is printed if it contains an error. `<image>` tags are rejected, see
+    /// [`markup_with`](Self::markup_with).
+    pub fn markup(&self, source: &str) -> Result<(), MarkupError> {
+        self.compile_markup(source, None)
+    }
+
+    /// Print a receipt written in markup, loading its images from `images`
+    pub fn markup_with(&self, source: &str, images: &dyn Images) -> Result<(), MarkupError> {
+        self.compile_markup(source, Some(images))
+    }
+
+    fn compile_markup(&self, source: &str, images: Option<&dyn Images>) -> Result<(), MarkupError> {
+        let buffer = self.buffer();
+
+        Compiler::new(source, &buffer, images).run()?;
+
+        self.submit_buffer(&buffer)
+            .map_err(|err| error(source, source.len(), err.into()))?;
+
+        Ok(())
+    }
+}
+
+/// Character style set by the style tags
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Style {
+    bold: bool,
+    underline: Underline,
+    width: u8,
+    height: u8,
+    justification: Justification,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            bold: false,
+            underline: Underline::None,
+            width: 1,
+            height: 1,
+            justification: Justification::Left,
+        }
+    }
+}
+
+struct Compiler<'a> {
+    source: &'a str,
+    printer: &'a Printer<Document>,
+    images: Option<&'a dyn Images>,
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    /// Open style tags, with their position and the style they replaced
+    open: Vec<(usize, String, Style)>,
+    style: Style,
+    /// Nothing has been printed on the current line
+    line_empty: bool,
+}
+
+impl<'a> Compiler<'a> {
+    fn new(
+        source: &'a str,
+        printer: &'a Printer<Document>,
+        images: Option<&'a dyn Images>,
+    ) -> Self {
+        Self {
+            source,
+            printer,
+            images,
+            tokens: Vec::new(),
+            next: 0,
+            open: Vec::new(),
+            style: Style::default(),
+            line_empty: true,
+        }
+    }
+
+    fn run(mut self) -> Result<(), MarkupError> {
+        self.tokens = tokenize(self.source)?;
+
+        while let Some((offset, token)) = self.token() {
+            match token {
+                Token::Text(text) => {
+                    // Lines with nothing but tags on them are not printed
+                    let text = if self.line_empty && offset > 0 {
+                        text.strip_prefix("\r\n")
+                            .or_else(|| text.strip_prefix('\n'))
+                            .unwrap_or(&text)
+                    } else {
+                        &text
+                    };
+
+                    self.printer
+                        .print(text)
+                        .map_err(|err| self.text_error(offset, err))?;
+                    if !text.is_empty() {
+                        self.line_empty = text.ends_with('\n');
+                    }
+                }
+                Token::Open {
+                    name,
+                    attributes,
+                    empty,
+                } => {
+                    self.open(offset, &name, &attributes, empty)?;
+                }
+                Token::Close(name) => match self.open.pop() {
+                    Some((_, open, style)) if open == name => {
+                        self.apply(offset, style)?;
+                    }
+                    Some((_, open, _)) => {
+                        return Err(self.error(
+                            offset,
+                            MarkupErrorKind::Mismatched {
+                                expected: open,
+                                found: name,
+                            },
+                        ))
+                    }
+                    None => return Err(self.error(offset, MarkupErrorKind::UnexpectedClose(name))),
+                },
+            }
+        }
+
+        match self.open.pop() {
+            Some((offset, name, _)) => Err(self.error(offset, MarkupErrorKind::Unclosed(name))),
+            None => Ok(()),
+        }
+    }
+
+    fn token(&mut self) -> Option<(usize, Token)> {
+        let token = self.tokens.get(self.next).cloned();
+        self.next += 1;
+        token
+    }
+
+    fn open(
+        &mut self,
+        offset: usize,
+        name: &str,
+        attributes: &[(String, String)],
+        empty: bool,
+    ) -> Result<(), MarkupError> {
+        let attribute = |key: &str| {
+            attributes
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value.as_str())
+        };
+        let invalid = |key: &str, value: &str| {
+            self.error(
+                offset,
+                MarkupErrorKind::InvalidAttribute {
+                    tag: name.to_owned(),
+                    attribute: key.to_owned(),
+                    value: value.to_owned(),
+                },
+            )
+        };
+        let number = |key: &str, range: std::ops::RangeInclusive<u8>, default: u8| {
+            attribute(key).map_or(Ok(default), |value| {
+                value
+                    .parse()
+                    .ok()
+                    .filter(|n| range.contains(n))
+                    .ok_or_else(|| invalid(key, value))
+            })
+        };
+
+        let mut style = self.style;
+
+        match name {
+            "b" => style.bold = true,
+            "u" => {
+                style.underline = match attribute("double") {
+                    Some(_) => Underline::Double,
+                    None => Underline::Single,
+                }
+            }
+            "size" => {
+                style.width = number("width", 1..=8, 1)?;
+                style.height = number("height", 1..=8, 1)?;
+            }
+            "align" => {
+                style.justification = match attribute("value") {
+                    Some("left") => Justification::Left,
+                    Some("center") => Justification::Center,
+                    Some("right") => Justification::Right,
+                    Some(value) => return Err(invalid("value", value)),
+                    None => return Err(self.missing(offset, name, "value")),
+                }
+            }
+            "br" => {
+                self.printer
+                    .print("\n")
+                    .map_err(|err| self.text_error(offset, err))?;
+                self.line_empty = true;
+                return self.close_empty(offset, name, empty);
+            }
+            "hr" => {
+                let c = attribute("char").unwrap_or("─");
+                let line = c.repeat(self.printer.columns() / c.chars().count().max(1));
+
+                self.new_line(offset)?;
+                self.printer
+                    .println(line)
+                    .map_err(|err| self.text_error(offset, err))?;
+                self.line_empty = true;
+                return self.close_empty(offset, name, empty);
+            }
+            "cut" => {
+                let mode = match attribute("type") {
+                    None => self.printer.profile().default_cut(),
+                    Some("full") => CutMode::Feed(CutType::Full, 0x96),
+                    Some("partial") => CutMode::Feed(CutType::Partial, 0x96),
+                    Some(value) => return Err(invalid("type", value)),
+                };
+
+                self.printer
+                    .cut(mode)
+                    .map_err(|err| self.error(offset, err.into()))?;
+                self.line_empty = true;
+                return self.close_empty(offset, name, empty);
+            }
+            "image" => {
+                let src = attribute("src").ok_or_else(|| self.missing(offset, name, "src"))?;
+                let image = self
+                    .images
+                    .ok_or(MarkupErrorKind::NoImages)
+                    .and_then(|images| images.load(src))
+                    .map_err(|err| self.error(offset, err))?;
+
+                self.new_line(offset)?;
+                self.printer
+                    .image(&image)
+                    .map_err(|err| self.error(offset, err.into()))?;
+                self.line_empty = true;
+                return self.close_empty(offset, name, empty);
+            }
+            "barcode" => {
+                let system = match attribute("type") {
+                    Some("upca") => BarcodeSystem::UpcA,
+                    Some("upce") => BarcodeSystem::UpcE,
+                    Some("ean13") => BarcodeSystem::Ean13,
+                    Some("ean8") => BarcodeSystem::Ean8,
+                    Some("code39") => BarcodeSystem::Code39,
+                    Some("itf") => BarcodeSystem::Itf,
+                    Some("codabar") => BarcodeSystem::Codabar,
+                    Some("code93") => BarcodeSystem::Code93,
+                    Some("code128") => BarcodeSystem::Code128,
+                    Some(value) => return Err(invalid("type", value)),
+                    None => return Err(self.missing(offset, name, "type")),
+                };
+                let mut data = self.content(offset, name, empty)?;
+
+                if system == BarcodeSystem::Code128 && !data.starts_with('{') {
+                    data.insert_str(0, "{B");
+                }
+
+                self.new_line(offset)?;
+                self.printer
+                    .barcode(system, data.as_bytes())
+                    .map_err(|err| self.barcode_error(offset, err))?;
+                self.line_empty = true;
+                return Ok(());
+            }
+            "qr" => {
+                let size = number("size", 1..=16, 4)?;
+                let correction = match attribute("correction") {
+                    Some("l") => QrCorrection::L,
+                    None | Some("m") => QrCorrection::M,
+                    Some("q") => QrCorrection::Q,
+                    Some("h") => QrCorrection::H,
+                    Some(value) => return Err(invalid("correction", value)),
+                };
+                let data = self.content(offset, name, empty)?;
+
+                self.new_line(offset)?;
+                self.printer
+                    .qr_code(data.as_bytes(), size, correction)
+                    .map_err(|err| self.barcode_error(offset, err))?;
+                self.line_empty = true;
+                return Ok(());
+            }
+            "columns" => {
+                let gap = number("gap", 0..=u8::MAX, 1)?;
+
+                self.new_line(offset)?;
+                self.columns(offset, gap as usize, empty)?;
+                self.line_empty = true;
+                return Ok(());
+            }
+            "col" => return Err(self.error(offset, MarkupErrorKind::Misplaced(name.to_owned()))),
+            _ => return Err(self.error(offset, MarkupErrorKind::UnknownTag(name.to_owned()))),
+        }
+
+        // A style tag
+        if !empty {
+            self.open.push((offset, name.to_owned(), self.style));
+            self.apply(offset, style)?;
+        }
+
+        Ok(())
+    }
+
+    /// Finish the current line, so a block starts at the left edge
+    fn new_line(&mut self, offset: usize) -> Result<(), MarkupError> {
+        if !self.line_empty {
+            self.printer
+                .print("\n")
+                .map_err(|err| self.text_error(offset, err))?;
+            self.line_empty = true;
+        }
+
+        Ok(())
+    }
+
+    /// Read a row of cells up to the closing </columns>
+    fn columns(&mut self, offset: usize, gap: usize, empty: bool) -> Result<(), MarkupError> {
+        let mut columns = Vec::new();
+        let mut cells = Vec::new();
+
+        if empty {
+            return Ok(());
+        }
+
+        loop {
+            match self.token() {
+                Some((_, Token::Text(text))) if text.trim().is_empty() => {}
+                Some((
+                    offset,
+                    Token::Open {
+                        name,
+                        attributes,
+                        empty,
+                    },
+                )) if name == "col" => {
+                    let attribute = |key: &str| {
+                        attributes
+                            .iter()
+                            .find(|(name, _)| name == key)
+                            .map(|(_, value)| value.as_str())
+                    };
+                    let invalid = |key: &str, value: &str| {
+                        self.error(
+                            offset,
+                            MarkupErrorKind::InvalidAttribute {
+                                tag: name.clone(),
+                                attribute: key.to_owned(),
+                                value: value.to_owned(),
+                            },
+                        )
+                    };
+
+                    let mut column = match attribute("width") {
+                        Some(value) => {
+                            Column::fixed(value.parse().map_err(|_| invalid("width", value))?)
+                        }
+                        None => Column::fill(),
+                    };
+                    column = match attribute("align") {
+                        None | Some("left") => column,
+                        Some("center") => column.justify(Justification::Center),
+                        Some("right") => column.justify(Justification::Right),
+                        Some(value) => return Err(invalid("align", value)),
+                    };
+                    column = match attribute("overflow") {
+                        None => column,
+                        Some("truncate") => column.overflow(Overflow::Truncate),
+                        Some("ellipsis") => column.overflow(Overflow::Ellipsis),
+                        Some(value) => return Err(invalid("overflow", value)),
+                    };
+
+                    columns.push(column);
+                    cells.push(self.content(offset, &name, empty)?);
+                }
+                Some((_, Token::Close(name))) if name == "columns" => break,
+                Some((offset, token)) => return Err(self.misplaced(offset, token)),
+                None => {
+                    return Err(self.error(offset, MarkupErrorKind::Unclosed("columns".to_owned())))
+                }
+            }
+        }
+
+        self.printer
+            .table_row(&Table::new(columns).gap(gap), &cells)
+            .map_err(|err| self.text_error(offset, err))
+    }
+
+    /// Read the text of a tag up to its closing tag
+    fn content(&mut self, offset: usize, name: &str, empty: bool) -> Result<String, MarkupError> {
+        let mut content = String::new();
+
+        if empty {
+            return Ok(content);
+        }
+
+        loop {
+            match self.token() {
+                Some((_, Token::Text(text))) => content.push_str(&text),
+                Some((_, Token::Close(close))) if close == name => break,
+                Some((offset, token)) => return Err(self.misplaced(offset, token)),
+                None => return Err(self.error(offset, MarkupErrorKind::Unclosed(name.to_owned()))),
+            }
+        }
+
+        Ok(content)
+    }
+
+    /// Check that a tag without content is not followed by content
+    fn close_empty(&mut self, offset: usize, name: &str, empty: bool) -> Result<(), MarkupError> {
+        if !empty && !self.content(offset, name, empty)?.is_empty() {
+            return Err(self.error(offset, MarkupErrorKind::Syntax("tag must be empty")));
+        }
+
+        Ok(())
+    }
+
+    /// Send the commands to switch to a new style
+    fn apply(&mut self, offset: usize, style: Style) -> Result<(), MarkupError> {
+        // Justification only takes effect at the start of a line
+        if style.justification != self.style.justification {
+            self.new_line(offset)?;
+        }
+
+        let printer = self.printer;
+        let current = self.style;
+
+        let result = (|| {
+            if style.bold != current.bold {
+                printer.emphasized(style.bold)?;
+            }
+            if style.underline != current.underline {
+                printer.underline(style.underline)?;
+            }
+            if (style.width, style.height) != (current.width, current.height) {
+                printer.character_size(style.width, style.height)?;
+            }
+            if style.justification != current.justification {
+                printer.justify(style.justification)?;
+            }
+
+            Ok(())
+        })();
+
+        self.style = style;
+        result.map_err(|err: crate::error::PrinterError| self.error(offset, err.into()))
+    }
+
+    fn misplaced(&self, offset: usize, token: Token) -> MarkupError {
+        match token {
+            Token::Open { name, .. } => self.error(offset, MarkupErrorKind::Misplaced(name)),
+            Token::Close(name) => self.error(offset, MarkupErrorKind::UnexpectedClose(name)),
+            Token::Text(_) => self.error(offset, MarkupErrorKind::Syntax("unexpected text")),
+        }
+    }
+
+    fn missing(&self, offset: usize, tag: &str, attribute: &'static str) -> MarkupError {
+        self.error(
+            offset,
+            MarkupErrorKind::MissingAttribute {
+                tag: tag.to_owned(),
+                attribute,
+            },
+        )
+    }
+
+    fn text_error(
+        &self,
+        offset: usize,
+        err: TextError<impl AsRef<str> + std::fmt::Debug>,
+    ) -> MarkupError {
+        match err {
+            TextError::Printer(err) => self.error(offset, err.into()),
+            err => self.error(offset, MarkupErrorKind::Text(err.to_string())),
+        }
+    }
+
+    fn barcode_error(&self, offset: usize, err: BarcodeError<&[u8]>) -> MarkupError {
+        match err {
+            BarcodeError::Printer(err) => self.error(offset, err.into()),
+            err => self.error(offset, MarkupErrorKind::Barcode(err.to_string())),
+        }
+    }
+
+    fn error(&self, offset: usize, kind: MarkupErrorKind) -> MarkupError {
+        error(self.source, offset, kind)
+    }
+}
+
+/// Create an error at a byte offset of the source
+fn error(source: &str, offset: usize, kind: MarkupErrorKind) -> MarkupError {
+    let (line, column) = position(source, offset);
+
+    MarkupError { line, column, kind }
+}
+
+/// The line and column, counting from 1, of a byte offset of the source
+pub(crate) fn position(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+/// Escape text so it is printed as is
+pub(crate) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Split markup into text and tags, with their byte offsets
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, MarkupError> {
+    let mut tokens = Vec::new();
+    let mut offset = 0;
+
+    while offset < source.len() {
+        let rest = &source[offset..];
+
+        if rest.starts_with("<!--") {
+            let end = rest.find("-->").ok_or_else(|| {
+                error(
+                    source,
+                    offset,
+                    MarkupErrorKind::Syntax("unterminated comment"),
+                )
+            })?;
+
+            offset += end + 3;
+        } else if rest.starts_with('<') {
+            let (token, len) = tag(source, offset)?;
+
+            tokens.push((offset, token));
+            offset += len;
+        } else {
+            let len = rest.find('<').unwrap_or(rest.len());
+            let text = unescape(source, offset, &rest[..len])?;
+
+            // Comments can split text in two
+            match tokens.last_mut() {
+                Some((_, Token::Text(previous))) => previous.push_str(&text),
+                _ => tokens.push((offset, Token::Text(text))),
+            }
+            offset += len;
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Parse the tag at `start`, returning it and its length
+fn tag(source: &str, start: usize) -> Result<(Token, usize), MarkupError> {
+    let mut chars = source[start..].char_indices().skip(1).peekable();
+    let position = |chars: &mut std::iter::Peekable<_>| {
+        chars
+            .peek()
+            .map_or(source.len(), |&(i, _): &(usize, char)| start + i)
+    };
+    let syntax = |offset: usize, message: &'static str| {
+        error(source, offset, MarkupErrorKind::Syntax(message))
+    };
+
+    let skip_whitespace = |chars: &mut std::iter::Peekable<
+        std::iter::Skip<std::str::CharIndices>,
+    >| { while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {} };
+    let name = |chars: &mut std::iter::Peekable<std::iter::Skip<std::str::CharIndices>>| {
+        let mut name = String::new();
+        while let Some((_, c)) =
+            chars.next_if(|&(_, c)| c.is_alphanumeric() || c == '-' || c == '_')
+        {
+            name.push(c);
+        }
+        name
+    };
+
+    let close = chars.next_if(|&(_, c)| c == '/').is_some();
+    let tag = name(&mut chars);
+
+    if tag.is_empty() {
+        return Err(syntax(position(&mut chars), "expected a tag name"));
+    }
+
+    if close {
+        skip_whitespace(&mut chars);
+
+        return match chars.next_if(|&(_, c)| c == '>') {
+            Some((i, _)) => Ok((Token::Close(tag), i + 1)),
+            _ => Err(syntax(position(&mut chars), "expected '>'")),
+        };
+    }
+
+    let mut attributes = Vec::new();
+
+    loop {
+        skip_whitespace(&mut chars);
+
+        match chars.peek().copied() {
+            Some((i, '>')) => {
+                return Ok((
+                    Token::Open {
+                        name: tag,
+                        attributes,
+                        empty: false,
+                    },
+                    i + 1,
+                ))
+            }
+            Some((_, '/')) => {
+                chars.next();
+
+                return match chars.next_if(|&(_, c)| c == '>') {
+                    Some((i, _)) => Ok((
+                        Token::Open {
+                            name: tag,
+                            attributes,
+                            empty: true,
+                        },
+                        i + 1,
+                    )),
+                    _ => Err(syntax(position(&mut chars), "expected '>'")),
+                };
+            }
+            Some(_) => {}
+            None => return Err(syntax(source.len(), "unexpected end of input")),
+        }
+
+        let key = name(&mut chars);
+        if key.is_empty() {
+            return Err(syntax(position(&mut chars), "expected an attribute name"));
+        }
+
+        skip_whitespace(&mut chars);
+
+        let value = if chars.next_if(|&(_, c)| c == '=').is_some() {
+            skip_whitespace(&mut chars);
+
+            let quote = match chars.next_if(|&(_, c)| c == '"' || c == '\'') {
+                Some((_, quote)) => quote,
+                _ => return Err(syntax(position(&mut chars), "expected a quoted value")),
+            };
+            let value_start = position(&mut chars);
+
+            loop {
+                match chars.next() {
+                    Some((i, c)) if c == quote => {
+                        let value = &source[value_start..start + i];
+                        break unescape(source, value_start, value)?;
+                    }
+                    Some(_) => {}
+                    None => return Err(syntax(value_start, "unterminated attribute value")),
+                }
+            }
+        } else {
+            String::new()
+        };
+
+        attributes.push((key, value));
+    }
+}
+
+/// Replace entities in text at `offset` of the source
+fn unescape(source: &str, offset: usize, text: &str) -> Result<String, MarkupError> {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+
+        let entity_offset = offset + (text.len() - rest.len()) + start;
+        let end = rest[start..].find(';').ok_or_else(|| {
+            error(
+                source,
+                entity_offset,
+                MarkupErrorKind::Syntax("'&' must be escaped as '&amp;'"),
+            )
+        })?;
+        let entity = &rest[start + 1..start + end];
+
+        let c = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(|dec| dec.parse()))
+                .and_then(Result::ok)
+                .and_then(char::from_u32),
+        };
+
+        match c {
+            Some(c) => unescaped.push(c),
+            None => {
+                return Err(error(
+                    source,
+                    entity_offset,
+                    MarkupErrorKind::UnknownEntity(entity.to_owned()),
+                ))
+            }
+        }
+
+        rest = &rest[start + end + 1..];
+    }
+
+    unescaped.push_str(rest);
+
+    Ok(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    fn open(name: &str, attributes: &[(&str, &str)], empty: bool) -> Token {
+        Token::Open {
+            name: name.to_owned(),
+            attributes: attributes
+                .iter()
+                .map(|&(key, value)| (key.to_owned(), value.to_owned()))
+                .collect(),
+            empty,
+        }
+    }
+
+    fn compile_error(source: &str) -> MarkupError {
+        compile(source, &Profile::default()).unwrap_err()
+    }
+
+    fn golden() -> Directory {
+        Directory::new(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden"))
+    }
+
+    #[test]
+    fn tokens() {
+        let tokens =
+            tokenize("a<b>x &lt;&#65;&#x42;</b ><!-- c -->d<qr size='2' correction=\"h\"/>")
+                .unwrap();
+
+        assert_eq!(
+            tokens,
+            [
+                (0, Token::Text("a".to_owned())),
+                (1, open("b", &[], false)),
+                (4, Token::Text("x <AB".to_owned())),
+                (21, Token::Close("b".to_owned())),
+                (36, Token::Text("d".to_owned())),
+                (37, open("qr", &[("size", "2"), ("correction", "h")], true)),
+            ]
+        );
+    }
+
+    #[test]
+    fn comments_join_text() {
+        assert_eq!(
+            tokenize("one <!-- two --> three").unwrap(),
+            [(0, Token::Text("one  three".to_owned()))]
+        );
+    }
+
+    #[test]
+    fn attributes_without_values() {
+        assert_eq!(
+            tokenize("<u double>").unwrap(),
+            [(0, open("u", &[("double", "")], false))]
+        );
+    }
+
+    #[test]
+    fn syntax_errors() {
+        for (source, line, column, message) in [
+            ("ab\ncd <!-- x", 2, 4, "unterminated comment"),
+            ("<>", 1, 2, "expected a tag name"),
+            ("</b x>", 1, 5, "expected '>'"),
+            ("<b/ >", 1, 4, "expected '>'"),
+            ("<qr size=2>", 1, 10, "expected a quoted value"),
+            ("<qr =\"2\">", 1, 5, "expected an attribute name"),
+            ("<qr size=\"2>", 1, 11, "unterminated attribute value"),
+            ("<qr size=\"2\"", 1, 13, "unexpected end of input"),
+            ("é\nfish & chips", 2, 6, "'&' must be escaped as '&amp;'"),
+        ] {
+            let err = compile_error(source);
+
+            assert_eq!((err.line, err.column), (line, column), "{}", source);
+            assert!(
+                matches!(err.kind, MarkupErrorKind::Syntax(m) if m == message),
+                "{}: {}",
+                source,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_entity() {
+        let err = compile_error("ok\n  &nbsp;");
+
+        assert_eq!((err.line, err.column), (2, 3));
+        assert!(matches!(err.kind, MarkupErrorKind::UnknownEntity(entity) if entity == "nbsp"));
+    }
+
+    #[test]
+    fn positions_count_characters() {
+        assert_eq!(position("", 0), (1, 1));
+        assert_eq!(position("ab\nc", 4), (2, 2));
+        assert_eq!(position("ééé", "éé".len()), (1, 3));
+    }
+
+    #[test]
+    fn compiler_errors() {
+        let err = compile_error("<b>\n<u>x</b>");
+        assert_eq!((err.line, err.column), (2, 5));
+        assert!(matches!(
+            err.kind,
+            MarkupErrorKind::Mismatched { expected, found } if expected == "u" && found == "b"
+        ));
+
+        let err = compile_error("x\n\t<b>");
+        assert_eq!((err.line, err.column), (2, 2));
+        assert!(matches!(err.kind, MarkupErrorKind::Unclosed(name) if name == "b"));
+
+        let err = compile_error("</b>");
+        assert!(matches!(err.kind, MarkupErrorKind::UnexpectedClose(name) if name == "b"));
+
+        let err = compile_error("<blink>");
+        assert!(matches!(err.kind, MarkupErrorKind::UnknownTag(name) if name == "blink"));
+
+        let err = compile_error("<col>x</col>");
+        assert!(matches!(err.kind, MarkupErrorKind::Misplaced(name) if name == "col"));
+
+        let err = compile_error("<columns><b>x</b></columns>");
+        assert_eq!((err.line, err.column), (1, 10));
+        assert!(matches!(err.kind, MarkupErrorKind::Misplaced(name) if name == "b"));
+
+        let err = compile_error("<align>x</align>");
+        assert!(matches!(
+            err.kind,
+            MarkupErrorKind::MissingAttribute { tag, attribute: "value" } if tag == "align"
+        ));
+
+        let err = compile_error("a\n<size width=\"9\">x</size>");
+        assert_eq!((err.line, err.column), (2, 1));
+        assert!(matches!(
+            err.kind,
+            MarkupErrorKind::InvalidAttribute { attribute, value, .. }
+                if attribute == "width" && value == "9"
+        ));
+
+        let err = compile_error("<br>x</br>");
+        assert!(matches!(
+            err.kind,
+            MarkupErrorKind::Syntax("tag must be empty")
+        ));
+    }
+
+    #[test]
+    fn styles() {
+        let document = compile("<b>A<u>B</u></b>C", &Profile::default()).unwrap();
+
+        assert_eq!(
+            document.to_vec(),
+            [0x1B, b'E', 1, b'A', 0x1B, b'-', 1, b'B', 0x1B, b'-', 0, 0x1B, b'E', 0, b'C']
+        );
+    }
+
+    #[test]
+    fn tag_only_lines_are_left_out() {
+        let document = compile("<b>\nA\n</b>\nB\n", &Profile::default()).unwrap();
+
+        assert_eq!(
+            document.to_vec(),
+            [0x1B, b'E', 1, b'A', b'\n', 0x1B, b'E', 0, b'B', b'\n']
+        );
+    }
+
+    #[test]
+    fn images_need_a_source() {
+        let err = compile_error("<image src=\"codes.png\"/>");
+
+        assert!(matches!(err.kind, MarkupErrorKind::NoImages));
+    }
+
+    #[test]
+    fn images_from_a_directory() {
+        let profile = Profile::default();
+
+        compile_with("<image src=\"codes.png\"/>", &profile, &golden()).unwrap();
+        compile_with("<image src=\"../golden/codes.png\"/>", &profile, &golden()).unwrap();
+
+        let outside = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+        for src in ["../../Cargo.toml", outside.to_str().unwrap()] {
+            let source = format!("<image src=\"{}\"/>", escape(src));
+            let err = compile_with(&source, &profile, &golden()).unwrap_err();
+
+            assert!(
+                matches!(&err.kind, MarkupErrorKind::ImagePath(path) if path == src),
+                "{}",
+                err
+            );
+        }
+
+        let err = compile_with("<image src=\"missing.png\"/>", &profile, &golden()).unwrap_err();
+        assert!(matches!(err.kind, MarkupErrorKind::Io(_)));
+    }
+
+    #[test]
+    fn images_from_a_resolver() {
+        let images = |src: &str| match src {
+            "dot" => Ok(Image::from_fn(8, 1, |_, _| true)),
+            _ => Err(MarkupErrorKind::ImagePath(src.to_owned())),
+        };
+
+        let document = compile_with("<image src=\"dot\"/>", &Profile::default(), &images).unwrap();
+        assert!(!document.is_empty());
+
+        let err = compile_with("\n <image src=\"x\"/>", &Profile::default(), &images).unwrap_err();
+        assert_eq!((err.line, err.column), (2, 2));
+    }
+}
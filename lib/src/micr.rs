@@ -0,0 +1,436 @@
+//! Reading the MICR line of checks and printing endorsements
+//!
+//! Printers with a MICR reader read the magnetic line at the bottom of a
+//! check as it is inserted, after which the check can be endorsed on the
+//! back before it is ejected:
+//!
+//! ```
+//! use thermal::{profile::Profile, MockDevice, Printer};
+//!
+//! let printer = Printer::with_profile(MockDevice::new(), Profile::EPSON_TM_H6000);
+//! printer.device().reply(b"_\0A021000021A 1234567890C 0101\0");
+//!
+//! let check = printer.check().read().unwrap();
+//! assert_eq!(check.micr().routing_number().as_deref(), Some("021000021"));
+//! assert_eq!(check.micr().account_number().as_deref(), Some("1234567890"));
+//! assert_eq!(check.micr().check_number().as_deref(), Some("0101"));
+//!
+//! let endorsement = check.endorse().unwrap();
+//! endorsement.println("FOR DEPOSIT ONLY").unwrap();
+//! endorsement.eject().unwrap();
+//! ```
+//!
+//! Symbols of the MICR fonts are sent back as letters: `A` to `D` are the
+//! E13B transit, amount, on-us and dash symbols, and `A` to `E` the CMC7
+//! symbols S1 to S5. Characters that could not be recognized are sent as `?`.
+
+use std::time::Duration;
+
+use crate::{
+    error::{MicrError, PrinterError},
+    slip::InsertedSlip,
+    Device, PaperType, Printer, SlipSide,
+};
+
+/// Header of the reading result
+const HEADER: u8 = b'_';
+
+/// The font of a MICR line
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MicrFont {
+    /// Used in the US, Canada, the UK and most of Asia
+    E13b = 0x00,
+    /// Used in France, Spain and most of Latin America
+    Cmc7 = 0x01,
+}
+
+/// The MICR line read from a check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Micr {
+    font: MicrFont,
+    text: String,
+}
+
+/// Reading the MICR line of a check
+///
+/// Created with [`Printer::check`].
+pub struct CheckJob<'a, D: Device> {
+    printer: &'a Printer<D>,
+    font: MicrFont,
+    timeout: Duration,
+}
+
+/// A check that has been read and is held by the printer
+pub struct InsertedCheck<'a, D: Device> {
+    printer: &'a Printer<D>,
+    micr: Micr,
+}
+
+impl Micr {
+    /// Parse a MICR line as sent back by the printer
+    pub fn new<S: Into<String>>(font: MicrFont, text: S) -> Self {
+        Self {
+            font,
+            text: text.into(),
+        }
+    }
+
+    /// The font the line was read in
+    pub fn font(&self) -> MicrFont {
+        self.font
+    }
+
+    /// The line as sent back by the printer
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// If every character of the line was recognized
+    pub fn is_complete(&self) -> bool {
+        !self.text.is_empty() && !self.text.contains('?')
+    }
+
+    /// The field between a pair of the given symbols
+    fn between(&self, symbol: char) -> Option<(usize, usize)> {
+        let start = self.text.find(symbol)? + 1;
+        let end = start + self.text[start..].find(symbol)?;
+
+        Some((start, end))
+    }
+
+    /// Digits of a field, with dash symbols written as `-`
+    fn field(text: &str) -> Option<String> {
+        let field: String = text
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| if c == 'D' { '-' } else { c })
+            .collect();
+
+        (!field.is_empty()).then_some(field)
+    }
+
+    /// The bank routing number between the two E13B transit symbols
+    pub fn routing_number(&self) -> Option<String> {
+        if self.font != MicrFont::E13b {
+            return None;
+        }
+
+        let (start, end) = self.between('A')?;
+        Self::field(&self.text[start..end])
+    }
+
+    /// The account number, the on-us field right after the routing number
+    pub fn account_number(&self) -> Option<String> {
+        if self.font != MicrFont::E13b {
+            return None;
+        }
+
+        let (_, routing_end) = self.between('A')?;
+        let rest = &self.text[routing_end + 1..];
+        Self::field(&rest[..rest.find('C')?])
+    }
+
+    /// The check number
+    ///
+    /// Personal checks have it after the account number, business checks in
+    /// an auxiliary on-us field before the routing number.
+    pub fn check_number(&self) -> Option<String> {
+        if self.font != MicrFont::E13b {
+            return None;
+        }
+
+        let (routing_start, routing_end) = self.between('A')?;
+        let auxiliary = &self.text[..routing_start - 1];
+        if let Some((start, end)) = Micr::new(MicrFont::E13b, auxiliary).between('C') {
+            return Self::field(&auxiliary[start..end]);
+        }
+
+        let rest = &self.text[routing_end + 1..];
+        let after_account = &rest[rest.find('C')? + 1..];
+        Self::field(after_account.split('B').next()?)
+    }
+
+    /// The amount encoded between the E13B amount symbols, in cents
+    pub fn amount(&self) -> Option<u64> {
+        if self.font != MicrFont::E13b {
+            return None;
+        }
+
+        let (start, end) = self.between('B')?;
+        Self::field(&self.text[start..end])?.parse().ok()
+    }
+
+    /// The groups of digits between the symbols of a CMC7 line
+    pub fn fields(&self) -> Vec<&str> {
+        let symbols: &[char] = match self.font {
+            MicrFont::E13b => &['A', 'B', 'C'],
+            MicrFont::Cmc7 => &['A', 'B', 'C', 'D', 'E'],
+        };
+
+        self.text
+            .split(symbols)
+            .map(str::trim)
+            .filter(|field| !field.is_empty())
+            .collect()
+    }
+}
+
+impl<D: Device> Printer<D> {
+    /// Start reading a check
+    pub fn check(&self) -> CheckJob<'_, D> {
+        CheckJob {
+            printer: self,
+            font: MicrFont::E13b,
+            timeout: Duration::from_secs(60),
+        }
+    }
+
+    /// Read check paper
+    ///
+    /// Waits for a check to be inserted and reads its MICR line in the given
+    /// font, holding on to the check afterwards
+    ///
+    /// ASCII: FS a 0 **n**
+    pub fn read_check(&self, font: MicrFont) -> Result<(), PrinterError> {
+        self.raw([Self::FS, b'a', b'0', font as u8])
+    }
+
+    /// Load check paper to print starting position
+    ///
+    /// ASCII: FS a 1
+    pub fn load_check(&self) -> Result<(), PrinterError> {
+        self.raw([Self::FS, b'a', b'1'])
+    }
+
+    /// Eject check paper
+    ///
+    /// ASCII: FS a 2
+    pub fn eject_check(&self) -> Result<(), PrinterError> {
+        self.raw([Self::FS, b'a', b'2'])
+    }
+
+    /// Request transmission of check paper reading result
+    ///
+    /// Waits at most `timeout` for the result of the last reading, which
+    /// arrives as the header `_`, a status byte, the MICR line and a NUL
+    ///
+    /// ASCII: FS b **n**
+    pub fn micr_result(&self, font: MicrFont, timeout: Duration) -> Result<Micr, MicrError> {
+        self.raw([Self::FS, b'b', 1])?;
+
+        // The status byte may itself be NUL, so the result ends with the NUL
+        // after it
+        let result = self.read_reply(2, timeout).map_err(|err| match err {
+            PrinterError::NoResponse => MicrError::Timeout(timeout),
+            err => err.into(),
+        })?;
+
+        match *result.as_slice() {
+            [HEADER, 0x00, ref text @ ..] if text.is_ascii() => {
+                Ok(Micr::new(font, String::from_utf8_lossy(text).into_owned()))
+            }
+            [HEADER, status, ..] => Err(MicrError::ReadFailed(status)),
+            _ => Err(MicrError::InvalidResult(result)),
+        }
+    }
+}
+
+impl<'a, D: Device> CheckJob<'a, D> {
+    /// The font of the MICR line
+    pub fn font(mut self, font: MicrFont) -> Self {
+        self.font = font;
+        self
+    }
+
+    /// How long to wait for the check to be inserted and read
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Wait for a check and read its MICR line
+    ///
+    /// A check that could not be read is ejected again. When no check is
+    /// inserted in time the paper path is released.
+    pub fn read(self) -> Result<InsertedCheck<'a, D>, MicrError> {
+        let printer = self.printer;
+
+        if !printer.profile.micr {
+            return Err(PrinterError::Unsupported {
+                feature: "MICR reading",
+                profile: printer.profile.name,
+            }
+            .into());
+        }
+
+        let minutes = self.timeout.as_secs().div_ceil(60).clamp(1, 64) as u8;
+        printer.slip_wait_time(minutes, 0)?;
+        printer.read_check(self.font)?;
+
+        match printer.micr_result(self.font, self.timeout) {
+            Ok(micr) => Ok(InsertedCheck { printer, micr }),
+            Err(MicrError::Timeout(timeout)) => {
+                printer.release_slip()?;
+                printer.paper_type(PaperType::Roll)?;
+
+                Err(MicrError::Timeout(timeout))
+            }
+            Err(err) => {
+                printer.eject_check()?;
+                printer.paper_type(PaperType::Roll)?;
+
+                Err(err)
+            }
+        }
+    }
+}
+
+impl<'a, D: Device> InsertedCheck<'a, D> {
+    /// The MICR line of the check
+    pub fn micr(&self) -> &Micr {
+        &self.micr
+    }
+
+    /// Load the check to print its endorsement on the back
+    ///
+    /// Everything printed goes to the back of the check until it is ejected.
+    pub fn endorse(self) -> Result<InsertedSlip<'a, D>, PrinterError> {
+        self.printer.load_check()?;
+        self.printer.slip_side(SlipSide::Back)?;
+
+        Ok(InsertedSlip::new(self.printer, PaperType::Slip))
+    }
+
+    /// Eject the check without printing on it and switch back to roll paper
+    pub fn eject(self) -> Result<(Micr, &'a Printer<D>), PrinterError> {
+        self.printer.eject_check()?;
+        self.printer.paper_type(PaperType::Roll)?;
+
+        Ok((self.micr, self.printer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{profile::Profile, MockDevice};
+
+    const ROLL: [u8; 4] = [0x1B, b'c', b'0', PaperType::Roll as u8];
+
+    fn printer() -> Printer<MockDevice> {
+        Printer::with_profile(MockDevice::new(), Profile::EPSON_TM_H6000)
+    }
+
+    #[test]
+    fn read() {
+        let printer = printer();
+        // The result may arrive in pieces, and a NUL status is not its end
+        printer.device().reply(b"_\0");
+        printer.device().reply(b"A021000021A 123");
+        printer.device().reply(b"4567890C 0101\0");
+
+        let check = printer.check().read().unwrap();
+
+        assert_eq!(check.micr().text(), "A021000021A 1234567890C 0101");
+        assert!(check.micr().is_complete());
+        assert_eq!(
+            printer.device().take_written(),
+            [
+                &[0x1B, b'f', 1, 0][..],
+                &[0x1C, b'a', b'0', MicrFont::E13b as u8],
+                &[0x1C, b'b', 1],
+            ]
+            .concat()
+        );
+
+        let (micr, _) = check.eject().unwrap();
+        assert_eq!(micr.routing_number().as_deref(), Some("021000021"));
+        assert_eq!(
+            printer.device().take_written(),
+            [&[0x1C, b'a', b'2'][..], &ROLL].concat()
+        );
+    }
+
+    #[test]
+    fn read_failure_ejects_the_check() {
+        let printer = printer();
+        printer.device().reply(b"_\x02\0");
+
+        let err = printer.check().read().err().unwrap();
+
+        assert!(matches!(err, MicrError::ReadFailed(0x02)));
+        assert!(printer
+            .device()
+            .written()
+            .ends_with(&[&[0x1C, b'a', b'2'][..], &ROLL].concat()));
+    }
+
+    #[test]
+    fn timeout_releases_the_paper() {
+        let printer = printer();
+        let timeout = Duration::from_millis(10);
+
+        let err = printer.check().timeout(timeout).read().err().unwrap();
+
+        assert!(matches!(err, MicrError::Timeout(t) if t == timeout));
+        assert!(printer
+            .device()
+            .written()
+            .ends_with(&[&[0x1B, b'q'][..], &ROLL].concat()));
+    }
+
+    #[test]
+    fn invalid_result() {
+        let printer = printer();
+        printer.device().reply(b"?\0\0");
+
+        let err = printer.micr_result(MicrFont::E13b, Duration::from_secs(1));
+
+        assert!(matches!(err, Err(MicrError::InvalidResult(result)) if result == b"?\0"));
+    }
+
+    #[test]
+    fn unsupported() {
+        let printer = Printer::with_profile(MockDevice::new(), Profile::EPSON_TM_T88);
+
+        assert!(matches!(
+            printer.check().read().err().unwrap(),
+            MicrError::Printer(PrinterError::Unsupported { .. })
+        ));
+        assert!(printer.device().written().is_empty());
+    }
+
+    #[test]
+    fn cmc7_fields() {
+        let micr = Micr::new(MicrFont::Cmc7, "A12345678B 0001E123456789012C ?");
+
+        assert_eq!(micr.fields(), ["12345678", "0001", "123456789012", "?"]);
+        assert!(!micr.is_complete());
+        assert_eq!(micr.routing_number(), None);
+        assert_eq!(micr.check_number(), None);
+    }
+
+    #[test]
+    fn personal_check() {
+        let micr = Micr::new(MicrFont::E13b, "A021000021A 12D3456C 0101 B0000012345B");
+
+        assert_eq!(micr.account_number().as_deref(), Some("12-3456"));
+        assert_eq!(micr.check_number().as_deref(), Some("0101"));
+        assert_eq!(micr.amount(), Some(12345));
+        assert_eq!(
+            micr.fields(),
+            ["021000021", "12D3456", "0101", "0000012345"]
+        );
+    }
+
+    #[test]
+    fn business_check() {
+        let micr = Micr::new(MicrFont::E13b, "C004512C A021000021A 1234567890C");
+
+        assert_eq!(micr.check_number().as_deref(), Some("004512"));
+        assert_eq!(micr.routing_number().as_deref(), Some("021000021"));
+        assert_eq!(micr.account_number().as_deref(), Some("1234567890"));
+        assert_eq!(micr.amount(), None);
+    }
+}
@@ -0,0 +1,514 @@
+//! NV user memory and the customization settings of the printer
+//!
+//! Both are kept in non-volatile memory, so they survive initializing and
+//! turning off the printer. NV memory wears out with every write, so
+//! settings are staged and compared to the printer's before anything is
+//! written:
+//!
+//! ```
+//! use thermal::{nv::CustomizeValue, profile::Profile, MockDevice, Printer};
+//!
+//! let printer = Printer::with_profile(MockDevice::new(), Profile::EPSON_TM_T88);
+//! printer.device().reply(b"\x37\x20\x00");
+//! printer.device().reply(b"\x37\x27\x05\x1f3\x00");
+//!
+//! let mut settings = printer.user_settings().unwrap();
+//! settings.set_customize_value(CustomizeValue::PrintSpeed, 9);
+//!
+//! // A dry run, nothing has been written
+//! for change in settings.diff().unwrap() {
+//!     println!("{}", change);
+//! }
+//!
+//! settings.discard().unwrap();
+//! ```
+
+use std::{collections::BTreeMap, fmt};
+
+use crate::{
+    error::{NvError, PrinterError},
+    Device, Printer,
+};
+
+/// The most bytes of NV user memory read or written by a single command
+const NV_CHUNK: usize = 80;
+
+/// A customized setting value (GS ( E function 5)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CustomizeValue {
+    /// Paper width, in model specific steps
+    PaperWidth,
+    /// Print density, from 65530 (-6, lightest) through 0 to 6 (darkest)
+    PrintDensity,
+    /// Print speed, from 1 (slowest) up to a model specific maximum
+    PrintSpeed,
+    /// Any other setting, by its number in the printer's specification
+    Other(u8),
+}
+
+/// A change the user setting session would make
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SettingChange {
+    MemorySwitch {
+        switch: u8,
+        bit: u8,
+        from: bool,
+        to: bool,
+    },
+    CustomizeValue {
+        value: CustomizeValue,
+        from: u16,
+        to: u16,
+    },
+}
+
+/// A user setting session, in which the printer accepts no other commands
+///
+/// Created with [`Printer::user_settings`]. The session ends with
+/// [`commit`](Self::commit) or [`discard`](Self::discard), or when it is
+/// dropped, after which the printer resets itself.
+pub struct UserSettings<'a, D: Device> {
+    printer: &'a Printer<D>,
+    /// Staged memory switch bits, by switch and bit (1-8)
+    switches: BTreeMap<(u8, u8), bool>,
+    values: BTreeMap<CustomizeValue, u16>,
+    open: bool,
+}
+
+impl CustomizeValue {
+    fn number(self) -> u8 {
+        match self {
+            CustomizeValue::PaperWidth => 3,
+            CustomizeValue::PrintDensity => 5,
+            CustomizeValue::PrintSpeed => 6,
+            CustomizeValue::Other(n) => n,
+        }
+    }
+}
+
+impl fmt::Display for SettingChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = |on| if on { "on" } else { "off" };
+
+        match *self {
+            SettingChange::MemorySwitch {
+                switch,
+                bit,
+                from,
+                to,
+            } => write!(
+                f,
+                "memory switch {} bit {}: {} -> {}",
+                switch,
+                bit,
+                state(from),
+                state(to)
+            ),
+            SettingChange::CustomizeValue { value, from, to } => {
+                write!(
+                    f,
+                    "{:?} (customize value {}): {} -> {}",
+                    value,
+                    value.number(),
+                    from,
+                    to
+                )
+            }
+        }
+    }
+}
+
+impl<D: Device> Printer<D> {
+    fn require_nv_memory(&self) -> Result<(), PrinterError> {
+        if !self.profile.nv_memory {
+            return Err(PrinterError::Unsupported {
+                feature: "NV memory",
+                profile: self.profile.name,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Write to NV user memory
+    ///
+    /// Writes `data` starting at `address`, in commands of at most 80 bytes
+    ///
+    /// ASCII: FS g 1 **m** **a1** **a2** **a3** **a4** **nL** **nH** **d1...dk**
+    pub fn write_nv_user_memory(&self, address: u32, data: &[u8]) -> Result<(), PrinterError> {
+        self.require_nv_memory()?;
+
+        for (i, chunk) in data.chunks(NV_CHUNK).enumerate() {
+            let address = address + (i * NV_CHUNK) as u32;
+            let len = chunk.len() as u16;
+
+            let mut command = vec![Self::FS, b'g', b'1', 0];
+            command.extend(address.to_le_bytes());
+            command.extend(len.to_le_bytes());
+            command.extend_from_slice(chunk);
+
+            self.raw(command)?;
+        }
+
+        Ok(())
+    }
+
+    /// Read from NV user memory
+    ///
+    /// Reads `len` bytes starting at `address`, in commands of at most 80
+    /// bytes
+    ///
+    /// ASCII: FS g 2 **m** **a1** **a2** **a3** **a4** **nL** **nH**
+    pub fn read_nv_user_memory(&self, address: u32, len: usize) -> Result<Vec<u8>, NvError> {
+        self.require_nv_memory()?;
+
+        let mut data = Vec::with_capacity(len);
+
+        while data.len() < len {
+            let chunk = (len - data.len()).min(NV_CHUNK);
+
+            let mut command = vec![Self::FS, b'g', b'2', 0];
+            command.extend((address + data.len() as u32).to_le_bytes());
+            command.extend((chunk as u16).to_le_bytes());
+            self.raw(command)?;
+
+            // Header, then the data, which may contain NUL itself
+            match self.read_reply(1 + chunk, Self::REPLY_TIMEOUT)?.as_slice() {
+                [0x5F, bytes @ ..] if bytes.len() == chunk => data.extend_from_slice(bytes),
+                reply => return Err(NvError::InvalidReply(reply.to_vec())),
+            }
+        }
+
+        Ok(data)
+    }
+
+    /// Change into the user setting mode
+    ///
+    /// The printer only accepts user setting commands until the session ends.
+    ///
+    /// ASCII: GS ( E 3 0 1 49 4E
+    pub fn user_settings(&self) -> Result<UserSettings<'_, D>, NvError> {
+        self.require_nv_memory()?;
+
+        self.raw([Self::GS, b'(', b'E', 3, 0, 1, b'I', b'N'])?;
+
+        match self.read_reply(2, Self::REPLY_TIMEOUT)?.as_slice() {
+            [0x37, 0x20] => Ok(UserSettings {
+                printer: self,
+                switches: BTreeMap::new(),
+                values: BTreeMap::new(),
+                open: true,
+            }),
+            reply => Err(NvError::InvalidReply(reply.to_vec())),
+        }
+    }
+}
+
+impl<'a, D: Device> UserSettings<'a, D> {
+    /// Transmit the settings of a memory switch
+    ///
+    /// Returns bits 1 to 8 of switch `switch` (1-8).
+    ///
+    /// ASCII: GS ( E 2 0 4 **a**
+    pub fn memory_switch(&self, switch: u8) -> Result<[bool; 8], NvError> {
+        if !(1..=8).contains(&switch) {
+            return Err(NvError::InvalidSwitch(switch));
+        }
+
+        self.printer
+            .raw([Printer::<D>::GS, b'(', b'E', 2, 0, 4, switch])?;
+
+        // Bit 8 comes first
+        match self
+            .printer
+            .read_reply(10, Printer::<D>::REPLY_TIMEOUT)?
+            .as_slice()
+        {
+            [0x37, 0x21, bits @ ..]
+                if bits.len() == 8 && bits.iter().all(|b| b"01".contains(b)) =>
+            {
+                let mut switches = [false; 8];
+                for (i, &bit) in bits.iter().rev().enumerate() {
+                    switches[i] = bit == b'1';
+                }
+
+                Ok(switches)
+            }
+            reply => Err(NvError::InvalidReply(reply.to_vec())),
+        }
+    }
+
+    /// Transmit a customized setting value
+    ///
+    /// ASCII: GS ( E 2 0 6 **a**
+    pub fn customize_value(&self, value: CustomizeValue) -> Result<u16, NvError> {
+        self.printer
+            .raw([Printer::<D>::GS, b'(', b'E', 2, 0, 6, value.number()])?;
+
+        let reply = self.printer.read_reply(2, Printer::<D>::REPLY_TIMEOUT)?;
+
+        // The value in decimal, after the setting number and a separator
+        let digits = match reply.as_slice() {
+            [0x37, 0x27, rest @ ..] => match rest.iter().position(|&b| b == 0x1F) {
+                Some(separator) => &rest[separator + 1..],
+                None => rest,
+            },
+            _ => return Err(NvError::InvalidReply(reply)),
+        };
+
+        std::str::from_utf8(digits)
+            .ok()
+            .and_then(|digits| digits.parse().ok())
+            .ok_or_else(|| NvError::InvalidReply(reply.clone()))
+    }
+
+    /// Stage a change to a bit (1-8) of a memory switch (1-8)
+    pub fn set_memory_switch(
+        &mut self,
+        switch: u8,
+        bit: u8,
+        on: bool,
+    ) -> Result<&mut Self, NvError> {
+        if !(1..=8).contains(&switch) {
+            return Err(NvError::InvalidSwitch(switch));
+        }
+        if !(1..=8).contains(&bit) {
+            return Err(NvError::InvalidBit(bit));
+        }
+
+        self.switches.insert((switch, bit), on);
+
+        Ok(self)
+    }
+
+    /// Stage a change to a customized setting value
+    pub fn set_customize_value(&mut self, value: CustomizeValue, n: u16) -> &mut Self {
+        self.values.insert(value, n);
+        self
+    }
+
+    /// Compare the staged settings to the printer's, without writing
+    /// anything
+    pub fn diff(&self) -> Result<Vec<SettingChange>, NvError> {
+        let mut changes = Vec::new();
+        let mut current_switch = None;
+
+        for (&(switch, bit), &to) in &self.switches {
+            let bits = match current_switch {
+                Some((n, bits)) if n == switch => bits,
+                _ => self.memory_switch(switch)?,
+            };
+            current_switch = Some((switch, bits));
+
+            let from = bits[bit as usize - 1];
+            if from != to {
+                changes.push(SettingChange::MemorySwitch {
+                    switch,
+                    bit,
+                    from,
+                    to,
+                });
+            }
+        }
+
+        for (&value, &to) in &self.values {
+            let from = self.customize_value(value)?;
+            if from != to {
+                changes.push(SettingChange::CustomizeValue { value, from, to });
+            }
+        }
+
+        Ok(changes)
+    }
+
+    /// Write the staged settings that differ from the printer's and end the
+    /// session
+    ///
+    /// Returns the changes that were written.
+    ///
+    /// ASCII: GS ( E **pL** **pH** 3 [**a** **b8**...**b1**]..., GS ( E **pL** **pH** 5 [**a** **nL** **nH**]...
+    pub fn commit(mut self) -> Result<Vec<SettingChange>, NvError> {
+        let changes = self.diff()?;
+
+        let mut switches = BTreeMap::new();
+        let mut values = Vec::new();
+        for change in &changes {
+            match *change {
+                SettingChange::MemorySwitch {
+                    switch, bit, to, ..
+                } => {
+                    // Bits that are not changed are left alone with '2'
+                    let bits = switches.entry(switch).or_insert([b'2'; 8]);
+                    bits[8 - bit as usize] = if to { b'1' } else { b'0' };
+                }
+                SettingChange::CustomizeValue { value, to, .. } => {
+                    values.push(value.number());
+                    values.extend(to.to_le_bytes());
+                }
+            }
+        }
+
+        if !switches.is_empty() {
+            let mut data = vec![3];
+            for (switch, bits) in switches {
+                data.push(switch);
+                data.extend(bits);
+            }
+
+            self.printer.raw(extended(&data))?;
+        }
+
+        if !values.is_empty() {
+            let mut data = vec![5];
+            data.extend(values);
+
+            self.printer.raw(extended(&data))?;
+        }
+
+        self.end()?;
+
+        Ok(changes)
+    }
+
+    /// End the session without writing anything
+    pub fn discard(mut self) -> Result<(), NvError> {
+        Ok(self.end()?)
+    }
+
+    /// End the user setting session, after which the printer resets
+    ///
+    /// ASCII: GS ( E 4 0 2 4F 55 54
+    fn end(&mut self) -> Result<(), PrinterError> {
+        self.open = false;
+        self.printer.state.set(Default::default());
+        self.printer.clear_glyphs();
+
+        self.printer
+            .raw([Printer::<D>::GS, b'(', b'E', 4, 0, 2, b'O', b'U', b'T'])
+    }
+}
+
+impl<D: Device> Drop for UserSettings<'_, D> {
+    fn drop(&mut self) {
+        if self.open {
+            let _ = self.end();
+        }
+    }
+}
+
+/// GS ( E **pL** **pH** followed by `data`
+fn extended(data: &[u8]) -> Vec<u8> {
+    let mut command = vec![0x1D, b'(', b'E'];
+    command.extend((data.len() as u16).to_le_bytes());
+    command.extend_from_slice(data);
+
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{profile::Profile, MockDevice};
+
+    #[test]
+    fn invalid_memory_switches() {
+        let printer = Printer::with_profile(MockDevice::new(), Profile::EPSON_TM_H6000);
+        printer.device().reply([0x37, 0x20, 0]);
+        let mut settings = printer.user_settings().unwrap();
+
+        assert!(matches!(
+            settings.set_memory_switch(9, 1, true),
+            Err(NvError::InvalidSwitch(9))
+        ));
+        assert!(matches!(
+            settings.set_memory_switch(1, 0, true),
+            Err(NvError::InvalidBit(0))
+        ));
+        assert!(settings.set_memory_switch(8, 8, true).is_ok());
+    }
+
+    #[test]
+    fn commit_memory_switches() {
+        let printer = Printer::with_profile(MockDevice::new(), Profile::EPSON_TM_H6000);
+        printer.device().reply([0x37, 0x20, 0]);
+        let mut settings = printer.user_settings().unwrap();
+        printer.device().take_written();
+
+        // Bit 8 first, so only bit 8 is on
+        printer.device().reply(b"\x37\x2110000000\0");
+        settings
+            .set_memory_switch(1, 1, true)
+            .unwrap()
+            .set_memory_switch(1, 3, false)
+            .unwrap()
+            .set_memory_switch(1, 8, false)
+            .unwrap();
+
+        assert_eq!(settings.commit().unwrap().len(), 2);
+
+        let mut expected = vec![0x1D, b'(', b'E', 2, 0, 4, 1];
+        expected.extend([0x1D, b'(', b'E', 10, 0, 3, 1]);
+        expected.extend(b"02222221");
+        expected.extend([0x1D, b'(', b'E', 4, 0, 2, b'O', b'U', b'T']);
+        assert_eq!(printer.device().written(), expected);
+    }
+
+    #[test]
+    fn commit_customize_values() {
+        let printer = Printer::with_profile(MockDevice::new(), Profile::EPSON_TM_H6000);
+        printer.device().reply([0x37, 0x20, 0]);
+        let mut settings = printer.user_settings().unwrap();
+        printer.device().take_written();
+
+        printer.device().reply(b"\x37\x27\x05\x1F0\0");
+        printer.device().reply(b"\x37\x27\x06\x1F2\0");
+        settings
+            .set_customize_value(CustomizeValue::PrintDensity, 65534)
+            .set_customize_value(CustomizeValue::PrintSpeed, 4);
+
+        assert_eq!(
+            settings.commit().unwrap(),
+            [
+                SettingChange::CustomizeValue {
+                    value: CustomizeValue::PrintDensity,
+                    from: 0,
+                    to: 65534,
+                },
+                SettingChange::CustomizeValue {
+                    value: CustomizeValue::PrintSpeed,
+                    from: 2,
+                    to: 4,
+                },
+            ]
+        );
+
+        let mut expected = vec![0x1D, b'(', b'E', 2, 0, 6, 5];
+        expected.extend([0x1D, b'(', b'E', 2, 0, 6, 6]);
+        expected.extend([0x1D, b'(', b'E', 7, 0, 5, 5, 0xFE, 0xFF, 6, 4, 0]);
+        expected.extend([0x1D, b'(', b'E', 4, 0, 2, b'O', b'U', b'T']);
+        assert_eq!(printer.device().written(), expected);
+    }
+
+    #[test]
+    fn commit_unchanged() {
+        let printer = Printer::with_profile(MockDevice::new(), Profile::EPSON_TM_H6000);
+        printer.device().reply([0x37, 0x20, 0]);
+        let mut settings = printer.user_settings().unwrap();
+        printer.device().take_written();
+
+        printer.device().reply(b"\x37\x2100000001\0");
+        printer.device().reply(b"\x37\x27\x06\x1F2\0");
+        settings
+            .set_memory_switch(2, 1, true)
+            .unwrap()
+            .set_customize_value(CustomizeValue::PrintSpeed, 2);
+
+        assert!(settings.commit().unwrap().is_empty());
+
+        // Only the reads and the end of the session
+        let mut expected = vec![0x1D, b'(', b'E', 2, 0, 4, 2];
+        expected.extend([0x1D, b'(', b'E', 2, 0, 6, 6]);
+        expected.extend([0x1D, b'(', b'E', 4, 0, 2, b'O', b'U', b'T']);
+        assert_eq!(printer.device().written(), expected);
+    }
+}
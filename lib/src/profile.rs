@@ -0,0 +1,243 @@
+//! Capabilities of the different printer models
+
+use crate::{CutMode, CutType, Font};
+
+/// The paper cutter of a printer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cutter {
+    /// Can cut the paper completely
+    pub full: bool,
+    /// Can cut the paper leaving one point uncut
+    pub partial: bool,
+    /// Supports presetting the cut position (GS V 97/98) and cutting with a
+    /// reverse feed back to the print start position (GS V 103/104)
+    pub reserve: bool,
+}
+
+/// How to sound the buzzer of a printer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Buzzer {
+    /// ESC ( A, on Epson printers with a buzzer
+    Epson,
+    /// ESC B, on the common clones
+    Clone,
+}
+
+/// How a printer sets its print density and speed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrintControl {
+    /// GS ( K, with fixed density and speed steps
+    Epson,
+    /// DC2 # and DC2 7, tuning the heating of the print head on the clones
+    Heating,
+}
+
+/// What a printer model is capable of
+///
+/// Used to refuse commands a printer would silently ignore, and to pick the
+/// right dialect of a command for printers that differ from the Epson spec.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    /// Human readable name of the model
+    pub name: &'static str,
+    /// USB vendor and product ids of the model
+    pub usb_ids: &'static [(u16, u16)],
+    /// Dot density of the print head, in dots per inch
+    pub dpi: u32,
+    /// Width of the printable area on roll paper, in dots
+    pub paper_width: u32,
+    /// Characters per line on roll paper with font A and font B
+    pub columns: [u32; 2],
+    /// The paper cutter, if the printer has one
+    pub cutter: Option<Cutter>,
+    /// Can feed the paper backwards (ESC K)
+    pub reverse_feed: bool,
+    /// Supports page mode (ESC L)
+    pub page_mode: bool,
+    /// Has a slip (cut sheet) paper path
+    pub slip: bool,
+    /// Can print on validation paper
+    pub validation: bool,
+    /// Has a MICR reader for the magnetic line on checks
+    pub micr: bool,
+    /// The buzzer, if the printer has one
+    pub buzzer: Option<Buzzer>,
+    /// Has NV user memory (FS g) and customization settings (GS ( E)
+    pub nv_memory: bool,
+    /// Keeps maintenance counters (GS g)
+    pub maintenance_counters: bool,
+    /// How print density and speed are set, if they can be
+    pub print_control: Option<PrintControl>,
+}
+
+impl Profile {
+    /// A profile that allows every command, for unknown printers
+    pub const GENERIC: Profile = Profile {
+        name: "generic ESC/POS printer",
+        usb_ids: &[],
+        dpi: 203,
+        paper_width: 576,
+        columns: [48, 64],
+        cutter: Some(Cutter {
+            full: true,
+            partial: true,
+            reserve: true,
+        }),
+        reverse_feed: true,
+        page_mode: true,
+        slip: true,
+        validation: true,
+        micr: true,
+        buzzer: Some(Buzzer::Epson),
+        nv_memory: true,
+        maintenance_counters: true,
+        print_control: Some(PrintControl::Epson),
+    };
+
+    /// The 80mm "POS-80" clones built around a WCH CH34x USB bridge
+    pub const POS_80: Profile = Profile {
+        name: "POS-80 (CH34x)",
+        usb_ids: &[(0x1a86, 0x7584)],
+        dpi: 203,
+        paper_width: 576,
+        columns: [48, 64],
+        cutter: Some(Cutter {
+            full: true,
+            partial: true,
+            reserve: false,
+        }),
+        reverse_feed: false,
+        page_mode: true,
+        slip: false,
+        validation: false,
+        micr: false,
+        buzzer: Some(Buzzer::Clone),
+        nv_memory: false,
+        maintenance_counters: false,
+        print_control: Some(PrintControl::Heating),
+    };
+
+    /// Epson TM-T88 series receipt printers
+    pub const EPSON_TM_T88: Profile = Profile {
+        name: "Epson TM-T88",
+        usb_ids: &[(0x04b8, 0x0202)],
+        dpi: 180,
+        paper_width: 512,
+        columns: [42, 56],
+        cutter: Some(Cutter {
+            full: false,
+            partial: true,
+            reserve: true,
+        }),
+        reverse_feed: false,
+        page_mode: true,
+        slip: false,
+        validation: false,
+        micr: false,
+        buzzer: Some(Buzzer::Epson),
+        nv_memory: true,
+        maintenance_counters: true,
+        print_control: Some(PrintControl::Epson),
+    };
+
+    /// Epson TM-H6000 series hybrid (roll, slip and check) printers
+    pub const EPSON_TM_H6000: Profile = Profile {
+        name: "Epson TM-H6000",
+        usb_ids: &[(0x04b8, 0x0203)],
+        dpi: 180,
+        paper_width: 512,
+        columns: [42, 56],
+        cutter: Some(Cutter {
+            full: false,
+            partial: true,
+            reserve: false,
+        }),
+        reverse_feed: true,
+        page_mode: true,
+        slip: true,
+        validation: true,
+        micr: true,
+        buzzer: Some(Buzzer::Epson),
+        nv_memory: true,
+        maintenance_counters: true,
+        print_control: Some(PrintControl::Epson),
+    };
+
+    /// All known printer profiles
+    pub const KNOWN: &'static [Profile] = &[
+        Profile::POS_80,
+        Profile::EPSON_TM_T88,
+        Profile::EPSON_TM_H6000,
+    ];
+
+    /// Characters per line on roll paper with the given font
+    pub fn columns(&self, font: Font) -> u32 {
+        self.columns[font as usize]
+    }
+
+    /// Height of a character of the given font, in dots
+    pub fn character_height(&self, font: Font) -> u32 {
+        match font {
+            Font::A => 24,
+            Font::B => 17,
+        }
+    }
+
+    /// Width of a character of the given font, including its spacing, in dots
+    pub fn character_width(&self, font: Font) -> u32 {
+        self.paper_width / self.columns(font)
+    }
+
+    /// Feed past the last printed line and cut, completely if the cutter
+    /// can
+    ///
+    /// Use this instead of [`CutMode::default`] when the printer might only
+    /// cut partially.
+    pub fn default_cut(&self) -> CutMode {
+        match self.cutter {
+            Some(cutter) if !cutter.full && cutter.partial => CutMode::Feed(CutType::Partial, 0x96),
+            _ => CutMode::default(),
+        }
+    }
+
+    /// Look up the profile of a printer by its USB ids
+    pub fn for_device(vendor_id: u16, product_id: u16) -> Option<&'static Profile> {
+        Profile::KNOWN
+            .iter()
+            .find(|profile| profile.usb_ids.contains(&(vendor_id, product_id)))
+    }
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile::GENERIC
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Document, Printer};
+
+    #[test]
+    fn default_cut_is_supported_by_every_cutter() {
+        for profile in Profile::KNOWN.iter().chain([&Profile::GENERIC]) {
+            let printer = Printer::with_profile(Document::new(), profile.clone());
+
+            assert!(
+                printer.cut(profile.default_cut()).is_ok(),
+                "{} can not make its default cut",
+                profile.name
+            );
+        }
+    }
+
+    #[test]
+    fn default_cut_falls_back_to_partial() {
+        assert_eq!(
+            Profile::EPSON_TM_T88.default_cut(),
+            CutMode::Feed(CutType::Partial, 0x96)
+        );
+        assert_eq!(Profile::GENERIC.default_cut(), CutMode::default());
+    }
+}
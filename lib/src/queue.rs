@@ -0,0 +1,719 @@
+//! A queue of print jobs, printed one at a time
+//!
+//! Commands written to a printer from several places at once end up mixed
+//! together. Jobs are whole [`Document`]s instead, printed in order of
+//! priority by a single worker that owns the printer:
+//!
+//! ```
+//! use thermal::{
+//!     queue::{JobQueue, Priority},
+//!     MockDevice, Printer,
+//! };
+//!
+//! let queue = JobQueue::new();
+//! let worker = queue.spawn_worker(Printer::new(MockDevice::new()));
+//!
+//! let receipt = Printer::document();
+//! receipt.println("Hello").unwrap();
+//!
+//! let job = queue.submit(receipt.into_device(), Priority::Normal).unwrap();
+//! job.wait().unwrap();
+//!
+//! let printer = worker.stop();
+//! assert_eq!(printer.device().written(), b"Hello\n");
+//! ```
+//!
+//! With a journal, from [`JobQueue::open`], jobs that have not been printed
+//! are kept on disk and picked up again after a restart. A job the printer
+//! was in the middle of is printed again from the start.
+
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, ErrorKind, Write},
+    panic,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Condvar, Mutex, MutexGuard,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{
+    error::{PrinterError, QueueError},
+    hotplug::ReconnectingPrinter,
+    Device, Document, Printer,
+};
+
+/// Identifies a job within its queue
+pub type JobId = u64;
+
+/// Jobs with a higher priority are printed first, jobs with the same
+/// priority in the order they were submitted
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// Something print jobs can be sent to
+pub trait JobPrinter: Send + 'static {
+    /// Print a whole job
+    fn print_job(&mut self, job: &Document) -> Result<(), PrinterError>;
+
+    /// If a job that failed with `err` could succeed when tried again, like
+    /// after the paper is replaced
+    fn is_recoverable(&mut self, err: &PrinterError) -> bool;
+}
+
+/// If the printer reports a problem that goes away by itself or with the
+/// help of the user
+fn recoverable<D: Device>(printer: &Printer<D>) -> bool {
+    match printer.offline_status() {
+        Ok(status) if status.cover_open || status.paper_end => true,
+        Ok(status) if status.error => printer
+            .error_status()
+            .is_ok_and(|error| error.auto_recoverable),
+        _ => false,
+    }
+}
+
+impl<D: Device + Send + 'static> JobPrinter for Printer<D> {
+    fn print_job(&mut self, job: &Document) -> Result<(), PrinterError> {
+        self.submit(job)
+    }
+
+    fn is_recoverable(&mut self, _err: &PrinterError) -> bool {
+        recoverable(self)
+    }
+}
+
+impl JobPrinter for ReconnectingPrinter {
+    fn print_job(&mut self, job: &Document) -> Result<(), PrinterError> {
+        self.run(|printer| printer.submit(job))
+    }
+
+    fn is_recoverable(&mut self, err: &PrinterError) -> bool {
+        // The printer did not come back in time
+        if let PrinterError::Reconnect(_) = err {
+            return false;
+        }
+
+        self.run(|printer| Ok::<_, PrinterError>(recoverable(printer)))
+            .unwrap_or(false)
+    }
+}
+
+/// Jobs restored from a journal, by id
+type Restored = BTreeMap<JobId, (Priority, Vec<u8>)>;
+
+/// The journal is compacted once it has this many entries and most of them
+/// are about finished jobs
+const COMPACT_ENTRIES: usize = 64;
+
+/// A line of the journal
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Entry {
+    Submit {
+        id: JobId,
+        priority: Priority,
+        #[serde(with = "base64_data")]
+        data: Vec<u8>,
+    },
+    /// Printed, failed or cancelled
+    Done { id: JobId },
+}
+
+/// Job data as base64 text, rather than a JSON array of numbers
+mod base64_data {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        base64::decode(String::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+}
+
+/// Jobs that have not been printed yet, as JSON lines
+struct Journal {
+    path: PathBuf,
+    file: File,
+    /// Lines in the file
+    entries: usize,
+}
+
+impl Journal {
+    /// Open a journal, returning the jobs it still has pending
+    ///
+    /// The journal is rewritten to contain only those jobs.
+    fn open(path: &Path) -> Result<(Self, Restored), QueueError> {
+        let mut pending = BTreeMap::new();
+
+        let lines = match File::open(path) {
+            Ok(file) => BufReader::new(file)
+                .lines()
+                .collect::<Result<Vec<_>, _>>()?,
+            Err(err) if err.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        for (i, line) in lines.iter().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            match serde_json::from_str(line) {
+                Ok(Entry::Submit { id, priority, data }) => {
+                    pending.insert(id, (priority, data));
+                }
+                Ok(Entry::Done { id }) => {
+                    pending.remove(&id);
+                }
+                // Cut off by a crash while it was written
+                Err(err) if i == lines.len() - 1 => {
+                    warn!(%err, "Ignoring an incomplete last entry in the job journal")
+                }
+                Err(err) => return Err(QueueError::Journal { line: i + 1, err }),
+            }
+        }
+
+        let journal = Self::rewrite(
+            path,
+            pending
+                .iter()
+                .map(|(&id, (priority, data))| (id, *priority, data.as_slice())),
+        )?;
+
+        Ok((journal, pending))
+    }
+
+    /// Replace the journal at `path` with one holding just `jobs`
+    ///
+    /// The new journal is written next to the old one first, so a crash
+    /// leaves one or the other.
+    fn rewrite<'a>(
+        path: &Path,
+        jobs: impl Iterator<Item = (JobId, Priority, &'a [u8])>,
+    ) -> Result<Self, QueueError> {
+        let mut temporary = OsString::from(path.as_os_str());
+        temporary.push(".tmp");
+        let temporary = PathBuf::from(temporary);
+
+        let mut journal = Self {
+            path: path.to_owned(),
+            file: File::create(&temporary)?,
+            entries: 0,
+        };
+        for (id, priority, data) in jobs {
+            journal.append(&Entry::Submit {
+                id,
+                priority,
+                data: data.to_vec(),
+            })?;
+        }
+        fs::rename(&temporary, path)?;
+
+        Ok(Self {
+            file: OpenOptions::new().append(true).open(path)?,
+            ..journal
+        })
+    }
+
+    fn append(&mut self, entry: &Entry) -> Result<(), QueueError> {
+        let mut line = serde_json::to_vec(entry).expect("journal entries can be serialized");
+        line.push(b'\n');
+
+        self.file.write_all(&line)?;
+        self.file.sync_data()?;
+        self.entries += 1;
+
+        Ok(())
+    }
+}
+
+struct Job {
+    id: JobId,
+    data: Vec<u8>,
+    /// Told how the job went, unless the job was restored from the journal
+    done: Option<mpsc::Sender<Result<(), QueueError>>>,
+}
+
+struct State {
+    next_id: JobId,
+    pending: BTreeMap<(Reverse<Priority>, JobId), Job>,
+    /// Jobs being printed, still needed in the journal
+    printing: BTreeMap<JobId, (Priority, Vec<u8>)>,
+    journal: Option<Journal>,
+    retries: u32,
+    retry_delay: Duration,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    /// Signalled when a job is submitted or a worker is stopped
+    changed: Condvar,
+}
+
+/// A queue of print jobs, shared by everything that prints
+///
+/// Cloning a queue gives another handle to the same queue.
+#[derive(Clone)]
+pub struct JobQueue {
+    shared: Arc<Shared>,
+}
+
+/// A submitted job
+pub struct JobHandle {
+    id: JobId,
+    done: mpsc::Receiver<Result<(), QueueError>>,
+}
+
+/// Prints the jobs of a queue on a background thread, until stopped or
+/// dropped
+pub struct Worker<P: JobPrinter> {
+    shared: Arc<Shared>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<P>>,
+}
+
+impl Default for JobQueue {
+    fn default() -> Self {
+        Self::with_state(None, BTreeMap::new())
+    }
+}
+
+impl JobQueue {
+    /// Create a queue that is kept in memory only
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a queue with a journal at `path`, starting with the jobs it
+    /// still has pending
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, QueueError> {
+        let (journal, restored) = Journal::open(path.as_ref())?;
+
+        if !restored.is_empty() {
+            info!(jobs = restored.len(), "Restored pending print jobs");
+        }
+
+        Ok(Self::with_state(Some(journal), restored))
+    }
+
+    fn with_state(journal: Option<Journal>, restored: Restored) -> Self {
+        let next_id = restored.keys().next_back().map_or(1, |id| id + 1);
+        let pending = restored
+            .into_iter()
+            .map(|(id, (priority, data))| {
+                let job = Job {
+                    id,
+                    data,
+                    done: None,
+                };
+
+                ((Reverse(priority), id), job)
+            })
+            .collect();
+
+        Self {
+            shared: Arc::new(Shared {
+                state: Mutex::new(State {
+                    next_id,
+                    pending,
+                    printing: BTreeMap::new(),
+                    journal,
+                    retries: 3,
+                    retry_delay: Duration::from_secs(5),
+                }),
+                changed: Condvar::new(),
+            }),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        lock(&self.shared)
+    }
+
+    /// How often a job is tried again after a recoverable error, 3 times by
+    /// default
+    pub fn retries(self, retries: u32) -> Self {
+        self.state().retries = retries;
+        self
+    }
+
+    /// How long to wait before trying a job again, 5 seconds by default
+    pub fn retry_delay(self, delay: Duration) -> Self {
+        self.state().retry_delay = delay;
+        self
+    }
+
+    /// Add a job to the queue
+    pub fn submit(&self, document: Document, priority: Priority) -> Result<JobHandle, QueueError> {
+        let data = document.into_bytes();
+        let (done, receiver) = mpsc::channel();
+
+        let mut state = self.state();
+        let id = state.next_id;
+
+        if let Some(journal) = &mut state.journal {
+            journal.append(&Entry::Submit {
+                id,
+                priority,
+                data: data.clone(),
+            })?;
+        }
+
+        state.next_id += 1;
+        state.pending.insert(
+            (Reverse(priority), id),
+            Job {
+                id,
+                data,
+                done: Some(done),
+            },
+        );
+        drop(state);
+
+        self.shared.changed.notify_one();
+
+        Ok(JobHandle { id, done: receiver })
+    }
+
+    /// Remove a job that has not started printing yet
+    ///
+    /// Returns if the job was still waiting.
+    pub fn cancel(&self, id: JobId) -> Result<bool, QueueError> {
+        let mut state = self.state();
+
+        let key = match state.pending.keys().find(|(_, job)| *job == id) {
+            Some(&key) => key,
+            None => return Ok(false),
+        };
+
+        if let Some(journal) = &mut state.journal {
+            journal.append(&Entry::Done { id })?;
+        }
+
+        let job = state.pending.remove(&key).expect("the job is pending");
+        state.compact_journal();
+        drop(state);
+
+        info!(id, "Cancelled print job");
+
+        if let Some(done) = job.done {
+            let _ = done.send(Err(QueueError::Cancelled));
+        }
+
+        Ok(true)
+    }
+
+    /// The jobs waiting to be printed, in the order they will be printed
+    pub fn pending(&self) -> Vec<(JobId, Priority)> {
+        self.state()
+            .pending
+            .keys()
+            .map(|&(Reverse(priority), id)| (id, priority))
+            .collect()
+    }
+
+    /// Start printing the jobs of the queue
+    pub fn spawn_worker<P: JobPrinter>(&self, mut printer: P) -> Worker<P> {
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread = thread::spawn({
+            let shared = self.shared.clone();
+            let stop = stop.clone();
+
+            move || {
+                work(&shared, &stop, &mut printer);
+                printer
+            }
+        });
+
+        Worker {
+            shared: self.shared.clone(),
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl State {
+    /// Rewrite the journal once it is mostly entries about finished jobs
+    fn compact_journal(&mut self) {
+        let live = self.pending.len() + self.printing.len();
+        let journal = match &mut self.journal {
+            Some(journal) if journal.entries >= COMPACT_ENTRIES && journal.entries > 2 * live => {
+                journal
+            }
+            _ => return,
+        };
+
+        let pending = self
+            .pending
+            .iter()
+            .map(|(&(Reverse(priority), id), job)| (id, priority, job.data.as_slice()));
+        let printing = self
+            .printing
+            .iter()
+            .map(|(&id, (priority, data))| (id, *priority, data.as_slice()));
+
+        // The old journal stays in use if this fails
+        match Journal::rewrite(&journal.path, pending.chain(printing)) {
+            Ok(compacted) => *journal = compacted,
+            Err(err) => warn!(%err, "Failed to compact the job journal"),
+        }
+    }
+}
+
+fn lock(shared: &Shared) -> MutexGuard<'_, State> {
+    shared
+        .state
+        .lock()
+        .expect("the print queue lock was poisoned")
+}
+
+/// Print jobs until told to stop
+fn work<P: JobPrinter>(shared: &Shared, stop: &AtomicBool, printer: &mut P) {
+    loop {
+        let mut state = lock(shared);
+        let job = loop {
+            if stop.load(Ordering::Relaxed) {
+                return;
+            }
+
+            if let Some(((Reverse(priority), id), job)) = state.pending.pop_first() {
+                // Kept for the journal until the job is done
+                if state.journal.is_some() {
+                    state.printing.insert(id, (priority, job.data.clone()));
+                }
+                break job;
+            }
+
+            state = shared
+                .changed
+                .wait(state)
+                .expect("the print queue lock was poisoned");
+        };
+        let (retries, retry_delay) = (state.retries, state.retry_delay);
+        drop(state);
+
+        let Job { id, data, done } = job;
+        let result = print(
+            printer,
+            id,
+            Document::from_bytes(data),
+            retries,
+            retry_delay,
+        );
+
+        let mut state = lock(shared);
+        state.printing.remove(&id);
+        if let Some(journal) = &mut state.journal {
+            if let Err(err) = journal.append(&Entry::Done { id }) {
+                warn!(id, %err, "Failed to record a finished print job");
+            }
+        }
+        state.compact_journal();
+        drop(state);
+
+        if let Some(done) = done {
+            let _ = done.send(result);
+        }
+    }
+}
+
+fn print<P: JobPrinter>(
+    printer: &mut P,
+    id: JobId,
+    document: Document,
+    retries: u32,
+    retry_delay: Duration,
+) -> Result<(), QueueError> {
+    let mut attempts = 0;
+
+    loop {
+        match printer.print_job(&document) {
+            Ok(()) => {
+                info!(id, "Printed job");
+
+                return Ok(());
+            }
+            Err(err) if attempts < retries && printer.is_recoverable(&err) => {
+                attempts += 1;
+                warn!(id, %err, attempts, "Failed to print job, trying again");
+
+                thread::sleep(retry_delay);
+            }
+            Err(err) => {
+                warn!(id, %err, "Failed to print job");
+
+                return Err(err.into());
+            }
+        }
+    }
+}
+
+impl JobHandle {
+    pub fn id(&self) -> JobId {
+        self.id
+    }
+
+    /// Wait for the job to be printed
+    ///
+    /// Fails if the job could not be printed, was cancelled, or the queue
+    /// stopped before getting to it.
+    pub fn wait(self) -> Result<(), QueueError> {
+        self.done.recv().unwrap_or(Err(QueueError::Stopped))
+    }
+}
+
+impl<P: JobPrinter> Worker<P> {
+    /// Stop printing after the current job and give back the printer
+    ///
+    /// Jobs that are still pending stay in the queue.
+    pub fn stop(mut self) -> P {
+        self.signal_stop();
+
+        let thread = self.thread.take().expect("the worker is running");
+        thread
+            .join()
+            .unwrap_or_else(|err| panic::resume_unwind(err))
+    }
+
+    fn signal_stop(&self) {
+        // Set while holding the lock, so the worker can not miss it between
+        // checking and waiting
+        let _state = lock(&self.shared);
+        self.stop.store(true, Ordering::Relaxed);
+        self.shared.changed.notify_all();
+    }
+}
+
+impl<P: JobPrinter> Drop for Worker<P> {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            self.signal_stop();
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockDevice;
+
+    /// A journal path of its own for every test
+    fn journal_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "thermal-queue-{}-{}.jsonl",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn document(text: &str) -> Document {
+        let document = Printer::document();
+        document.print(text).unwrap();
+        document.into_device()
+    }
+
+    fn lines(path: &Path) -> Vec<String> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(str::to_owned)
+            .collect()
+    }
+
+    #[test]
+    fn data_is_base64() {
+        let path = journal_path("base64");
+        let queue = JobQueue::open(&path).unwrap();
+
+        queue.submit(document("Hi!"), Priority::High).unwrap();
+
+        assert_eq!(
+            lines(&path),
+            [r#"{"op":"submit","id":1,"priority":"high","data":"SGkh"}"#]
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn pending_jobs_are_restored() {
+        let path = journal_path("restore");
+
+        let queue = JobQueue::open(&path).unwrap();
+        queue.submit(document("a"), Priority::Low).unwrap();
+        let cancelled = queue.submit(document("b"), Priority::Normal).unwrap();
+        queue.submit(document("c"), Priority::High).unwrap();
+        assert!(queue.cancel(cancelled.id()).unwrap());
+        drop(queue);
+
+        let queue = JobQueue::open(&path).unwrap();
+        assert_eq!(queue.pending(), [(3, Priority::High), (1, Priority::Low)]);
+        // Reopening drops the entries of finished jobs
+        assert_eq!(lines(&path).len(), 2);
+
+        let printer = queue.spawn_worker(Printer::new(MockDevice::new()));
+        let next = queue.submit(document("d"), Priority::Low).unwrap();
+        assert_eq!(next.id(), 4);
+        next.wait().unwrap();
+
+        assert_eq!(printer.stop().device().written(), b"cad");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn journal_is_compacted() {
+        let path = journal_path("compact");
+        let queue = JobQueue::open(&path).unwrap();
+        let worker = queue.spawn_worker(Printer::new(MockDevice::new()));
+
+        for i in 0..COMPACT_ENTRIES {
+            queue
+                .submit(document(&i.to_string()), Priority::Normal)
+                .unwrap()
+                .wait()
+                .unwrap();
+        }
+        worker.stop();
+
+        assert!(lines(&path).len() < COMPACT_ENTRIES);
+
+        // Pending jobs survive compaction
+        queue.submit(document("kept"), Priority::Normal).unwrap();
+        for _ in 0..COMPACT_ENTRIES {
+            let job = queue.submit(document("x"), Priority::Low).unwrap();
+            queue.cancel(job.id()).unwrap();
+        }
+
+        assert!(lines(&path).len() < COMPACT_ENTRIES);
+        drop(queue);
+        assert_eq!(
+            JobQueue::open(&path).unwrap().pending(),
+            [(COMPACT_ENTRIES as JobId + 1, Priority::Normal)]
+        );
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,300 @@
+//! Printing on slip and validation paper
+//!
+//! Cut sheets have to be inserted by hand, so printing on them starts with
+//! waiting for the paper:
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use thermal::{Printer, SlipSide};
+//! # fn example(printer: &Printer) -> Result<(), Box<dyn std::error::Error>> {
+//! let slip = printer
+//!     .slip()
+//!     .side(SlipSide::Face)
+//!     .timeout(Duration::from_secs(30))
+//!     .insert()?;
+//!
+//! slip.println("PAID")?;
+//! slip.eject()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    ops::Deref,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::{PrinterError, SlipError},
+    status::SlipStatus,
+    Device, PaperType, Printer, SlipSide,
+};
+
+/// How often the paper sensors are checked while waiting for insertion
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Waiting for a sheet of slip or validation paper to be inserted
+///
+/// Created with [`Printer::slip`] or [`Printer::validation`].
+pub struct SlipJob<'a, D: Device> {
+    printer: &'a Printer<D>,
+    paper_type: PaperType,
+    side: SlipSide,
+    timeout: Duration,
+    delay: Duration,
+}
+
+/// A sheet of slip or validation paper that is ready to be printed on
+///
+/// Dereferences to the printer, so everything printed goes to the sheet
+/// until it is ejected or released.
+pub struct InsertedSlip<'a, D: Device> {
+    printer: &'a Printer<D>,
+    paper_type: PaperType,
+}
+
+impl<D: Device> Printer<D> {
+    /// Start a job on slip paper
+    pub fn slip(&self) -> SlipJob<'_, D> {
+        SlipJob::new(self, PaperType::Slip)
+    }
+
+    /// Start a job on validation paper
+    pub fn validation(&self) -> SlipJob<'_, D> {
+        SlipJob::new(self, PaperType::Validation)
+    }
+
+    /// Set cut sheet wait time
+    ///
+    /// Waits **t1** minutes for a cut sheet to be inserted, then starts
+    /// printing [**t2** × 0.1 seconds] after it has been detected
+    ///
+    /// ASCII: ESC f **t1** **t2**
+    pub fn slip_wait_time(&self, minutes: u8, delay: u8) -> Result<(), PrinterError> {
+        self.raw([Self::ESC, b'f', minutes.min(64), delay.min(64)])
+    }
+
+    /// Release paper
+    ///
+    /// Releases the cut sheet so it can be taken out by hand
+    ///
+    /// ASCII: ESC q
+    pub fn release_slip(&self) -> Result<(), PrinterError> {
+        self.raw([Self::ESC, b'q'])
+    }
+
+    /// Print and eject cut sheet
+    ///
+    /// Prints the data in the print buffer and ejects the cut sheet
+    ///
+    /// ASCII: FF
+    pub fn eject_slip(&self) -> Result<(), PrinterError> {
+        self.form_feed()
+    }
+}
+
+impl<'a, D: Device> SlipJob<'a, D> {
+    fn new(printer: &'a Printer<D>, paper_type: PaperType) -> Self {
+        Self {
+            printer,
+            paper_type,
+            side: SlipSide::Face,
+            timeout: Duration::from_secs(60),
+            delay: Duration::from_secs(1),
+        }
+    }
+
+    /// The side of the slip to print on
+    ///
+    /// Only applies to slip paper, validation paper is always printed on the
+    /// face.
+    pub fn side(mut self, side: SlipSide) -> Self {
+        self.side = side;
+        self
+    }
+
+    /// How long to wait for the paper to be inserted
+    ///
+    /// The printer only counts whole minutes, up to 64, so it keeps waiting a
+    /// little longer than this when the status can not be read.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How long to wait after the paper was detected before printing
+    ///
+    /// Gives the operator time to let go of the sheet. At most 6.4 seconds.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Select the paper and wait for it to be inserted
+    ///
+    /// On a timeout the paper path is released and roll paper is selected
+    /// again. Printers that can not report their status are left waiting for
+    /// the paper in hardware, with printing held back until it arrives.
+    pub fn insert(self) -> Result<InsertedSlip<'a, D>, SlipError> {
+        let printer = self.printer;
+
+        let (supported, feature) = match self.paper_type {
+            PaperType::Validation => (printer.profile.validation, "validation paper"),
+            _ => (printer.profile.slip, "slip paper"),
+        };
+        if !supported {
+            return Err(PrinterError::Unsupported {
+                feature,
+                profile: printer.profile.name,
+            }
+            .into());
+        }
+
+        let minutes = self.timeout.as_secs().div_ceil(60).clamp(1, 64) as u8;
+        let delay = (self.delay.as_millis() / 100).min(64) as u8;
+        printer.slip_wait_time(minutes, delay)?;
+
+        match self.paper_type {
+            PaperType::Slip => printer.slip_side(self.side)?,
+            paper_type => printer.paper_type(paper_type)?,
+        }
+
+        let slip = InsertedSlip::new(printer, self.paper_type);
+
+        let start = Instant::now();
+        loop {
+            match slip.status() {
+                Ok(status) if status.paper_present => break,
+                Ok(_) => {}
+                Err(PrinterError::NotReadable) => break,
+                Err(err) => return Err(err.into()),
+            }
+
+            if start.elapsed() >= self.timeout {
+                printer.release_slip()?;
+                printer.paper_type(PaperType::Roll)?;
+
+                return Err(SlipError::Timeout(self.timeout));
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        // Let the printer's own delay pass before handing the paper over
+        thread::sleep(self.delay.min(Duration::from_millis(6400)));
+
+        Ok(slip)
+    }
+}
+
+impl<'a, D: Device> InsertedSlip<'a, D> {
+    pub(crate) fn new(printer: &'a Printer<D>, paper_type: PaperType) -> Self {
+        Self {
+            printer,
+            paper_type,
+        }
+    }
+
+    /// The paper sensors of the inserted sheet
+    pub fn status(&self) -> Result<SlipStatus, PrinterError> {
+        match self.paper_type {
+            PaperType::Validation => self.printer.validation_status(),
+            _ => self.printer.slip_status(),
+        }
+    }
+
+    /// Print what is left in the buffer, eject the sheet and switch back to
+    /// roll paper
+    pub fn eject(self) -> Result<&'a Printer<D>, PrinterError> {
+        self.printer.eject_slip()?;
+        self.printer.paper_type(PaperType::Roll)?;
+
+        Ok(self.printer)
+    }
+
+    /// Release the sheet without ejecting it and switch back to roll paper
+    pub fn release(self) -> Result<&'a Printer<D>, PrinterError> {
+        self.printer.release_slip()?;
+        self.printer.paper_type(PaperType::Roll)?;
+
+        Ok(self.printer)
+    }
+}
+
+impl<D: Device> Deref for InsertedSlip<'_, D> {
+    type Target = Printer<D>;
+
+    fn deref(&self) -> &Self::Target {
+        self.printer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{profile::Profile, MockDevice};
+
+    #[test]
+    fn paper_present() {
+        let printer = Printer::new(MockDevice::new());
+        // The slip paper sensor reports "off" while it sees paper
+        printer.device().reply([0x12]);
+
+        let slip = printer
+            .slip()
+            .side(SlipSide::Back)
+            .timeout(Duration::from_secs(90))
+            .delay(Duration::ZERO)
+            .insert()
+            .unwrap();
+        slip.eject().unwrap();
+
+        assert_eq!(
+            printer.device().written(),
+            [
+                0x1B, b'f', 2, 0, // Wait time
+                0x1D, b'(', b'G', 2, 0, 48, 0x44, // Back of the slip
+                0x10, 0x04, 5,    // Slip status
+                0x0C, // Eject
+                0x1B, b'c', b'0', 0b0011, // Roll paper
+            ]
+        );
+    }
+
+    #[test]
+    fn timeout() {
+        let printer = Printer::new(MockDevice::new());
+        printer.device().reply([0x32]);
+
+        assert!(matches!(
+            printer
+                .validation()
+                .timeout(Duration::ZERO)
+                .delay(Duration::from_millis(500))
+                .insert(),
+            Err(SlipError::Timeout(Duration::ZERO))
+        ));
+        assert_eq!(
+            printer.device().written(),
+            [
+                0x1B, b'f', 1, 5, // Wait time
+                0x1B, b'c', b'0', 0b1000, // Validation paper
+                0x10, 0x04, 6, // Validation status
+                0x1B, b'q', // Release
+                0x1B, b'c', b'0', 0b0011, // Roll paper
+            ]
+        );
+    }
+
+    #[test]
+    fn unsupported() {
+        let printer = Printer::with_profile(MockDevice::new(), Profile::POS_80);
+
+        assert!(matches!(
+            printer.slip().insert(),
+            Err(SlipError::Printer(PrinterError::Unsupported { .. }))
+        ));
+        assert!(printer.device().written().is_empty());
+    }
+}
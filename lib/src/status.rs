@@ -0,0 +1,224 @@
+//! Real-time status of the printer
+//!
+//! Status is requested with DLE EOT, which the printer answers right away
+//! even while it is busy or offline. Every status is a single byte, where a
+//! few fixed bits tell it apart from other data the printer sends.
+
+use std::time::{Duration, Instant};
+
+use crate::{error::PrinterError, Device, Printer};
+
+const DLE: u8 = 0x10;
+const EOT: u8 = 0x04;
+
+/// General printer status (DLE EOT 1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrinterStatus {
+    /// Pin 3 of the drawer kick-out connector is high
+    pub drawer_sensor: bool,
+    pub offline: bool,
+    /// Waiting for an error to be recovered from
+    pub waiting_for_recovery: bool,
+    /// The paper feed button is held down
+    pub feed_button: bool,
+}
+
+/// Why the printer is offline (DLE EOT 2)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OfflineStatus {
+    pub cover_open: bool,
+    /// Paper is being fed with the paper feed button
+    pub feed_button: bool,
+    /// Printing stopped because the paper ran out
+    pub paper_end: bool,
+    pub error: bool,
+}
+
+/// Which error occurred (DLE EOT 3)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorStatus {
+    pub mechanical: bool,
+    pub autocutter: bool,
+    pub unrecoverable: bool,
+    /// An error that clears by itself, like an overheated print head
+    pub auto_recoverable: bool,
+}
+
+/// Roll paper sensors (DLE EOT 4)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RollPaperStatus {
+    pub near_end: bool,
+    pub end: bool,
+}
+
+/// Slip or validation paper sensors (DLE EOT 5 and DLE EOT 6)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlipStatus {
+    /// The paper is selected as the active sheet
+    pub selected: bool,
+    /// The printer is waiting for the paper to be inserted
+    pub waiting_for_insertion: bool,
+    /// Paper is detected at the top of form sensor
+    pub paper_present: bool,
+    /// Paper is detected at the bottom of form sensor
+    pub bottom_present: bool,
+}
+
+impl SlipStatus {
+    fn from_byte(status: u8) -> Self {
+        // The sensors report "off" when active
+        Self {
+            selected: status & 0x04 == 0,
+            waiting_for_insertion: status & 0x08 == 0,
+            paper_present: status & 0x20 == 0,
+            bottom_present: status & 0x40 == 0,
+        }
+    }
+}
+
+impl<D: Device> Printer<D> {
+    /// Transmit real-time status
+    ///
+    /// ASCII: DLE EOT **n**
+    fn real_time_status(&self, n: u8) -> Result<u8, PrinterError> {
+        self.raw([DLE, EOT, n])?;
+
+        let mut status = [0];
+        if self.device.read(&mut status, Self::REPLY_TIMEOUT)? == 0 {
+            return Err(PrinterError::NoResponse);
+        }
+
+        // Status bytes are always 0xx1xx10
+        match status[0] {
+            status if status & 0x93 == 0x12 => Ok(status),
+            status => Err(PrinterError::InvalidStatus(status)),
+        }
+    }
+
+    /// Read a reply terminated by NUL, that is at least `min_len` bytes long
+    /// before the NUL
+    ///
+    /// Fails with [`PrinterError::NoResponse`] unless the whole reply arrives
+    /// within `timeout`.
+    pub(crate) fn read_reply(
+        &self,
+        min_len: usize,
+        timeout: Duration,
+    ) -> Result<Vec<u8>, PrinterError> {
+        let start = Instant::now();
+        let mut reply = Vec::new();
+        let mut buf = [0; 64];
+
+        while reply.len() <= min_len || reply.last() != Some(&0) {
+            // A timeout of 0 would wait forever
+            let remaining = timeout
+                .checked_sub(start.elapsed())
+                .filter(|remaining| *remaining >= Duration::from_millis(1))
+                .ok_or(PrinterError::NoResponse)?;
+
+            let len = self.device.read(&mut buf, remaining)?;
+            reply.extend_from_slice(&buf[..len]);
+        }
+
+        reply.pop();
+
+        Ok(reply)
+    }
+
+    /// Transmit printer status
+    ///
+    /// ASCII: DLE EOT 1
+    pub fn status(&self) -> Result<PrinterStatus, PrinterError> {
+        let status = self.real_time_status(1)?;
+
+        Ok(PrinterStatus {
+            drawer_sensor: status & 0x04 != 0,
+            offline: status & 0x08 != 0,
+            waiting_for_recovery: status & 0x20 != 0,
+            feed_button: status & 0x40 != 0,
+        })
+    }
+
+    /// Transmit offline cause status
+    ///
+    /// ASCII: DLE EOT 2
+    pub fn offline_status(&self) -> Result<OfflineStatus, PrinterError> {
+        let status = self.real_time_status(2)?;
+
+        Ok(OfflineStatus {
+            cover_open: status & 0x04 != 0,
+            feed_button: status & 0x08 != 0,
+            paper_end: status & 0x20 != 0,
+            error: status & 0x40 != 0,
+        })
+    }
+
+    /// Transmit error cause status
+    ///
+    /// ASCII: DLE EOT 3
+    pub fn error_status(&self) -> Result<ErrorStatus, PrinterError> {
+        let status = self.real_time_status(3)?;
+
+        Ok(ErrorStatus {
+            mechanical: status & 0x04 != 0,
+            autocutter: status & 0x08 != 0,
+            unrecoverable: status & 0x20 != 0,
+            auto_recoverable: status & 0x40 != 0,
+        })
+    }
+
+    /// Transmit roll paper sensor status
+    ///
+    /// ASCII: DLE EOT 4
+    pub fn roll_paper_status(&self) -> Result<RollPaperStatus, PrinterError> {
+        let status = self.real_time_status(4)?;
+
+        Ok(RollPaperStatus {
+            near_end: status & 0x0C != 0,
+            end: status & 0x60 != 0,
+        })
+    }
+
+    /// Transmit slip status
+    ///
+    /// ASCII: DLE EOT 5
+    pub fn slip_status(&self) -> Result<SlipStatus, PrinterError> {
+        Ok(SlipStatus::from_byte(self.real_time_status(5)?))
+    }
+
+    /// Transmit validation status
+    ///
+    /// ASCII: DLE EOT 6
+    pub fn validation_status(&self) -> Result<SlipStatus, PrinterError> {
+        Ok(SlipStatus::from_byte(self.real_time_status(6)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockDevice;
+
+    #[test]
+    fn replies_end_with_nul() {
+        let printer = Printer::new(MockDevice::new());
+        printer.device().reply(b"_12");
+        printer.device().reply(b"3\0");
+
+        assert_eq!(
+            printer.read_reply(1, Duration::from_secs(1)).unwrap(),
+            b"_123"
+        );
+    }
+
+    #[test]
+    fn reply_timeout_under_a_millisecond() {
+        let printer = Printer::new(MockDevice::new());
+        printer.device().reply(b"_1\0");
+
+        // Reading with a timeout rounded down to 0 would wait forever
+        let result = printer.read_reply(1, Duration::from_micros(500));
+
+        assert!(matches!(result, Err(PrinterError::NoResponse)));
+    }
+}
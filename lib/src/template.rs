@@ -0,0 +1,1208 @@
+//! Receipt templates
+//!
+//! Templates are receipts written in [markup](crate::markup) with
+//! placeholders that are filled in from data, so the same layout can be
+//! printed for every order:
+//!
+//! ```text
+//! <align value="center"><b>{{ shop.name | upper }}</b>
+//! {{ date | date: "%d/%m/%Y %H:%M" }}</align>
+//! <hr/>
+//! {% for item in items %}
+//! <columns><col width="3">{{ item.quantity }}</col><col>{{ item.name }}</col><col width="9" align="right">{{ item.price | currency: "$" }}</col></columns>
+//! {% endfor %}
+//! <hr/>
+//! {% if discount %}
+//! Discount {{ discount | currency: "$" | pad_left: 39 }}
+//! {% endif %}
+//! <b>Total {{ total | currency: "$" | pad_left: 42 }}</b>
+//! ```
+//!
+//! `{{ value }}` prints a value, `{% if %}` / `{% elif %}` / `{% else %}` /
+//! `{% endif %}` print parts of the template conditionally and
+//! `{% for name in list %}` / `{% endfor %}` repeat them for every element of
+//! a list. Inside a loop `loop.index`, `loop.first`, `loop.last` and
+//! `loop.length` describe the current iteration. `{# comments #}` are left
+//! out. Lines with nothing but a `{% %}` tag on them are left out entirely.
+//!
+//! Values are looked up by their path in the data, like `order.items.0.name`.
+//! Missing values are empty, and printed values are escaped so they can not
+//! add tags to the markup.
+//!
+//! Conditions can compare values with `==`, `!=`, `<`, `<=`, `>` and `>=`
+//! and be combined with `and`, `or` and `not`. `null`, `false`, `0`, empty
+//! strings, empty lists and empty objects are false.
+//!
+//! Values can be passed through filters, `value | filter: argument, ...`:
+//!
+//! | Filter | Arguments | |
+//! |--------|-----------|-|
+//! | `upper`, `lower` | | Change the case of text |
+//! | `default` | value | Replace a false value |
+//! | `length` | | The length of text or a list |
+//! | `number` | decimals | A number with a fixed amount of decimals |
+//! | `currency` | symbol, decimals | An amount of money, like `$1,234.50` |
+//! | `date` | format | A [`strftime`](chrono::format::strftime) formatted date, from RFC 3339 text or a Unix timestamp |
+//! | `pad_left`, `pad_right`, `center` | width, character | Pad text to a width, aligning it right, left or in the center |
+//! | `truncate` | width | Cut text off with an ellipsis |
+
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc,
+};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::{
+    error::TemplateError,
+    markup::{self, escape, position, Images},
+    wrap::{self, width},
+    Device, Document, Printer, Profile,
+};
+
+const FILTERS: &[&str] = &[
+    "upper",
+    "lower",
+    "default",
+    "length",
+    "number",
+    "currency",
+    "date",
+    "pad_left",
+    "pad_right",
+    "center",
+    "truncate",
+];
+
+/// A parsed receipt template
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    source: String,
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Text(String),
+    Output(Expression),
+    If {
+        branches: Vec<(Condition, Vec<Node>)>,
+        otherwise: Vec<Node>,
+    },
+    For {
+        offset: usize,
+        name: String,
+        list: Expression,
+        body: Vec<Node>,
+    },
+}
+
+/// A value followed by filters
+#[derive(Debug, Clone, PartialEq)]
+struct Expression {
+    offset: usize,
+    operand: Operand,
+    filters: Vec<Filter>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    Path(Vec<String>),
+    Literal(Value),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Filter {
+    offset: usize,
+    name: String,
+    arguments: Vec<Operand>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Condition {
+    Value(Expression),
+    Compare(Expression, Comparison, Expression),
+    Not(Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+impl Template {
+    /// Parse a template
+    pub fn parse(source: &str) -> Result<Self, TemplateError> {
+        let mut parser = Parser { source, offset: 0 };
+
+        let (nodes, end) = parser.nodes(&[])?;
+        if let Some((offset, _, tag)) = end {
+            return Err(parser.error(offset, format!("unexpected {{% {} %}}", tag)));
+        }
+
+        Ok(Self {
+            source: source.to_owned(),
+            nodes,
+        })
+    }
+
+    /// Fill in the template, producing markup
+    pub fn render<T: Serialize>(&self, data: &T) -> Result<String, TemplateError> {
+        let data = serde_json::to_value(data)?;
+        let mut renderer = Renderer {
+            source: &self.source,
+            data: &data,
+            scopes: Vec::new(),
+            output: String::new(),
+        };
+
+        renderer.nodes(&self.nodes)?;
+
+        Ok(renderer.output)
+    }
+
+    /// Fill in the template and compile it into a document for the given profile
+    pub fn compile<T: Serialize>(
+        &self,
+        data: &T,
+        profile: &Profile,
+    ) -> Result<Document, TemplateError> {
+        Ok(markup::compile(&self.render(data)?, profile)?)
+    }
+
+    /// Fill in the template and compile it, loading its images from `images`
+    pub fn compile_with<T: Serialize>(
+        &self,
+        data: &T,
+        profile: &Profile,
+        images: &dyn Images,
+    ) -> Result<Document, TemplateError> {
+        Ok(markup::compile_with(&self.render(data)?, profile, images)?)
+    }
+}
+
+impl<D: Device> Printer<D> {
+    /// Fill in a template and print it
+    pub fn template<T: Serialize>(
+        &self,
+        template: &Template,
+        data: &T,
+    ) -> Result<(), TemplateError> {
+        Ok(self.markup(&template.render(data)?)?)
+    }
+
+    /// Fill in a template and print it, loading its images from `images`
+    pub fn template_with<T: Serialize>(
+        &self,
+        template: &Template,
+        data: &T,
+        images: &dyn Images,
+    ) -> Result<(), TemplateError> {
+        Ok(self.markup_with(&template.render(data)?, images)?)
+    }
+}
+
+fn error(source: &str, offset: usize, message: String) -> TemplateError {
+    let (line, column) = position(source, offset);
+
+    TemplateError::Template {
+        line,
+        column,
+        message,
+    }
+}
+
+/// A block tag ending a list of nodes, with its offset and the offset of its
+/// arguments
+type EndTag = (usize, usize, String);
+
+struct Parser<'a> {
+    source: &'a str,
+    offset: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, offset: usize, message: String) -> TemplateError {
+        error(self.source, offset, message)
+    }
+
+    /// Parse nodes up to one of the `end` block tags, returning the tag that
+    /// ended them
+    fn nodes(&mut self, end: &[&str]) -> Result<(Vec<Node>, Option<EndTag>), TemplateError> {
+        let mut nodes = Vec::new();
+
+        loop {
+            let rest = &self.source[self.offset..];
+            let start = ["{{", "{%", "{#"]
+                .iter()
+                .filter_map(|open| rest.find(open))
+                .min();
+
+            let start = match start {
+                Some(start) => start,
+                None => {
+                    push_text(&mut nodes, rest);
+                    self.offset = self.source.len();
+                    return Ok((nodes, None));
+                }
+            };
+
+            let text = &rest[..start];
+            let tag_offset = self.offset + start;
+            let open = &rest[start..start + 2];
+            let close = match open {
+                "{{" => "}}",
+                "{%" => "%}",
+                _ => "#}",
+            };
+            let inner_start = tag_offset + 2;
+            let inner_len = self.source[inner_start..]
+                .find(close)
+                .ok_or_else(|| self.error(tag_offset, format!("'{}' is never closed", open)))?;
+            let inner = &self.source[inner_start..inner_start + inner_len];
+            let mut after = inner_start + inner_len + 2;
+
+            if open == "{{" {
+                push_text(&mut nodes, text);
+                self.offset = after;
+                nodes.push(Node::Output(self.expression(inner_start, inner)?));
+                continue;
+            }
+
+            // Leave out lines with nothing but a block tag or comment
+            let line_start = text.rfind('\n').map_or(0, |i| i + 1);
+            let at_line_start = text[line_start..].trim().is_empty()
+                && (line_start > 0
+                    || self.offset == 0
+                    || self.source[..self.offset].ends_with('\n'));
+            let line_end = self.source[after..]
+                .find('\n')
+                .map_or(self.source.len(), |i| after + i + 1);
+            let text = if at_line_start && self.source[after..line_end].trim().is_empty() {
+                after = line_end;
+                &text[..line_start]
+            } else {
+                text
+            };
+
+            push_text(&mut nodes, text);
+            self.offset = after;
+
+            if open == "{#" {
+                continue;
+            }
+
+            let tag = inner.trim();
+            let tag_start = inner_start + (inner.len() - inner.trim_start().len());
+            let (keyword, arguments) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+            let arguments_offset = tag_start + (tag.len() - arguments.len());
+
+            if end.contains(&keyword) {
+                return Ok((nodes, Some((tag_offset, arguments_offset, tag.to_owned()))));
+            }
+
+            match keyword {
+                "if" => nodes.push(self.if_block(tag_offset, arguments_offset, arguments)?),
+                "for" => nodes.push(self.for_block(tag_offset, arguments_offset, arguments)?),
+                _ => {
+                    return Err(self.error(tag_offset, format!("unexpected {{% {} %}}", tag)));
+                }
+            }
+        }
+    }
+
+    fn if_block(
+        &mut self,
+        tag_offset: usize,
+        offset: usize,
+        condition: &str,
+    ) -> Result<Node, TemplateError> {
+        let mut branches = Vec::new();
+        let mut condition = self.condition(offset, condition)?;
+
+        loop {
+            let (body, end) = self.nodes(&["elif", "else", "endif"])?;
+            let (_, arguments_offset, end) =
+                end.ok_or_else(|| self.error(tag_offset, "{% if %} is never closed".to_owned()))?;
+
+            branches.push((condition, body));
+
+            let (keyword, arguments) = end.split_once(char::is_whitespace).unwrap_or((&end, ""));
+            match keyword {
+                "elif" => condition = self.condition(arguments_offset, arguments)?,
+                "else" => {
+                    let (otherwise, end) = self.nodes(&["endif"])?;
+                    end.ok_or_else(|| {
+                        self.error(tag_offset, "{% if %} is never closed".to_owned())
+                    })?;
+
+                    return Ok(Node::If {
+                        branches,
+                        otherwise,
+                    });
+                }
+                _ => {
+                    return Ok(Node::If {
+                        branches,
+                        otherwise: Vec::new(),
+                    })
+                }
+            }
+        }
+    }
+
+    fn for_block(
+        &mut self,
+        tag_offset: usize,
+        offset: usize,
+        arguments: &str,
+    ) -> Result<Node, TemplateError> {
+        let mut tokens = lex(self.source, offset, arguments)?.into_iter();
+
+        let name = match (tokens.next(), tokens.next()) {
+            (Some((_, Token::Word(name))), Some((_, Token::Word(keyword)))) if keyword == "in" => {
+                name
+            }
+            _ => return Err(self.error(offset, "expected {% for name in list %}".to_owned())),
+        };
+
+        let tokens: Vec<_> = tokens.collect();
+        let mut tokens = Tokens {
+            source: self.source,
+            end: offset + arguments.len(),
+            tokens: &tokens,
+            next: 0,
+        };
+        let list = tokens.expression()?;
+        tokens.finish()?;
+
+        let (body, end) = self.nodes(&["endfor"])?;
+        end.ok_or_else(|| self.error(tag_offset, "{% for %} is never closed".to_owned()))?;
+
+        Ok(Node::For {
+            offset: tag_offset,
+            name,
+            list,
+            body,
+        })
+    }
+
+    fn expression(&self, offset: usize, text: &str) -> Result<Expression, TemplateError> {
+        let tokens = lex(self.source, offset, text)?;
+        let mut tokens = Tokens {
+            source: self.source,
+            end: offset + text.len(),
+            tokens: &tokens,
+            next: 0,
+        };
+
+        let expression = tokens.expression()?;
+        tokens.finish()?;
+
+        Ok(expression)
+    }
+
+    fn condition(&self, offset: usize, text: &str) -> Result<Condition, TemplateError> {
+        let tokens = lex(self.source, offset, text)?;
+        let mut tokens = Tokens {
+            source: self.source,
+            end: offset + text.len(),
+            tokens: &tokens,
+            next: 0,
+        };
+
+        let condition = tokens.or()?;
+        tokens.finish()?;
+
+        Ok(condition)
+    }
+}
+
+/// Add text to the nodes, merging it with text before it
+fn push_text(nodes: &mut Vec<Node>, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+
+    match nodes.last_mut() {
+        Some(Node::Text(previous)) => previous.push_str(text),
+        _ => nodes.push(Node::Text(text.to_owned())),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Literal(Value),
+    Symbol(&'static str),
+}
+
+/// Split the inside of a tag into tokens, with their offsets in the source
+fn lex(source: &str, offset: usize, text: &str) -> Result<Vec<(usize, Token)>, TemplateError> {
+    const SYMBOLS: &[&str] = &["==", "!=", "<=", ">=", "<", ">", "|", ":", ","];
+
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+
+    while let Some(&(i, c)) = chars.peek() {
+        let start = offset + i;
+
+        if c.is_whitespace() {
+            chars.next();
+        } else if let Some(symbol) = SYMBOLS.iter().find(|symbol| text[i..].starts_with(*symbol)) {
+            for _ in 0..symbol.len() {
+                chars.next();
+            }
+            tokens.push((start, Token::Symbol(symbol)));
+        } else if c == '"' || c == '\'' {
+            chars.next();
+            let mut string = String::new();
+
+            loop {
+                match chars.next() {
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, 'n')) => string.push('\n'),
+                        Some((_, c)) => string.push(c),
+                        None => break,
+                    },
+                    Some((_, end)) if end == c => {
+                        tokens.push((start, Token::Literal(Value::String(string))));
+                        break;
+                    }
+                    Some((_, c)) => string.push(c),
+                    None => {
+                        return Err(error(source, start, "unterminated string".to_owned()));
+                    }
+                }
+            }
+        } else if c.is_ascii_digit() || c == '-' {
+            let mut number = String::new();
+            while let Some((_, c)) =
+                chars.next_if(|&(_, c)| c.is_ascii_digit() || c == '.' || c == '-')
+            {
+                number.push(c);
+            }
+
+            let value = number
+                .parse::<i64>()
+                .map(Value::from)
+                .or_else(|_| number.parse::<f64>().map(Value::from))
+                .map_err(|_| error(source, start, format!("invalid number '{}'", number)))?;
+            tokens.push((start, Token::Literal(value)));
+        } else if c.is_alphanumeric() || c == '_' {
+            let mut word = String::new();
+            while let Some((_, c)) =
+                chars.next_if(|&(_, c)| c.is_alphanumeric() || c == '_' || c == '.')
+            {
+                word.push(c);
+            }
+
+            tokens.push((
+                start,
+                match word.as_str() {
+                    "true" => Token::Literal(Value::Bool(true)),
+                    "false" => Token::Literal(Value::Bool(false)),
+                    "null" => Token::Literal(Value::Null),
+                    _ => Token::Word(word),
+                },
+            ));
+        } else {
+            return Err(error(source, start, format!("unexpected '{}'", c)));
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// A recursive descent parser over the tokens of a tag
+struct Tokens<'a> {
+    source: &'a str,
+    /// Offset of the end of the tag, for errors at the end
+    end: usize,
+    tokens: &'a [(usize, Token)],
+    next: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.next).map(|(_, token)| token)
+    }
+
+    fn offset(&self) -> usize {
+        self.tokens
+            .get(self.next)
+            .map_or(self.end, |&(offset, _)| offset)
+    }
+
+    fn error(&self, message: String) -> TemplateError {
+        error(self.source, self.offset(), message)
+    }
+
+    fn eat(&mut self, expected: &Token) -> bool {
+        if self.peek() == Some(expected) {
+            self.next += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_word(&mut self, word: &str) -> bool {
+        self.eat(&Token::Word(word.to_owned()))
+    }
+
+    fn finish(&self) -> Result<(), TemplateError> {
+        match self.peek() {
+            None => Ok(()),
+            Some(_) => Err(self.error("unexpected token".to_owned())),
+        }
+    }
+
+    fn or(&mut self) -> Result<Condition, TemplateError> {
+        let mut condition = self.and()?;
+
+        while self.eat_word("or") {
+            condition = Condition::Or(Box::new(condition), Box::new(self.and()?));
+        }
+
+        Ok(condition)
+    }
+
+    fn and(&mut self) -> Result<Condition, TemplateError> {
+        let mut condition = self.not()?;
+
+        while self.eat_word("and") {
+            condition = Condition::And(Box::new(condition), Box::new(self.not()?));
+        }
+
+        Ok(condition)
+    }
+
+    fn not(&mut self) -> Result<Condition, TemplateError> {
+        if self.eat_word("not") {
+            return Ok(Condition::Not(Box::new(self.not()?)));
+        }
+
+        let left = self.expression()?;
+        let comparison = match self.peek() {
+            Some(Token::Symbol("==")) => Comparison::Equal,
+            Some(Token::Symbol("!=")) => Comparison::NotEqual,
+            Some(Token::Symbol("<")) => Comparison::Less,
+            Some(Token::Symbol("<=")) => Comparison::LessOrEqual,
+            Some(Token::Symbol(">")) => Comparison::Greater,
+            Some(Token::Symbol(">=")) => Comparison::GreaterOrEqual,
+            _ => return Ok(Condition::Value(left)),
+        };
+        self.next += 1;
+
+        Ok(Condition::Compare(left, comparison, self.expression()?))
+    }
+
+    fn expression(&mut self) -> Result<Expression, TemplateError> {
+        let offset = self.offset();
+        let operand = self.operand()?;
+        let mut filters = Vec::new();
+
+        while self.eat(&Token::Symbol("|")) {
+            let offset = self.offset();
+            let name = match self.tokens.get(self.next) {
+                Some((_, Token::Word(name))) if FILTERS.contains(&name.as_str()) => name.clone(),
+                Some((_, Token::Word(name))) => {
+                    return Err(self.error(format!("unknown filter '{}'", name)))
+                }
+                _ => return Err(self.error("expected a filter name".to_owned())),
+            };
+            self.next += 1;
+
+            let mut arguments = Vec::new();
+            if self.eat(&Token::Symbol(":")) {
+                arguments.push(self.operand()?);
+
+                while self.eat(&Token::Symbol(",")) {
+                    arguments.push(self.operand()?);
+                }
+            }
+
+            filters.push(Filter {
+                offset,
+                name,
+                arguments,
+            });
+        }
+
+        Ok(Expression {
+            offset,
+            operand,
+            filters,
+        })
+    }
+
+    fn operand(&mut self) -> Result<Operand, TemplateError> {
+        let operand = match self.peek() {
+            Some(Token::Literal(value)) => Operand::Literal(value.clone()),
+            Some(Token::Word(path)) if !["and", "or", "not", "in"].contains(&path.as_str()) => {
+                Operand::Path(path.split('.').map(str::to_owned).collect())
+            }
+            _ => return Err(self.error("expected a value".to_owned())),
+        };
+        self.next += 1;
+
+        Ok(operand)
+    }
+}
+
+struct Renderer<'a> {
+    source: &'a str,
+    data: &'a Value,
+    /// Loop variables, innermost last
+    scopes: Vec<(String, Value)>,
+    output: String,
+}
+
+impl<'a> Renderer<'a> {
+    fn error(&self, offset: usize, message: String) -> TemplateError {
+        error(self.source, offset, message)
+    }
+
+    fn nodes(&mut self, nodes: &[Node]) -> Result<(), TemplateError> {
+        for node in nodes {
+            match node {
+                Node::Text(text) => self.output.push_str(text),
+                Node::Output(expression) => {
+                    let value = self.evaluate(expression)?;
+                    self.output.push_str(&escape(&display(&value)));
+                }
+                Node::If {
+                    branches,
+                    otherwise,
+                } => {
+                    let mut body = otherwise;
+
+                    for (condition, branch) in branches {
+                        if self.condition(condition)? {
+                            body = branch;
+                            break;
+                        }
+                    }
+
+                    self.nodes(body)?;
+                }
+                Node::For {
+                    offset,
+                    name,
+                    list,
+                    body,
+                } => {
+                    let items = match self.evaluate(list)? {
+                        Value::Array(items) => items,
+                        Value::Null => Vec::new(),
+                        value => {
+                            return Err(
+                                self.error(*offset, format!("can not loop over {}", kind(&value)))
+                            )
+                        }
+                    };
+                    let length = items.len();
+
+                    for (index, item) in items.into_iter().enumerate() {
+                        let mut info = Map::new();
+                        info.insert("index".to_owned(), Value::from(index + 1));
+                        info.insert("first".to_owned(), Value::from(index == 0));
+                        info.insert("last".to_owned(), Value::from(index + 1 == length));
+                        info.insert("length".to_owned(), Value::from(length));
+
+                        self.scopes.push(("loop".to_owned(), Value::Object(info)));
+                        self.scopes.push((name.clone(), item));
+                        let result = self.nodes(body);
+                        self.scopes.truncate(self.scopes.len() - 2);
+                        result?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn condition(&self, condition: &Condition) -> Result<bool, TemplateError> {
+        Ok(match condition {
+            Condition::Value(expression) => truthy(&self.evaluate(expression)?),
+            Condition::Not(condition) => !self.condition(condition)?,
+            Condition::And(left, right) => self.condition(left)? && self.condition(right)?,
+            Condition::Or(left, right) => self.condition(left)? || self.condition(right)?,
+            Condition::Compare(left, comparison, right) => {
+                let (left, right) = (self.evaluate(left)?, self.evaluate(right)?);
+                let ordering = match (&left, &right) {
+                    (Value::Number(a), Value::Number(b)) => a.as_f64().partial_cmp(&b.as_f64()),
+                    (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+                    _ => None,
+                };
+
+                match comparison {
+                    Comparison::Equal => ordering.map_or(left == right, |o| o.is_eq()),
+                    Comparison::NotEqual => ordering.map_or(left != right, |o| o.is_ne()),
+                    Comparison::Less => ordering.is_some_and(|o| o.is_lt()),
+                    Comparison::LessOrEqual => ordering.is_some_and(|o| o.is_le()),
+                    Comparison::Greater => ordering.is_some_and(|o| o.is_gt()),
+                    Comparison::GreaterOrEqual => ordering.is_some_and(|o| o.is_ge()),
+                }
+            }
+        })
+    }
+
+    fn lookup(&self, operand: &Operand) -> Value {
+        let path = match operand {
+            Operand::Literal(value) => return value.clone(),
+            Operand::Path(path) => path,
+        };
+
+        let root = self
+            .scopes
+            .iter()
+            .rev()
+            .find(|(name, _)| *name == path[0])
+            .map_or_else(|| self.data.get(&path[0]), |(_, value)| Some(value));
+
+        path[1..]
+            .iter()
+            .try_fold(root, |value, key| match (value, key.parse::<usize>()) {
+                (Some(Value::Array(items)), Ok(index)) => Some(items.get(index)),
+                (Some(value), _) => Some(value.get(key)),
+                (None, _) => None,
+            })
+            .flatten()
+            .cloned()
+            .unwrap_or(Value::Null)
+    }
+
+    fn evaluate(&self, expression: &Expression) -> Result<Value, TemplateError> {
+        let mut value = self.lookup(&expression.operand);
+
+        for filter in &expression.filters {
+            let arguments: Vec<Value> = filter.arguments.iter().map(|a| self.lookup(a)).collect();
+            value = apply(&filter.name, value, &arguments)
+                .map_err(|message| self.error(filter.offset, message))?;
+        }
+
+        Ok(value)
+    }
+}
+
+/// Apply a filter to a value
+fn apply(filter: &str, value: Value, arguments: &[Value]) -> Result<Value, String> {
+    let argument = |i: usize| arguments.get(i).filter(|a| !a.is_null());
+    let number_argument = |i: usize, name: &str| match argument(i) {
+        None => Ok(None),
+        Some(value) => value
+            .as_u64()
+            .map(|n| Some(n as usize))
+            .ok_or_else(|| format!("the {} of '{}' must be a whole number", name, filter)),
+    };
+    let number = |value: &Value| {
+        match value {
+            Value::Number(n) => n.as_f64(),
+            Value::String(s) => s.trim().parse().ok(),
+            _ => None,
+        }
+        .ok_or_else(|| format!("'{}' expects a number, not {}", filter, kind(value)))
+    };
+
+    Ok(match filter {
+        "upper" => Value::from(display(&value).to_uppercase()),
+        "lower" => Value::from(display(&value).to_lowercase()),
+        "default" if truthy(&value) => value,
+        "default" => argument(0).cloned().unwrap_or(Value::Null),
+        "length" => Value::from(match &value {
+            Value::Array(items) => items.len(),
+            Value::Object(map) => map.len(),
+            Value::Null => 0,
+            value => display(value).chars().count(),
+        }),
+        "number" => {
+            let decimals = number_argument(0, "amount of decimals")?.unwrap_or(0);
+
+            Value::from(format!("{:.*}", decimals, number(&value)?))
+        }
+        "currency" => {
+            let symbol = argument(0).map(display).unwrap_or_default();
+            let decimals = number_argument(1, "amount of decimals")?.unwrap_or(2);
+
+            Value::from(currency(number(&value)?, &symbol, decimals))
+        }
+        "date" => {
+            let format = argument(0).map_or_else(|| "%Y-%m-%d %H:%M".to_owned(), display);
+            if StrftimeItems::new(&format).any(|item| matches!(item, Item::Error)) {
+                return Err(format!("invalid date format '{}'", format));
+            }
+
+            let date =
+                date(&value).ok_or_else(|| format!("'{}' is not a date", display(&value)))?;
+
+            Value::from(date.format(&format).to_string())
+        }
+        "pad_left" | "pad_right" | "center" => {
+            let width = number_argument(0, "width")?.unwrap_or(0);
+            let fill = argument(1).map_or_else(|| " ".to_owned(), display);
+            let text = display(&value);
+            let free = width.saturating_sub(self::width(&text)) / self::width(&fill).max(1);
+            let left = match filter {
+                "pad_left" => free,
+                "center" => free / 2,
+                _ => 0,
+            };
+
+            Value::from(format!(
+                "{}{}{}",
+                fill.repeat(left),
+                text,
+                fill.repeat(free - left)
+            ))
+        }
+        "truncate" => {
+            let width = number_argument(0, "width")?.unwrap_or(0);
+            let text = display(&value);
+
+            if self::width(&text) <= width {
+                Value::from(text)
+            } else if width > 3 {
+                Value::from(format!("{}...", wrap::take(&text, width - 3).0))
+            } else {
+                Value::from(wrap::take(&text, width).0)
+            }
+        }
+        _ => unreachable!("unknown filters are rejected by the parser"),
+    })
+}
+
+/// Format an amount of money with thousands separators
+fn currency(amount: f64, symbol: &str, decimals: usize) -> String {
+    let formatted = format!("{:.*}", decimals, amount.abs());
+    let (integer, fraction) = formatted.split_at(formatted.find('.').unwrap_or(formatted.len()));
+
+    let mut grouped = String::new();
+    for (i, c) in integer.chars().enumerate() {
+        if i > 0 && (integer.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(c);
+    }
+
+    let sign = if amount < 0.0 && formatted.chars().any(|c| c.is_ascii_digit() && c != '0') {
+        "-"
+    } else {
+        ""
+    };
+
+    format!("{}{}{}{}", sign, symbol, grouped, fraction)
+}
+
+/// Read a date from RFC 3339 text, a plain date or time, or a Unix timestamp
+fn date(value: &Value) -> Option<DateTime<Utc>> {
+    match value {
+        Value::Number(n) => Utc.timestamp_opt(n.as_i64()?, 0).single(),
+        Value::String(s) => {
+            if let Ok(date) = DateTime::parse_from_rfc3339(s) {
+                return Some(date.with_timezone(&Utc));
+            }
+
+            let date = NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S")
+                .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S"))
+                .ok()
+                .or_else(|| {
+                    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                        .ok()?
+                        .and_hms_opt(0, 0, 0)
+                })?;
+
+            Some(Utc.from_utc_datetime(&date))
+        }
+        _ => None,
+    }
+}
+
+/// Text to print for a value
+fn display(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64() != Some(0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(map) => !map.is_empty(),
+    }
+}
+
+/// The kind of a value, for errors
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "text",
+        Value::Array(_) => "a list",
+        Value::Object(_) => "an object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn render(source: &str, data: Value) -> String {
+        Template::parse(source).unwrap().render(&data).unwrap()
+    }
+
+    /// The line, column and message of a parse or render error
+    fn error(source: &str, data: Value) -> (usize, usize, String) {
+        let err = Template::parse(source)
+            .and_then(|template| template.render(&data))
+            .unwrap_err();
+
+        match err {
+            TemplateError::Template {
+                line,
+                column,
+                message,
+            } => (line, column, message),
+            err => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn values_are_escaped() {
+        assert_eq!(
+            render(
+                "{{ a }} {{ b.c }} {{ d.1 }} {{ missing.x }}.",
+                json!({"a": "<b>", "b": {"c": 1.5}, "d": [false, true]})
+            ),
+            "&lt;b&gt; 1.5 true ."
+        );
+    }
+
+    #[test]
+    fn filters() {
+        let data = json!({
+            "name": "Café",
+            "items": [1, 2, 3],
+            "price": 1234.5,
+            "negative": -0.004,
+            "time": "2024-03-01T18:05:00Z",
+            "timestamp": 0,
+        });
+
+        for (source, rendered) in [
+            ("{{ name | upper }}", "CAFÉ"),
+            ("{{ name | lower }}", "café"),
+            ("{{ missing | default: 'none' }}", "none"),
+            ("{{ name | default: 'none' }}", "Café"),
+            ("{{ items | length }}", "3"),
+            ("{{ name | length }}", "4"),
+            ("{{ missing | length }}", "0"),
+            ("{{ price | number }}", "1234"),
+            ("{{ '2.345' | number: 2 }}", "2.35"),
+            ("{{ price | currency: '$' }}", "$1,234.50"),
+            ("{{ price | currency: '€', 0 }}", "€1,234"),
+            ("{{ negative | currency: '$' }}", "$0.00"),
+            ("{{ -1000000 | currency }}", "-1,000,000.00"),
+            ("{{ time | date: '%d/%m/%Y %H:%M' }}", "01/03/2024 18:05"),
+            ("{{ timestamp | date }}", "1970-01-01 00:00"),
+            ("{{ '2024-03-01' | date: '%A' }}", "Friday"),
+            ("[{{ name | pad_left: 6 }}]", "[  Café]"),
+            ("[{{ name | pad_right: 6, '.' }}]", "[Café..]"),
+            ("[{{ name | center: 9, '-' }}]", "[--Café---]"),
+            ("[{{ name | pad_left: 2 }}]", "[Café]"),
+            ("{{ 'receipt' | truncate: 6 }}", "rec..."),
+            ("{{ 'receipt' | truncate: 2 }}", "re"),
+            ("{{ 'receipt' | truncate: 7 }}", "receipt"),
+            ("{{ name | lower | pad_left: 5 | upper }}", " CAFÉ"),
+        ] {
+            assert_eq!(render(source, data.clone()), rendered, "{}", source);
+        }
+    }
+
+    #[test]
+    fn wide_characters_are_padded_by_width() {
+        assert_eq!(
+            render("[{{ '日本' | pad_left: 6 }}]", json!({})),
+            "[  日本]"
+        );
+    }
+
+    #[test]
+    fn filter_errors() {
+        for (source, column, message) in [
+            (
+                "{{ 'x' | number }}",
+                10,
+                "'number' expects a number, not text",
+            ),
+            (
+                "{{ 1 | number: 'a' }}",
+                8,
+                "the amount of decimals of 'number' must be a whole number",
+            ),
+            ("{{ 1 | date: '%Q' }}", 8, "invalid date format '%Q'"),
+            ("{{ 'soon' | date }}", 13, "'soon' is not a date"),
+        ] {
+            let (line, found_column, found) = error(source, json!({}));
+
+            assert_eq!((line, found_column), (1, column), "{}", source);
+            assert_eq!(found, message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn conditions() {
+        let source =
+            "{% if a > 1 and not b %}one{% elif c == 'x' or d %}two{% else %}three{% endif %}";
+
+        assert_eq!(render(source, json!({"a": 2})), "one");
+        assert_eq!(render(source, json!({"a": 2, "b": true, "c": "x"})), "two");
+        assert_eq!(render(source, json!({"d": [0]})), "two");
+        assert_eq!(render(source, json!({"d": [], "c": "y"})), "three");
+        assert_eq!(
+            render("{% if 'b' >= 'a' %}yes{% endif %}", json!({})),
+            "yes"
+        );
+        assert_eq!(render("{% if 1 == 1.0 %}yes{% endif %}", json!({})), "yes");
+        assert_eq!(render("{% if x != null %}yes{% endif %}", json!({})), "");
+    }
+
+    #[test]
+    fn nested_loops_and_conditions() {
+        let source = "\
+{% for order in orders %}
+#{{ loop.index }}/{{ loop.length }} {{ order.name }}
+{% for item in order.items %}
+{% if loop.last %}
+  {{ item }}.
+{% elif loop.first %}
+  {{ item }},
+{% else %}
+  {{ item }};
+{% endif %}
+{% endfor %}
+{% endfor %}
+";
+        let data = json!({
+            "orders": [
+                {"name": "a", "items": ["x", "y", "z"]},
+                {"name": "b", "items": ["w", "v"]},
+            ]
+        });
+
+        assert_eq!(
+            render(source, data),
+            "#1/2 a\n  x,\n  y;\n  z.\n#2/2 b\n  w,\n  v.\n"
+        );
+    }
+
+    #[test]
+    fn loop_variables_shadow_data() {
+        assert_eq!(
+            render(
+                "{{ x }}{% for x in xs %}{{ x }}{% endfor %}{{ x }}",
+                json!({"x": "-", "xs": [1, 2]})
+            ),
+            "-12-"
+        );
+    }
+
+    #[test]
+    fn tag_lines_are_left_out() {
+        assert_eq!(
+            render(
+                "a\n  {% if true %}  \nb\n{# note #}\n{% endif %}\nc",
+                json!({})
+            ),
+            "a\nb\nc"
+        );
+        assert_eq!(render("a {% if true %}b{% endif %}\n", json!({})), "a b\n");
+    }
+
+    #[test]
+    fn parse_errors() {
+        for (source, position, message) in [
+            ("ab\n{{ x", (2, 1), "'{{' is never closed"),
+            ("{% if x %}\n", (1, 1), "{% if %} is never closed"),
+            ("{% if x %}{% else %}", (1, 1), "{% if %} is never closed"),
+            ("{% for x in xs %}", (1, 1), "{% for %} is never closed"),
+            ("a\n{% endif %}", (2, 1), "unexpected {% endif %}"),
+            ("{% while x %}", (1, 1), "unexpected {% while x %}"),
+            (
+                "{% for x of xs %}{% endfor %}",
+                (1, 8),
+                "expected {% for name in list %}",
+            ),
+            ("{{ x | shout }}", (1, 8), "unknown filter 'shout'"),
+            ("{{ x | }}", (1, 8), "expected a filter name"),
+            ("{{ x y }}", (1, 6), "unexpected token"),
+            ("{{ }}", (1, 4), "expected a value"),
+            ("{{ 'x }}", (1, 4), "unterminated string"),
+            ("{{ 1-2 }}", (1, 4), "invalid number '1-2'"),
+            ("{{ x ; }}", (1, 6), "unexpected ';'"),
+            ("é {% if x == %}{% endif %}", (1, 13), "expected a value"),
+        ] {
+            let (line, column, found) = error(source, json!({}));
+
+            assert_eq!((line, column), position, "{}", source);
+            assert_eq!(found, message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn elif_error_positions() {
+        for source in [
+            "{% if a %}{% elif == %}{% endif %}",
+            "{% if a %}{%elif == %}{% endif %}",
+            "{% if a %}{%   elif    == %}{% endif %}",
+            "{% if a %}{%\nelif\t== %}{% endif %}",
+        ] {
+            let offset = source.find("==").unwrap();
+            let expected = position(source, offset);
+            let (line, column, message) = error(source, json!({}));
+
+            assert_eq!((line, column), expected, "{:?}", source);
+            assert_eq!(message, "expected a value");
+        }
+    }
+
+    #[test]
+    fn render_errors() {
+        assert_eq!(
+            error("\n  {% for x in n %}{% endfor %}", json!({"n": 3})),
+            (2, 3, "can not loop over a number".to_owned())
+        );
+    }
+
+    #[test]
+    fn compiles_to_markup() {
+        let template = Template::parse("<b>{{ name }}</b>").unwrap();
+        let document = template
+            .compile(&json!({"name": "A"}), &Profile::default())
+            .unwrap();
+
+        assert_eq!(document.to_vec(), [0x1B, b'E', 1, b'A', 0x1B, b'E', 0]);
+        // Values are escaped, so markup characters in the data are printed
+        assert!(template
+            .compile(&json!({"name": "<&>"}), &Profile::default())
+            .is_ok());
+    }
+}
@@ -0,0 +1,335 @@
+//! Word wrapping
+//!
+//! The printer wraps text at the end of the print area wherever it happens to
+//! be, splitting words in half. This breaks lines between words instead,
+//! measuring text by its display width so wide (CJK) characters take up two
+//! columns and combining characters none.
+
+use std::ops::Range;
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::{error::TextError, Device, Printer};
+
+/// The display width of text, in columns
+pub fn width(text: &str) -> usize {
+    text.width()
+}
+
+/// What to do with a word that is wider than a line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LongWords {
+    /// Split the word over multiple lines, ending each part with a hyphen
+    Hyphenate,
+    /// Cut the word off at the end of the line
+    Truncate,
+}
+
+/// Settings for wrapping text into lines
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Wrap {
+    indent: usize,
+    long_words: LongWords,
+}
+
+impl Default for Wrap {
+    fn default() -> Self {
+        Self {
+            indent: 0,
+            long_words: LongWords::Hyphenate,
+        }
+    }
+}
+
+/// A word, with the offset of the whitespace before it
+struct Word<'a> {
+    space: Option<usize>,
+    start: usize,
+    text: &'a str,
+}
+
+/// A part of a wrapped line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Piece {
+    /// Text of the paragraph, by its byte range
+    Text(Range<usize>),
+    /// A single space in place of the whitespace at this offset of the
+    /// paragraph
+    Space(usize),
+    /// A hyphen after part of a word
+    Hyphen,
+}
+
+impl Wrap {
+    /// Wrap without a hanging indent, hyphenating words that do not fit
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Indent every line but the first of each paragraph by this many columns
+    pub fn hanging_indent(mut self, indent: usize) -> Self {
+        self.indent = indent;
+        self
+    }
+
+    /// Select what happens to words that do not fit on a line
+    pub fn long_words(mut self, long_words: LongWords) -> Self {
+        self.long_words = long_words;
+        self
+    }
+
+    /// Break text into lines of at most `width` columns
+    ///
+    /// Every newline in the text starts a new paragraph. Continuation lines
+    /// start with the hanging indent.
+    pub fn lines(&self, text: &str, width: usize) -> Vec<String> {
+        // Always leave at least one column for text
+        let indent = " ".repeat(self.indent.min(width.max(1) - 1));
+
+        let mut lines = Vec::new();
+
+        for paragraph in text.lines() {
+            for (i, pieces) in self.paragraph(paragraph, width).into_iter().enumerate() {
+                let mut line = if i == 0 {
+                    String::new()
+                } else {
+                    indent.clone()
+                };
+
+                for piece in pieces {
+                    match piece {
+                        Piece::Text(range) => line.push_str(&paragraph[range]),
+                        Piece::Space(_) => line.push(' '),
+                        Piece::Hyphen => line.push('-'),
+                    }
+                }
+
+                lines.push(line);
+            }
+        }
+
+        lines
+    }
+
+    /// Break a paragraph into lines of at most `width` columns, leaving room
+    /// for the hanging indent on continuation lines
+    ///
+    /// Any whitespace in the paragraph, including newlines, separates words.
+    pub(crate) fn paragraph(&self, paragraph: &str, width: usize) -> Vec<Vec<Piece>> {
+        let width = width.max(1);
+        let indent = self.indent.min(width - 1);
+        let line_width = |line: usize| if line == 0 { width } else { width - indent };
+
+        let mut lines = Vec::new();
+        let mut line = Vec::new();
+        let mut used = 0;
+
+        for word in words(paragraph) {
+            let available = line_width(lines.len());
+            let space = word.space.filter(|_| used > 0);
+            let space_width = space.is_some() as usize;
+            let word_width = self::width(word.text);
+            let end = word.start + word.text.len();
+
+            if used + space_width + word_width <= available {
+                line.extend(space.map(Piece::Space));
+                line.push(Piece::Text(word.start..end));
+                used += space_width + word_width;
+                continue;
+            }
+
+            if used > 0 {
+                lines.push(std::mem::take(&mut line));
+                used = 0;
+            }
+
+            let available = line_width(lines.len());
+
+            if word_width <= available {
+                line.push(Piece::Text(word.start..end));
+                used = word_width;
+                continue;
+            }
+
+            match self.long_words {
+                LongWords::Truncate => {
+                    let part = take(word.text, available).0;
+
+                    lines.push(vec![Piece::Text(word.start..word.start + part.len())]);
+                }
+                LongWords::Hyphenate => {
+                    let mut start = word.start;
+
+                    loop {
+                        let rest = &paragraph[start..end];
+                        let available = line_width(lines.len());
+
+                        if self::width(rest) <= available {
+                            line.push(Piece::Text(start..end));
+                            used = self::width(rest);
+                            break;
+                        }
+
+                        // A hyphen needs room for at least one character before it
+                        let part = if available > 1 {
+                            take(rest, available - 1).0
+                        } else {
+                            take(rest, available).0
+                        };
+
+                        let mut pieces = vec![Piece::Text(start..start + part.len())];
+                        if available > 1 {
+                            pieces.push(Piece::Hyphen);
+                        }
+                        lines.push(pieces);
+                        start += part.len();
+                    }
+                }
+            }
+        }
+
+        if used > 0 || lines.is_empty() {
+            lines.push(line);
+        }
+
+        lines
+    }
+}
+
+/// Split a paragraph into words
+///
+/// Words are separated by whitespace, and wide characters are words of their
+/// own since CJK text may be broken between any two characters.
+fn words(paragraph: &str) -> Vec<Word<'_>> {
+    let mut words: Vec<Word> = Vec::new();
+    let mut start = None;
+    let mut space = None;
+    let mut wide = false;
+
+    for (index, segment) in paragraph.split_word_bound_indices() {
+        let is_wide = width(segment) > segment.graphemes(true).count();
+
+        if segment.trim().is_empty() || is_wide || wide {
+            if let Some(start) = start.take() {
+                words.push(Word {
+                    space: space.take(),
+                    start,
+                    text: &paragraph[start..index],
+                });
+            }
+        }
+
+        wide = is_wide;
+
+        if segment.trim().is_empty() {
+            space.get_or_insert(index);
+        } else if start.is_none() {
+            start = Some(index);
+        }
+    }
+
+    if let Some(start) = start {
+        words.push(Word {
+            space,
+            start,
+            text: &paragraph[start..],
+        });
+    }
+
+    words
+}
+
+/// Split off the graphemes that fit in `columns`, always taking at least one
+pub(crate) fn take(text: &str, columns: usize) -> (&str, &str) {
+    let mut used = 0;
+
+    for (index, grapheme) in text.grapheme_indices(true) {
+        used += width(grapheme);
+
+        if used > columns && index > 0 {
+            return text.split_at(index);
+        }
+    }
+
+    (text, "")
+}
+
+impl<D: Device> Printer<D> {
+    /// Print text wrapped between words to fit the print area
+    ///
+    /// The line width follows the current font, character size, left margin
+    /// and print area width.
+    pub fn println_wrapped<S: AsRef<str>>(
+        &self,
+        text: S,
+        wrap: &Wrap,
+    ) -> Result<(), TextError<String>> {
+        for line in wrap.lines(text.as_ref(), self.columns()) {
+            self.println(line)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn between_words() {
+        assert_eq!(
+            Wrap::new().lines("The quick  brown fox", 10),
+            ["The quick", "brown fox"]
+        );
+    }
+
+    #[test]
+    fn hanging_indent() {
+        assert_eq!(
+            Wrap::new().hanging_indent(2).lines("one two three four", 9),
+            ["one two", "  three", "  four"]
+        );
+        // Always leaves a column for text
+        assert_eq!(
+            Wrap::new().hanging_indent(5).lines("ab cd", 2),
+            ["ab", " c", " d"]
+        );
+    }
+
+    #[test]
+    fn hyphenate() {
+        assert_eq!(
+            Wrap::new().lines("abcdefghijk", 4),
+            ["abc-", "def-", "ghi-", "jk"]
+        );
+        assert_eq!(Wrap::new().lines("go abcdef", 4), ["go", "abc-", "def"]);
+    }
+
+    #[test]
+    fn truncate() {
+        let wrap = Wrap::new().long_words(LongWords::Truncate);
+
+        assert_eq!(wrap.lines("abcdefghij xy", 4), ["abcd", "xy"]);
+    }
+
+    #[test]
+    fn cjk_width() {
+        assert_eq!(width("日本語"), 6);
+        assert_eq!(width("e\u{301}"), 1);
+
+        assert_eq!(
+            Wrap::new().lines("日本語日本語", 5),
+            ["日本", "語日", "本語"]
+        );
+        assert_eq!(Wrap::new().lines("ok 日本", 5), ["ok 日", "本"]);
+    }
+
+    #[test]
+    fn empty_paragraphs() {
+        assert_eq!(Wrap::new().lines("one\n\ntwo", 10), ["one", "", "two"]);
+        assert_eq!(Wrap::new().lines("   ", 10), [""]);
+        assert!(Wrap::new().lines("", 10).is_empty());
+    }
+}
@@ -4,17 +4,63 @@ use serenity::{
     model::{
         channel::ReactionType,
         interactions::{
-            application_command::{ApplicationCommand, ApplicationCommandOptionType},
+            application_command::{
+                ApplicationCommand, ApplicationCommandInteraction, ApplicationCommandOptionType,
+            },
             message_component::ButtonStyle,
             Interaction, InteractionApplicationCommandCallbackDataFlags,
         },
-        prelude::{Activity, OnlineStatus, Ready},
+        prelude::{Activity, OnlineStatus, Permissions, Ready},
     },
     utils::Color,
 };
+use thermal::{
+    asynchronous::AsyncPrinter,
+    drawer::DrawerPin,
+    error::QueueError,
+    hotplug::ReconnectingPrinter,
+    queue::{JobQueue, Priority, Worker},
+    Printer,
+};
 use tracing::{error, info, warn};
 
-pub struct Handler;
+/// Permissions a member needs to open the cash drawer
+const DRAWER_PERMISSIONS: Permissions = Permissions::MANAGE_GUILD;
+
+pub struct Handler {
+    /// Replies from the printer, taking turns with the queue's worker
+    printer: AsyncPrinter<ReconnectingPrinter>,
+    /// Everything printed goes through the queue
+    queue: JobQueue,
+    _worker: Worker<AsyncPrinter<ReconnectingPrinter>>,
+    /// Whether the printer keeps maintenance counters for `/printer-stats`
+    maintenance_counters: bool,
+}
+
+impl Handler {
+    pub fn new(printer: ReconnectingPrinter, maintenance_counters: bool) -> Self {
+        let printer = AsyncPrinter::new(printer);
+        let queue = JobQueue::new();
+
+        Self {
+            _worker: queue.spawn_worker(printer.clone()),
+            printer,
+            queue,
+            maintenance_counters,
+        }
+    }
+
+    /// Queue a drawer pulse ahead of other jobs, and wait for it to be sent
+    async fn open_drawer(&self, pin: DrawerPin) -> Result<(), QueueError> {
+        let pulse = Printer::document();
+        pulse.open_drawer(pin)?;
+
+        let job = self.queue.submit(pulse.into_device(), Priority::High)?;
+        tokio::task::spawn_blocking(move || job.wait())
+            .await
+            .unwrap_or(Err(QueueError::Stopped))
+    }
+}
 
 #[async_trait]
 impl EventHandler for Handler {
@@ -90,6 +136,141 @@ impl EventHandler for Handler {
                         })
                         .await
                 }
+                "open-drawer" if !may_open_drawer(&command) => {
+                    command
+                        .create_interaction_response(&ctx, |response| {
+                            response.interaction_response_data(|data| {
+                                data.create_embed(|embed| {
+                                    embed
+                                        .color(Color::DARK_RED)
+                                        .title("Not allowed")
+                                        .description(
+                                            "Opening the drawer needs the Manage Server permission",
+                                        )
+                                })
+                                .flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL)
+                            })
+                        })
+                        .await
+                }
+                "open-drawer" => {
+                    let pin = match command
+                        .data
+                        .options
+                        .iter()
+                        .find(|option| option.name == "pin")
+                        .and_then(|option| option.value.as_ref()?.as_str())
+                    {
+                        Some("5") => DrawerPin::Pin5,
+                        _ => DrawerPin::Pin2,
+                    };
+
+                    let result = self.open_drawer(pin).await;
+
+                    if let Err(err) = &result {
+                        error!(%err, "Failed to open the drawer");
+                    }
+
+                    command
+                        .create_interaction_response(&ctx, |response| {
+                            response.interaction_response_data(|data| match result {
+                                Ok(()) => data.create_embed(|embed| {
+                                    embed.color(Color::DARK_GREEN).title("Drawer opened")
+                                }),
+                                Err(err) => data
+                                    .create_embed(|embed| {
+                                        embed
+                                            .color(Color::DARK_RED)
+                                            .title("Failed to open the drawer")
+                                            .description(err)
+                                    })
+                                    .flags(
+                                        InteractionApplicationCommandCallbackDataFlags::EPHEMERAL,
+                                    ),
+                            })
+                        })
+                        .await
+                }
+                "printer-stats" if !self.maintenance_counters => {
+                    command
+                        .create_interaction_response(&ctx, |response| {
+                            response.interaction_response_data(|data| {
+                                data.create_embed(|embed| {
+                                    embed
+                                        .color(Color::DARK_RED)
+                                        .title("Not supported")
+                                        .description(
+                                            "This printer does not keep maintenance counters",
+                                        )
+                                })
+                                .flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL)
+                            })
+                        })
+                        .await
+                }
+                "printer-stats" => {
+                    let result = self
+                        .printer
+                        .run(|printer| {
+                            printer.run(|printer| {
+                                printer.maintenance_counters().and_then(|counters| {
+                                    Ok((counters, printer.cumulative_maintenance_counters()?))
+                                })
+                            })
+                        })
+                        .await;
+
+                    if let Err(err) = &result {
+                        error!(%err, "Failed to read the maintenance counters");
+                    }
+
+                    command
+                        .create_interaction_response(&ctx, |response| {
+                            response.interaction_response_data(|data| match result {
+                                Ok((counters, cumulative)) => data.create_embed(|embed| {
+                                    let stat =
+                                        |current, total| format!("{} ({} total)", current, total);
+
+                                    embed
+                                        .color(Color::DARK_GREEN)
+                                        .title("Printer stats")
+                                        .field(
+                                            "Lines fed",
+                                            stat(counters.line_feeds, cumulative.line_feeds),
+                                            true,
+                                        )
+                                        .field(
+                                            "Head energized",
+                                            stat(
+                                                counters.head_energizing,
+                                                cumulative.head_energizing,
+                                            ),
+                                            true,
+                                        )
+                                        .field("Cuts", stat(counters.cuts, cumulative.cuts), true)
+                                        .field(
+                                            "Operating hours",
+                                            stat(
+                                                counters.operating_hours,
+                                                cumulative.operating_hours,
+                                            ),
+                                            true,
+                                        )
+                                }),
+                                Err(err) => data
+                                    .create_embed(|embed| {
+                                        embed
+                                            .color(Color::DARK_RED)
+                                            .title("Failed to read the printer stats")
+                                            .description(err)
+                                    })
+                                    .flags(
+                                        InteractionApplicationCommandCallbackDataFlags::EPHEMERAL,
+                                    ),
+                            })
+                        })
+                        .await
+                }
                 _ => {
                     command
                         .create_interaction_response(&ctx, |response| {
@@ -149,11 +330,44 @@ impl EventHandler for Handler {
                                 .add_string_choice("slip back", "slip_back")
                         })
                 })
+                .create_application_command(|command| {
+                    command
+                        .name("open-drawer")
+                        .description("Open the cash drawer connected to the printer");
+                    // Hidden from members without the permission, and from
+                    // DMs where there are no permissions to check. This
+                    // version of serenity has no builder methods for them.
+                    command.0.insert(
+                        "default_member_permissions",
+                        DRAWER_PERMISSIONS.bits().to_string().into(),
+                    );
+                    command.0.insert("dm_permission", false.into());
+                    command.create_option(|option| {
+                        option
+                            .name("pin")
+                            .description("The connector pin the drawer is wired to")
+                            .kind(ApplicationCommandOptionType::String)
+                            .required(false)
+                            .add_string_choice("pin 2", "2")
+                            .add_string_choice("pin 5", "5")
+                    })
+                })
                 .create_application_command(|command| {
                     command
                         .name("finish-print")
                         .description("Finish and send print to the thermal printer")
-                })
+                });
+
+            // Only offered when the printer can answer it
+            if self.maintenance_counters {
+                commands.create_application_command(|command| {
+                    command
+                        .name("printer-stats")
+                        .description("Show the maintenance counters of the printer")
+                });
+            }
+
+            commands
         })
         .await;
 
@@ -172,3 +386,14 @@ impl EventHandler for Handler {
         }
     }
 }
+
+/// Whether the member using a command may open the cash drawer
+///
+/// Checked here as well, since server admins can change who sees the command.
+fn may_open_drawer(command: &ApplicationCommandInteraction) -> bool {
+    command
+        .member
+        .as_ref()
+        .and_then(|member| member.permissions)
+        .is_some_and(|permissions| permissions.contains(DRAWER_PERMISSIONS))
+}
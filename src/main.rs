@@ -1,10 +1,10 @@
 use std::env;
 
-use color_eyre::eyre::{ContextCompat, WrapErr};
+use color_eyre::eyre::WrapErr;
 use serenity::Client;
-use thermal::{rusb::Context, Printer};
+use thermal::{hotplug::ReconnectingPrinter, rusb::Context, Printer};
 use tokio::runtime::Builder;
-use tracing::{debug, info, instrument, warn};
+use tracing::{info, instrument, warn};
 use tracing_subscriber::EnvFilter;
 
 use crate::handler::Handler;
@@ -22,24 +22,26 @@ async fn main() -> color_eyre::Result<()> {
 
     let context = Context::new().wrap_err("Failed to get libusb context")?;
 
-    let printer = Printer::builder(PRINTER_VID, PRINTER_PID)
-        .connect(&context)
+    // Initializes the printer, and again whenever it comes back after
+    // being unplugged
+    let mut printer =
+        ReconnectingPrinter::connect(&context, Printer::builder(PRINTER_VID, PRINTER_PID))
+            .wrap_err("Failed to connect to the printer")?;
+    let maintenance_counters = printer
+        .printer()
         .wrap_err("Failed to connect to the printer")?
-        .wrap_err("No printer found")?;
+        .profile()
+        .maintenance_counters;
 
     info!("Connected to printer!");
 
-    printer.init()?;
-
-    debug!("Printer Initializing...");
-
     warn!("Assuming printer connected, no way of knowing...");
     info!("Starting up discord bot...");
 
     let token = env::var("DISCORD_TOKEN").wrap_err("DISCORD_TOKEN must be set")?;
 
     let mut client: Client = Client::builder(token)
-        .event_handler(Handler)
+        .event_handler(Handler::new(printer, maintenance_counters))
         .application_id(APPLICATION_ID)
         .await
         .expect("Error creating client");
@@ -69,7 +71,7 @@ async fn main() -> color_eyre::Result<()> {
 
     // printer.carriage_reset(false)?;
 
-    // printer.cut()?;
+    // printer.cut(printer.profile().default_cut())?;
 
     // printer.raw([0x1B, 0x53])?; // Select Standard mode: ESC S
     // printer.paper_type(PaperType::Slip)?;